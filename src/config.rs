@@ -4,14 +4,19 @@ use serde::Deserialize;
 use std::fs;
 use std::path::PathBuf;
 use toml::Value;
+use wind_kvstore::Durability;
 
 const DEFAULT_HOST: &str = "127.0.0.1";
 const DEFAULT_PORT: u16 = 14514;
+const DEFAULT_GROUP_COMMIT_INTERVAL_MS: u64 = 10;
+const DEFAULT_GROUP_COMMIT_MAX_BATCH: usize = 128;
 
 #[derive(Deserialize, Debug)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    #[serde(skip)]
+    pub durability: Durability,
 }
 
 impl Default for ServerConfig {
@@ -19,6 +24,7 @@ impl Default for ServerConfig {
         ServerConfig {
             host: DEFAULT_HOST.to_string(),
             port: DEFAULT_PORT,
+            durability: Durability::default(),
         }
     }
 }
@@ -34,8 +40,8 @@ pub fn load_config() -> Result<ServerConfig> {
     let config_content = fs::read_to_string(&config_path)?;
     let config_value: Value = toml::from_str(&config_content)?;
 
-    let server_config = config_value.get("server").and_then(|s| {
-        Some(ServerConfig {
+    let mut server_config = config_value.get("server").map(|s| {
+        ServerConfig {
             host: s.get("host")
                 .and_then(|h| h.as_str())
                 .unwrap_or(DEFAULT_HOST)
@@ -44,12 +50,58 @@ pub fn load_config() -> Result<ServerConfig> {
                 .and_then(|p| p.as_integer())
                 .map(|p| p as u16)
                 .unwrap_or(DEFAULT_PORT),
-        })
+            durability: Durability::default(),
+        }
     }).unwrap_or_default();
 
+    if let Some(storage) = config_value.get("storage") {
+        server_config.durability = parse_durability(storage)?;
+    }
+
     Ok(server_config)
 }
 
+// [storage] 段的 durability 取值：full、group_commit、os_buffered、off
+fn parse_durability(storage: &Value) -> Result<Durability> {
+    let mode = storage.get("durability")
+        .and_then(|d| d.as_str())
+        .unwrap_or("full");
+
+    match mode.to_ascii_lowercase().as_str() {
+        "full" => Ok(Durability::Full),
+        "group_commit" => Ok(Durability::GroupCommit {
+            interval_ms: positive_setting(storage, "group_commit_interval_ms")?
+                .unwrap_or(DEFAULT_GROUP_COMMIT_INTERVAL_MS),
+            max_batch: positive_setting(storage, "group_commit_max_batch")?
+                .map(usize::try_from)
+                .transpose()?
+                .unwrap_or(DEFAULT_GROUP_COMMIT_MAX_BATCH),
+        }),
+        "os_buffered" => Ok(Durability::OsBuffered),
+        "off" => Ok(Durability::Off),
+        other => Err(anyhow::anyhow!(
+            "Unknown durability mode `{}`, expected full, group_commit, os_buffered or off",
+            other
+        )),
+    }
+}
+
+// [storage] 段中必须为正整数的设置，未设置时返回 None
+fn positive_setting(storage: &Value, name: &str) -> Result<Option<u64>> {
+    let value = match storage.get(name).and_then(|v| v.as_integer()) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    match u64::try_from(value) {
+        Ok(value) if value > 0 => Ok(Some(value)),
+        _ => Err(anyhow::anyhow!(
+            "Invalid `{}` = {} in [storage], expected a positive integer",
+            name,
+            value
+        )),
+    }
+}
+
 fn get_config_path() -> Result<PathBuf> {
     let mut path = home_dir().ok_or(anyhow::anyhow!("Home directory not found"))?;
     path.push(".stv_project");
@@ -63,7 +115,37 @@ fn create_default_config(path: &PathBuf) -> Result<()> {
 [server]
 host = "127.0.0.1"
 port = 14514
+
+[storage]
+# full | group_commit | os_buffered | off
+durability = "full"
+group_commit_interval_ms = 10
+group_commit_max_batch = 128
 "#;
     fs::write(path, default_config)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage(text: &str) -> Value {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn group_commit_settings_must_be_positive() {
+        let parsed = parse_durability(&storage(
+            "durability = \"group_commit\"\ngroup_commit_interval_ms = 5\ngroup_commit_max_batch = 64",
+        ));
+        assert_eq!(parsed.unwrap(), Durability::GroupCommit { interval_ms: 5, max_batch: 64 });
+
+        for setting in ["group_commit_interval_ms", "group_commit_max_batch"] {
+            for value in [0, -1] {
+                let text = format!("durability = \"group_commit\"\n{} = {}", setting, value);
+                assert!(parse_durability(&storage(&text)).is_err(), "{}", text);
+            }
+        }
+    }
+}
@@ -1,24 +1,78 @@
-// src/lib.rs
+// src/kvstore.rs
+use crate::options::{Durability, KVStoreOptions, DEFAULT_IDENTIFIER, DEFAULT_PAGE_SIZE};
 use anyhow::{anyhow, bail, Result};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use linked_hash_map::LinkedHashMap;
-use log::error;
-use memmap2::{MmapMut, MmapOptions};
-use std::collections::HashSet;
-use std::fs::{File, OpenOptions};
+use log::{error, warn};
+use memmap2::{Mmap, MmapMut, MmapOptions};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{File, OpenOptions, TryLockError};
 use std::io::{Cursor, Read, Write};
 use std::mem;
+use std::ops::{Bound, Deref, DerefMut, Range, RangeBounds};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
+mod blob;
+mod btree;
+mod compression;
+mod conditional;
+mod counter;
+mod cursor;
+mod keyspace;
+mod salvage;
+mod secondary;
+mod shared;
+mod snapshot;
+mod transaction;
+mod ttl;
+mod verify;
+
+use btree::Node;
+use compression::decompress;
+use keyspace::{KeyspaceEntry, DEFAULT_KEYSPACE_ID};
+use secondary::IndexDef;
+use snapshot::PinnedPages;
+pub use blob::BlobReader;
+pub use compression::{Codec, Compression};
+pub use cursor::KVCursor;
+pub use keyspace::{Keyspace, KeyspaceStats, DEFAULT_KEYSPACE};
+pub use salvage::SalvageReport;
+pub use secondary::IndexInfo;
+pub use shared::SharedKVStore;
+pub use snapshot::Snapshot;
+pub use transaction::Transaction;
+pub use verify::{PageKind, VerifyIssue, VerifyReport};
+
 // 常量定义
 const MAGIC_NUMBER: u32 = 0x565453; // 'STV' in ASCII
-const PAGE_SIZE: usize = 1024; // 1KB 页大小
+const FORMAT_VERSION: u16 = 10; // 文件格式版本，0 为无索引的旧格式
+const CHECKSUM_VERSION: u16 = 2; // 从此版本起页与文件头带 CRC32C 校验和
+const PAGE_SIZE_VERSION: u16 = 3; // 从此版本起页大小由文件头决定，之前固定为 1KB
+const ENTRY_FORMAT_VERSION: u16 = 4; // 从此版本起键长为变长编码，长键可溢出
+const VALUE_OVERFLOW_VERSION: u16 = 5; // 从此版本起每个条目自带值的溢出链
+const TTL_VERSION: u16 = 6; // 从此版本起值字段可以带过期时间
+const KEY_VERSION_VERSION: u16 = 7; // 从此版本起每个条目带版本号
+const KEYSPACE_VERSION: u16 = 8; // 从此版本起支持命名键空间，数据页记录所属键空间
+const COMPRESSION_VERSION: u16 = 9; // 从此版本起值可以压缩保存，键空间目录带压缩设置
+const INDEX_NUMBER_VERSION: u16 = 10; // 从此版本起二级索引中的整数按精确值编码
+const MIN_PAGE_SIZE: usize = 1024; // 最小页大小
+const MAX_PAGE_SIZE: usize = 64 * 1024; // 最大页大小
 const HEADER_SIZE: usize = 128; // 文件头大小
-const MAX_CACHE_SIZE: usize = 100 * 1024; // 100KB 缓存
-const OVERFLOW_THRESHOLD: usize = 900; // 溢出阈值
 const WAL_FILE_EXT: &str = "wal"; // WAL文件后缀
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(10); // 等待文件锁时的轮询间隔
+const WAL_MAGIC: u32 = 0x4C41_5757; // 'WWAL'，没有该文件头的是旧格式的 WAL
+const WAL_VERSION: u16 = 1;
+const WAL_HEADER_SIZE: usize = 8; // 魔数 u32 | 版本 u16 | 保留 u16
+const WAL_FRAME_HEADER_SIZE: usize = 16; // CRC32C u32 | 记录长 u32 | LSN u64
+
+// 页头标志位
+const PAGE_FLAG_OVERFLOW: u8 = 0x02; // 溢出页（版本 5 之前也标记带页级溢出链的数据页）
+const PAGE_FLAG_FREE: u8 = 0x04; // 空闲页
+const PAGE_FLAG_INDEX_LEAF: u8 = 0x08; // 索引叶子节点
+const PAGE_FLAG_INDEX_BRANCH: u8 = 0x10; // 索引内部节点
 
 
 #[derive(Debug, Error)]
@@ -33,19 +87,106 @@ pub enum KvError {
     Io(#[from] std::io::Error),
     #[error("UTF-8 error: {0}")]
     Utf8(#[from] std::str::Utf8Error),
+    #[error("Unsupported file format version: {0}")]
+    UnsupportedVersion(u16),
+    #[error("Key of {len} bytes exceeds the maximum key size of {max} bytes")]
+    KeyTooLarge { len: usize, max: usize },
+    #[error("Invalid page size {0}, expected a power of two between 1KB and 64KB")]
+    InvalidPageSize(usize),
+    /// 校验和不匹配，`page` 为 0 时表示文件头；页第一次从磁盘读入时校验
+    #[error("Checksum mismatch on page {page}, data may be corrupted")]
+    Corruption { page: u32 },
+    /// 数据库文件已被另一个 [`KVStore`] 打开（可能在其他进程中）
+    #[error("Database {} is locked by another process", .0.display())]
+    Locked(PathBuf),
+    /// 对只读打开的数据库执行了写操作
+    #[error("Database is opened read-only")]
+    ReadOnly,
+    /// 对值不是数字的键执行了计数器操作
+    #[error("Value of key {0} is not a number")]
+    NotNumeric(String),
+    /// 计数器运算的结果超出了范围
+    #[error("Counter overflow on key {0}")]
+    CounterOverflow(String),
+    /// 浮点计数器的增量是 `NaN` 或无穷大
+    #[error("Counter delta must be finite, got {0}")]
+    NonFiniteDelta(f64),
     #[error("Other error: {0}")]
     #[allow(dead_code)]
     Other(String),
 }
 
 
-// 分页头结构
+// 分页头结构，保留区的前 4 字节存放整页的 CRC32C（见 seal_page），
+// 之后 2 字节为数据页所属键空间的编号
 #[derive(Debug, Clone, Copy)]
 struct PageHeader {
     flags: u8,      // 状态标志
     kv_count: u16,  // 键值对数量
     data_len: u16,  // 数据长度
     next_page: u32, // 下一页号
+    keyspace: u16,  // 数据页和索引页所属的键空间
+}
+
+
+const PAGE_CHECKSUM_OFFSET: usize = 9;
+
+
+// 计算整页的校验和，校验和字段本身按 0 参与计算
+fn page_checksum(page: &[u8]) -> u32 {
+    let end = PAGE_CHECKSUM_OFFSET + 4;
+    let crc = crc32c::crc32c(&page[..PAGE_CHECKSUM_OFFSET]);
+    let crc = crc32c::crc32c_append(crc, &[0u8; 4]);
+    crc32c::crc32c_append(crc, &page[end..])
+}
+
+
+fn seal_page(page: &mut [u8]) {
+    let checksum = page_checksum(page);
+    page[PAGE_CHECKSUM_OFFSET..PAGE_CHECKSUM_OFFSET + 4].copy_from_slice(&checksum.to_le_bytes());
+}
+
+
+fn verify_page(page: &[u8]) -> bool {
+    let stored = &page[PAGE_CHECKSUM_OFFSET..PAGE_CHECKSUM_OFFSET + 4];
+    u32::from_le_bytes([stored[0], stored[1], stored[2], stored[3]]) == page_checksum(page)
+}
+
+
+fn check_page_size(page_size: usize) -> Result<()> {
+    if !page_size.is_power_of_two() || !(MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size) {
+        bail!(KvError::InvalidPageSize(page_size));
+    }
+    Ok(())
+}
+
+
+// 对数据库文件加建议锁（flock），读写打开用排他锁，只读打开用共享锁，进程退出或文件关闭时自动释放。
+// 锁被占用时按 timeout 轮询等待，None 表示立即返回 KvError::Locked
+fn lock_file(file: &File, path: &Path, shared: bool, timeout: Option<Duration>) -> Result<()> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        let locked = if shared {
+            file.try_lock_shared()
+        } else {
+            file.try_lock()
+        };
+        match locked {
+            Ok(()) => return Ok(()),
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        match deadline {
+            Some(deadline) if Instant::now() < deadline => std::thread::sleep(LOCK_RETRY_INTERVAL),
+            _ => bail!(KvError::Locked(path.to_path_buf())),
+        }
+    }
+}
+
+
+// 超过此长度的值溢出到溢出链，按页大小等比例缩放（1KB 页为 900 字节）
+fn overflow_threshold(page_size: usize) -> usize {
+    page_size * 900 / 1024
 }
 
 
@@ -59,7 +200,9 @@ impl PageHeader {
         cursor.write_u16::<LittleEndian>(self.kv_count).unwrap();
         cursor.write_u16::<LittleEndian>(self.data_len).unwrap();
         cursor.write_u32::<LittleEndian>(self.next_page).unwrap();
-        cursor.write_all(&[0u8; 7]).unwrap(); // 保留区
+        cursor.write_all(&[0u8; 4]).unwrap(); // 校验和
+        cursor.write_u16::<LittleEndian>(self.keyspace).unwrap();
+        cursor.write_all(&[0u8; 1]).unwrap(); // 保留区
         buf
     }
 
@@ -73,34 +216,357 @@ impl PageHeader {
         let kv_count = cursor.read_u16::<LittleEndian>()?;
         let data_len = cursor.read_u16::<LittleEndian>()?;
         let next_page = cursor.read_u32::<LittleEndian>()?;
-        cursor.read_exact(&mut [0u8; 7])?; // 跳过保留区
+        cursor.read_exact(&mut [0u8; 4])?; // 跳过校验和
+        let keyspace = cursor.read_u16::<LittleEndian>()?; // 旧文件此处为保留区，读出默认键空间
+        cursor.read_exact(&mut [0u8; 1])?; // 跳过保留区
 
         Ok(Self {
             flags,
             kv_count,
             data_len,
             next_page,
+            keyspace,
         })
     }
+
+    // 是否为存放键值对的数据页（溢出页的 kv_count 恒为 0）
+    fn is_data_page(&self) -> bool {
+        self.flags & (PAGE_FLAG_FREE | PAGE_FLAG_INDEX_LEAF | PAGE_FLAG_INDEX_BRANCH) == 0
+            && !(self.flags & PAGE_FLAG_OVERFLOW != 0 && self.kv_count == 0)
+    }
+}
+
+
+// 数据页中一个键值条目的位置（相对数据区）
+struct EntrySpan {
+    start: usize,
+    key_len: usize,       // 完整键长
+    key: Range<usize>,    // 页内保存的键（溢出时只是前缀）
+    key_overflow: u32,    // 键剩余部分所在的溢出链，0 表示键完整保存在页内
+    value_field: usize,   // 值长度字段的起始位置
+    value_len: usize,     // 完整值长，压缩时为压缩后的长度
+    value: Range<usize>,  // 页内保存的值，值溢出时为空
+    value_overflow: u32,  // 保存整个值的溢出链，0 表示值保存在页内
+    expires_at: Option<u64>, // 过期时间（毫秒时间戳）
+    version: u64,         // 最后写入该键的 WAL 记录的 LSN，升级前写入的条目为 0
+    compressed: bool,     // 值是否压缩保存
+}
+
+
+impl EntrySpan {
+    fn end(&self) -> usize {
+        self.value.end
+    }
+
+    fn meta(&self) -> EntryMeta {
+        EntryMeta {
+            expires_at: self.expires_at,
+            version: self.version,
+            compressed: self.compressed,
+        }
+    }
+}
+
+
+// 和值一起保存在值字段中的元数据
+#[derive(Clone, Copy, Default)]
+struct EntryMeta {
+    expires_at: Option<u64>,
+    version: u64,
+    compressed: bool,
+}
+
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+
+fn varint_len(mut value: u64) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+
+// 超过此长度的键只在页内保存前缀，其余部分放入溢出链（1KB 页为 64 字节）
+// 取页大小的 1/16，保证长键与截断到溢出阈值的值仍能放进同一页
+fn key_inline_limit(page_size: usize) -> usize {
+    page_size / 16
+}
+
+
+// 编码键字段：varint(键长 << 1 | 是否溢出) | 页内部分 | [溢出链起始页 u32]
+// 数据页条目与索引节点共用这一格式
+fn encode_key(buf: &mut Vec<u8>, key: &[u8], overflow: u32, page_size: usize) {
+    let inline = key.len().min(key_inline_limit(page_size));
+    let spilled = inline < key.len();
+    write_varint(buf, (key.len() as u64) << 1 | spilled as u64);
+    buf.extend_from_slice(&key[..inline]);
+    if spilled {
+        buf.extend_from_slice(&overflow.to_le_bytes());
+    }
+}
+
+
+// 编码后的键字段长度
+fn encoded_key_len(key: &[u8], page_size: usize) -> usize {
+    let inline = key.len().min(key_inline_limit(page_size));
+    let overflow = if inline < key.len() { 4 } else { 0 };
+    varint_len((key.len() as u64) << 1 | 1) + inline + overflow
+}
+
+
+// 解析键字段，返回 (完整键长, 页内部分, 溢出链起始页)
+fn decode_key(data: &[u8], pos: &mut usize, page_size: usize) -> Option<(usize, Range<usize>, u32)> {
+    let field = read_varint(data, pos)?;
+    let key_len = usize::try_from(field >> 1).ok()?;
+    let spilled = field & 1 != 0;
+
+    let inline = if spilled {
+        key_inline_limit(page_size)
+    } else {
+        key_len
+    };
+    if inline > key_len || *pos + inline > data.len() {
+        return None;
+    }
+    let key = *pos..*pos + inline;
+    *pos += inline;
+
+    let overflow = if spilled {
+        let bytes = data.get(*pos..*pos + 4)?;
+        *pos += 4;
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    } else {
+        0
+    };
+    Some((key_len, key, overflow))
+}
+
+
+// 条目中保存的值：小值直接放在页内，大值整体放在溢出链中，条目只保留引用，
+// 因此多个大值可以共享一页
+enum StoredValue<'a> {
+    Inline(&'a [u8]),
+    Overflow { len: usize, start: u32 },
+}
+
+
+impl StoredValue<'_> {
+    // 值字段：varint(值长 << 3 | 是否压缩 << 2 | 是否带过期时间 << 1 | 是否溢出) | varint(版本号) |
+    // [过期时间 u64] | 值 或 [溢出链起始页 u32]
+    fn encode(&self, buf: &mut Vec<u8>, meta: EntryMeta) {
+        write_varint(buf, self.field_tag(meta));
+        write_varint(buf, meta.version);
+        if let Some(expires_at) = meta.expires_at {
+            buf.extend_from_slice(&expires_at.to_le_bytes());
+        }
+        match self {
+            StoredValue::Inline(value) => buf.extend_from_slice(value),
+            StoredValue::Overflow { start, .. } => buf.extend_from_slice(&start.to_le_bytes()),
+        }
+    }
+
+    fn encoded_len(&self, meta: EntryMeta) -> usize {
+        let stored = match self {
+            StoredValue::Inline(value) => value.len(),
+            StoredValue::Overflow { .. } => 4,
+        };
+        let expiry = if meta.expires_at.is_some() { 8 } else { 0 };
+        varint_len(self.field_tag(meta)) + varint_len(meta.version) + expiry + stored
+    }
+
+    fn field_tag(&self, meta: EntryMeta) -> u64 {
+        let (len, spilled) = match self {
+            StoredValue::Inline(value) => (value.len(), false),
+            StoredValue::Overflow { len, .. } => (*len, true),
+        };
+        (len as u64) << 3 | (meta.compressed as u64) << 2 | (meta.expires_at.is_some() as u64) << 1 | spilled as u64
+    }
+}
+
+
+// 数据页条目：键字段 | 值字段
+fn encode_entry(
+    buf: &mut Vec<u8>,
+    key: &[u8],
+    key_overflow: u32,
+    value: &StoredValue,
+    meta: EntryMeta,
+    page_size: usize,
+) {
+    encode_key(buf, key, key_overflow, page_size);
+    value.encode(buf, meta);
+}
+
+
+// 按常规方式写入时条目的大小：超过溢出阈值的值放入溢出链
+fn entry_size(key: &[u8], value: &[u8], meta: EntryMeta, page_size: usize) -> usize {
+    let stored = if value.len() > overflow_threshold(page_size) {
+        StoredValue::Overflow {
+            len: value.len(),
+            start: 0,
+        }
+    } else {
+        StoredValue::Inline(value)
+    };
+    encoded_key_len(key, page_size) + stored.encoded_len(meta)
+}
+
+
+// 解析数据页中的条目，遇到不完整的条目时停止
+fn parse_entries(data: &[u8], kv_count: u16, page_size: usize) -> Vec<EntrySpan> {
+    parse_entries_as(data, kv_count, page_size, FORMAT_VERSION)
+}
+
+
+// 按指定格式版本解析条目，用于升级、校验和抢救旧文件：
+// 版本 4 之前：[klen u8 | key | vlen u16 | value]*
+// 版本 4：[键字段 | varint(vlen) | value]*
+// 这两种格式的大值只在页内保存前 overflow_threshold 字节，其余部分挂在页级溢出链上
+// 版本 5：[键字段 | varint(vlen << 1 | 是否溢出) | value 或 溢出链起始页]*
+// 版本 6：同版本 7，但没有版本号
+// 版本 7、8：值字段为 varint(vlen << 2 | 是否带过期时间 << 1 | 是否溢出)，没有压缩标志
+fn parse_entries_as(data: &[u8], kv_count: u16, page_size: usize, version: u16) -> Vec<EntrySpan> {
+    let mut entries = Vec::with_capacity(kv_count as usize);
+    let mut pos = 0;
+
+    for _ in 0..kv_count {
+        match parse_entry(data, &mut pos, page_size, version) {
+            Some(entry) => entries.push(entry),
+            None => break,
+        }
+    }
+
+    entries
+}
+
+
+fn parse_entry(data: &[u8], pos: &mut usize, page_size: usize, version: u16) -> Option<EntrySpan> {
+    let start = *pos;
+    let (key_len, key, key_overflow) = if version >= ENTRY_FORMAT_VERSION {
+        decode_key(data, pos, page_size)?
+    } else {
+        let klen = *data.get(*pos)? as usize;
+        let key = *pos + 1..*pos + 1 + klen;
+        *pos = key.end;
+        (klen, key, 0)
+    };
+    if *pos > data.len() {
+        return None;
+    }
+
+    let value_field = *pos;
+    let mut expiring = false;
+    let mut entry_version = 0;
+    let mut compressed = false;
+    let (value_len, spilled) = if version >= COMPRESSION_VERSION {
+        let field = read_varint(data, pos)?;
+        compressed = field & 4 != 0;
+        expiring = field & 2 != 0;
+        entry_version = read_varint(data, pos)?;
+        (usize::try_from(field >> 3).ok()?, field & 1 != 0)
+    } else if version >= TTL_VERSION {
+        let field = read_varint(data, pos)?;
+        expiring = field & 2 != 0;
+        if version >= KEY_VERSION_VERSION {
+            entry_version = read_varint(data, pos)?;
+        }
+        (usize::try_from(field >> 2).ok()?, field & 1 != 0)
+    } else if version >= VALUE_OVERFLOW_VERSION {
+        let field = read_varint(data, pos)?;
+        (usize::try_from(field >> 1).ok()?, field & 1 != 0)
+    } else if version >= ENTRY_FORMAT_VERSION {
+        (usize::try_from(read_varint(data, pos)?).ok()?, false)
+    } else {
+        let bytes = data.get(*pos..*pos + 2)?;
+        *pos += 2;
+        (u16::from_le_bytes([bytes[0], bytes[1]]) as usize, false)
+    };
+
+    let expires_at = if expiring {
+        let bytes = data.get(*pos..*pos + 8)?;
+        *pos += 8;
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    } else {
+        None
+    };
+
+    let value_overflow = if spilled {
+        let bytes = data.get(*pos..*pos + 4)?;
+        *pos += 4;
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    } else {
+        0
+    };
+    let stored = if spilled { 0 } else { value_len };
+    if *pos + stored > data.len() {
+        return None;
+    }
+    let value = *pos..*pos + stored;
+    *pos += stored;
+
+    Some(EntrySpan {
+        start,
+        key_len,
+        key,
+        key_overflow,
+        value_field,
+        value_len,
+        value,
+        value_overflow,
+        expires_at,
+        version: entry_version,
+        compressed,
+    })
 }
 
 
 // 数据库文件头结构
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct DBHeader {
     magic: u32,                 // 魔数
     db_identifier: String,      // 数据库标识
     create_time: u64,           // 创建时间（毫秒）
     modify_time: u64,           // 修改时间（毫秒）
-    page_size: u16,             // 页大小
+    page_size: u32,             // 页大小，文件中以 u16 存储，65536 存为 0
     total_pages: u32,           // 总页数
     overflow_start: u32,        // 溢出页起始
     free_page_head: u32,        // 空闲页链表头
+    format_version: u16,        // 文件格式版本
+    index_root: u32,            // B+树索引根页，0 表示尚未建立
+    applied_lsn: u64,           // 已应用到页面的最后一条 WAL 记录的 LSN
+    keyspace_catalog: u32,      // 键空间目录所在溢出链的起始页，0 表示没有命名键空间
+    compression: Compression,   // 数据库默认的值压缩设置，旧文件读出不压缩
+    pending_blob: u32,          // 已提交但还没有 WAL 记录引用的流式写入溢出链，0 表示没有
 }
 
 
 impl DBHeader {
     const SIZE: usize = HEADER_SIZE;
+    const CHECKSUM_OFFSET: usize = Self::SIZE - 4;
 
     fn pack(&self) -> [u8; Self::SIZE] {
         let mut buf = [0u8; Self::SIZE];
@@ -119,16 +585,41 @@ impl DBHeader {
         cursor.write_all(&padded_identifier).unwrap();
         cursor.write_u64::<LittleEndian>(self.create_time).unwrap();
         cursor.write_u64::<LittleEndian>(self.modify_time).unwrap();
-        cursor.write_u16::<LittleEndian>(self.page_size).unwrap();
+        cursor.write_u16::<LittleEndian>(self.page_size as u16).unwrap();
         cursor.write_u32::<LittleEndian>(self.total_pages).unwrap();
         cursor.write_u32::<LittleEndian>(self.overflow_start).unwrap();
         cursor.write_u32::<LittleEndian>(self.free_page_head).unwrap();
-        cursor.write_all(&[0u8; 62]).unwrap(); // 保留区
-
+        cursor.write_u16::<LittleEndian>(self.format_version).unwrap();
+        cursor.write_u32::<LittleEndian>(self.index_root).unwrap();
+        cursor.write_u64::<LittleEndian>(self.applied_lsn).unwrap();
+        cursor.write_u32::<LittleEndian>(self.keyspace_catalog).unwrap();
+        cursor.write_u8(self.compression.codec.id()).unwrap();
+        cursor.write_u32::<LittleEndian>(self.compression.threshold).unwrap();
+        cursor.write_u32::<LittleEndian>(self.pending_blob).unwrap();
+        cursor.write_all(&[0u8; 31]).unwrap(); // 保留区
+
+        // 最后 4 字节为前面所有字节的 CRC32C
+        let checksum = crc32c::crc32c(&buf[..Self::CHECKSUM_OFFSET]);
+        buf[Self::CHECKSUM_OFFSET..].copy_from_slice(&checksum.to_le_bytes());
         buf
     }
 
     fn unpack(data: &[u8]) -> Result<Self> {
+        let header = Self::unpack_unchecked(data)?;
+        if header.format_version >= CHECKSUM_VERSION && !Self::checksum_ok(data) {
+            bail!(KvError::Corruption { page: 0 });
+        }
+        Ok(header)
+    }
+
+    fn checksum_ok(data: &[u8]) -> bool {
+        let stored = &data[Self::CHECKSUM_OFFSET..Self::SIZE];
+        u32::from_le_bytes([stored[0], stored[1], stored[2], stored[3]])
+            == crc32c::crc32c(&data[..Self::CHECKSUM_OFFSET])
+    }
+
+    // 只解析字段，不校验校验和（fsck 需要在文件头损坏时继续检查）
+    fn unpack_unchecked(data: &[u8]) -> Result<Self> {
         if data.len() < Self::SIZE {
             bail!("Header data too short");
         }
@@ -146,11 +637,32 @@ impl DBHeader {
 
         let create_time = cursor.read_u64::<LittleEndian>()?;
         let modify_time = cursor.read_u64::<LittleEndian>()?;
-        let page_size = cursor.read_u16::<LittleEndian>()?;
+        let page_size = match cursor.read_u16::<LittleEndian>()? {
+            0 => MAX_PAGE_SIZE as u32,
+            size => size as u32,
+        };
         let total_pages = cursor.read_u32::<LittleEndian>()?;
         let overflow_start = cursor.read_u32::<LittleEndian>()?;
         let free_page_head = cursor.read_u32::<LittleEndian>()?;
-        cursor.read_exact(&mut [0u8; 62])?; // 跳过保留区
+        let format_version = cursor.read_u16::<LittleEndian>()?;
+        let index_root = cursor.read_u32::<LittleEndian>()?;
+        let applied_lsn = cursor.read_u64::<LittleEndian>()?; // 旧文件此处为保留区，读出 0
+        let keyspace_catalog = cursor.read_u32::<LittleEndian>()?;
+        let codec = cursor.read_u8()?;
+        let threshold = cursor.read_u32::<LittleEndian>()?;
+        let pending_blob = cursor.read_u32::<LittleEndian>()?; // 旧文件此处为保留区，读出 0
+        cursor.read_exact(&mut [0u8; 31])?; // 跳过保留区
+        let compression = Compression {
+            codec: Codec::from_id(codec).ok_or_else(|| anyhow!("Unknown compression codec {}", codec))?,
+            threshold,
+        };
+
+        // 旧版本总是按 1KB 页读写，不信任文件头中的值
+        let page_size = if format_version < PAGE_SIZE_VERSION {
+            DEFAULT_PAGE_SIZE as u32
+        } else {
+            page_size
+        };
 
         Ok(Self {
             magic,
@@ -161,11 +673,66 @@ impl DBHeader {
             total_pages,
             overflow_start,
             free_page_head,
+            format_version,
+            index_root,
+            applied_lsn,
+            keyspace_catalog,
+            compression,
+            pending_blob,
         })
     }
 }
 
 
+// 数据库文件的内存映射，只读打开时使用只读映射
+enum FileMap {
+    ReadWrite(MmapMut),
+    ReadOnly(Mmap),
+}
+
+
+impl FileMap {
+    fn new(file: &File, read_only: bool) -> Result<Self> {
+        let map = if read_only {
+            Self::ReadOnly(unsafe { MmapOptions::new().map(file)? })
+        } else {
+            Self::ReadWrite(unsafe { MmapOptions::new().map_mut(file)? })
+        };
+        Ok(map)
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        match self {
+            Self::ReadWrite(map) => map.flush(),
+            Self::ReadOnly(_) => Ok(()),
+        }
+    }
+}
+
+
+impl Deref for FileMap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::ReadWrite(map) => map,
+            Self::ReadOnly(map) => map,
+        }
+    }
+}
+
+
+impl DerefMut for FileMap {
+    // 写操作在入口处已由 check_writable 拦截，不会走到只读映射
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            Self::ReadWrite(map) => map,
+            Self::ReadOnly(_) => unreachable!("write to a read-only database mapping"),
+        }
+    }
+}
+
+
 // LRU缓存实现
 struct LRUCache {
     cache: LinkedHashMap<u32, Vec<u8>>,
@@ -215,9 +782,8 @@ impl LRUCache {
 
     #[allow(dead_code)]
     fn remove(&mut self, key: u32) -> Option<Vec<u8>> {
-        self.cache.remove(&key).map(|v| {
+        self.cache.remove(&key).inspect(|v| {
             self.curr_size -= v.len();
-            v
         })
     }
 
@@ -230,110 +796,549 @@ impl LRUCache {
 }
 
 
+// 恢复时缓存的 WAL 记录：(操作类型, 键, 值)
+type WALRecord = (u8, Vec<u8>, Vec<u8>);
+
+
+/// WAL 的统计信息，见 [`KVStore::wal_stats`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalStats {
+    /// 当前 WAL 文件的字节数
+    pub size: u64,
+    /// 本次打开以来完成的检查点次数
+    pub checkpoints: u64,
+    /// 距上次检查点（或打开数据库）的时间
+    pub since_checkpoint: Duration,
+}
+
+
+impl std::fmt::Display for WalStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "WAL size {} bytes, {} checkpoints, last one {:.1}s ago",
+            self.size,
+            self.checkpoints,
+            self.since_checkpoint.as_secs_f64()
+        )
+    }
+}
+
+
+// 打开着的 WAL 文件，组提交时与后台刷盘线程共享
+struct WALWriter {
+    file: Option<File>,
+    unsynced: usize, // 上次 fsync 之后追加的批数
+    last_sync: Instant,
+}
+
+
+impl WALWriter {
+    fn sync(&mut self) -> Result<()> {
+        if let Some(file) = &self.file
+            && self.unsynced > 0
+        {
+            file.sync_all()?;
+        }
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+}
+
+
 // 预写日志管理器
 struct WALManager {
     wal_path: PathBuf,
+    durability: Durability,
+    writer: Arc<Mutex<WALWriter>>,
+    flusher_started: bool,
+    last_lsn: u64, // 最后一条写入 WAL 的记录的 LSN
+    // 当前的命名键空间，每条操作记录前都先写一条指向它的 OP_KEYSPACE 记录
+    keyspace: Option<String>,
+    size: u64,
+    checkpoints: u64,
+    last_checkpoint: Instant,
 }
 
 
 impl WALManager {
     const OP_PUT: u8 = 0;
     const OP_DELETE: u8 = 1;
-
-    fn new(db_path: &Path) -> Self {
+    const OP_BEGIN: u8 = 2; // 事务开始，之后的操作在 OP_COMMIT 之前不生效
+    const OP_COMMIT: u8 = 3; // 事务提交
+    const OP_PUT_BLOB: u8 = 4; // 值已写入溢出链，记录中只有 值长 u64 | 起始页 u32
+    const OP_PUT_TTL: u8 = 5; // 带过期时间的写入，值为 过期时间 u64 | 值
+    const OP_EXPIRE: u8 = 6; // 修改过期时间，值为 过期时间 u64，0 表示取消过期
+    const OP_KEYSPACE: u8 = 7; // 紧随其后的一条记录作用于键为名称的键空间（不存在时创建）
+    const OP_CREATE_KEYSPACE: u8 = 8; // 创建键为名称的键空间
+    const OP_CLEAR_KEYSPACE: u8 = 9; // 清空键为名称的键空间
+    const OP_DROP_KEYSPACE: u8 = 10; // 删除键为名称的键空间
+    const OP_CREATE_INDEX: u8 = 11; // 创建键为名称的二级索引，值为 JSON 指针
+    const OP_DROP_INDEX: u8 = 12; // 删除键为名称的二级索引
+    const OP_SET_COMPRESSION: u8 = 13; // 设置当前键空间的值压缩，值为 算法 u8 | 阈值 u32
+
+    fn new(db_path: &Path, durability: Durability) -> Self {
         let mut wal_path = db_path.to_path_buf();
         wal_path.set_extension(WAL_FILE_EXT);
-        Self { wal_path }
+        Self {
+            wal_path,
+            durability,
+            writer: Arc::new(Mutex::new(WALWriter {
+                file: None,
+                unsynced: 0,
+                last_sync: Instant::now(),
+            })),
+            flusher_started: false,
+            last_lsn: 0,
+            keyspace: None,
+            size: 0,
+            checkpoints: 0,
+            last_checkpoint: Instant::now(),
+        }
     }
 
-    fn log_operation(&self, op_type: u8, key: &[u8], value: Option<&[u8]>) -> Result<()> {
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.wal_path)?;
-
-        file.write_all(&[op_type])?;
+    // 每条记录一帧：CRC32C | 记录长 | LSN | 操作 | 键长 u16 | 键 | 值长 u32 | 值，
+    // 校验和覆盖记录长之后的全部字节
+    fn encode_record(buf: &mut Vec<u8>, lsn: u64, op_type: u8, key: &[u8], value: Option<&[u8]>) -> Result<()> {
+        let start = buf.len();
+        buf.extend_from_slice(&[0u8; 8]);
+        buf.write_u64::<LittleEndian>(lsn)?;
+        buf.push(op_type);
 
         // 写入键长度和键
-        file.write_u16::<LittleEndian>(key.len() as u16)?;
-        file.write_all(key)?;
+        buf.write_u16::<LittleEndian>(key.len() as u16)?;
+        buf.extend_from_slice(key);
 
         // 写入值长度和值（如果存在）
         let value = value.unwrap_or_default();
-        file.write_u32::<LittleEndian>(value.len() as u32)?;
-        file.write_all(value)?;
+        buf.write_u32::<LittleEndian>(value.len() as u32)?;
+        buf.extend_from_slice(value);
 
-        file.sync_all()?;
+        let record_len = (buf.len() - start - WAL_FRAME_HEADER_SIZE) as u32;
+        buf[start + 4..start + 8].copy_from_slice(&record_len.to_le_bytes());
+        let checksum = crc32c::crc32c(&buf[start + 4..]);
+        buf[start..start + 4].copy_from_slice(&checksum.to_le_bytes());
         Ok(())
     }
 
-    fn recover(self, store: &mut KVStore) -> Result<()> {
-        if !self.wal_path.exists() {
+    fn file_header() -> [u8; WAL_HEADER_SIZE] {
+        let mut header = [0u8; WAL_HEADER_SIZE];
+        header[..4].copy_from_slice(&WAL_MAGIC.to_le_bytes());
+        header[4..6].copy_from_slice(&WAL_VERSION.to_le_bytes());
+        header
+    }
+
+    fn lock_writer(&self) -> Result<MutexGuard<'_, WALWriter>> {
+        self.writer.lock().map_err(|_| anyhow!("WAL writer lock poisoned"))
+    }
+
+    fn append(&mut self, records: &[u8]) -> Result<()> {
+        if self.durability == Durability::Off {
             return Ok(());
         }
 
-        let mut file = File::open(&self.wal_path)?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)?;
-
-        let mut pos = 0;
-        while pos < buffer.len() {
-            if pos + 1 > buffer.len() {
-                break;
+        let mut writer = self.lock_writer()?;
+        let file = match &mut writer.file {
+            Some(file) => file,
+            None => writer.file.insert(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.wal_path)?,
+            ),
+        };
+        let mut written = if file.metadata()?.len() == 0 {
+            file.write_all(&Self::file_header())
+        } else {
+            Ok(())
+        };
+        if written.is_ok() {
+            written = file.write_all(records);
+        }
+        if let Err(e) = written {
+            // 去掉写了一半的帧，否则恢复会停在这里，丢掉之后的记录
+            file.set_len(self.size)?;
+            return Err(e.into());
+        }
+        let size = file.metadata()?.len();
+        writer.unsynced += 1;
+
+        let mut flush_interval = None;
+        match self.durability {
+            Durability::Full => writer.sync()?,
+            Durability::GroupCommit { interval_ms, max_batch } => {
+                let interval = Duration::from_millis(interval_ms);
+                if writer.unsynced >= max_batch || writer.last_sync.elapsed() >= interval {
+                    writer.sync()?;
+                }
+                flush_interval = Some(interval);
             }
-            let op_type = buffer[pos];
-            pos += 1;
+            Durability::OsBuffered | Durability::Off => {}
+        }
+        drop(writer);
 
-            if pos + 2 > buffer.len() {
-                break;
+        self.size = size;
+        if let Some(interval) = flush_interval {
+            self.start_flusher(interval)?;
+        }
+        Ok(())
+    }
+
+    // 组提交的后台线程：按间隔 fsync 尚未落盘的批次，WALManager 释放后退出
+    fn start_flusher(&mut self, interval: Duration) -> Result<()> {
+        if self.flusher_started {
+            return Ok(());
+        }
+        let writer = Arc::downgrade(&self.writer);
+        std::thread::Builder::new()
+            .name("wal-flusher".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                let Some(writer) = writer.upgrade() else {
+                    break;
+                };
+                let Ok(mut writer) = writer.lock() else {
+                    break;
+                };
+                if writer.unsynced > 0
+                    && writer.last_sync.elapsed() >= interval
+                    && let Err(e) = writer.sync()
+                {
+                    error!("WAL group commit sync failed: {}", e);
+                }
+            })?;
+        self.flusher_started = true;
+        Ok(())
+    }
+
+    fn log_operation(&mut self, op_type: u8, key: &[u8], value: Option<&[u8]>) -> Result<()> {
+        let mut lsn = self.last_lsn + 1;
+        let mut buf = Vec::new();
+        if let Some(keyspace) = &self.keyspace {
+            Self::encode_record(&mut buf, lsn, Self::OP_KEYSPACE, keyspace.as_bytes(), None)?;
+            lsn += 1;
+        }
+        Self::encode_record(&mut buf, lsn, op_type, key, value)?;
+        self.append(&buf)?;
+        self.last_lsn = lsn;
+        Ok(())
+    }
+
+    // 整个事务一次写入并落盘：BEGIN | 操作... | COMMIT
+    fn log_transaction(&mut self, writes: &BTreeMap<Vec<u8>, Option<Vec<u8>>>) -> Result<()> {
+        let mut lsn = self.last_lsn + 1;
+        let mut buf = Vec::new();
+        Self::encode_record(&mut buf, lsn, Self::OP_BEGIN, &[], None)?;
+        for (key, value) in writes {
+            if let Some(keyspace) = &self.keyspace {
+                lsn += 1;
+                Self::encode_record(&mut buf, lsn, Self::OP_KEYSPACE, keyspace.as_bytes(), None)?;
             }
-            let key_len = u16::from_le_bytes([buffer[pos], buffer[pos + 1]]) as usize;
-            pos += 2;
+            lsn += 1;
+            match value {
+                Some(value) => Self::encode_record(&mut buf, lsn, Self::OP_PUT, key, Some(value))?,
+                None => Self::encode_record(&mut buf, lsn, Self::OP_DELETE, key, None)?,
+            }
+        }
+        lsn += 1;
+        Self::encode_record(&mut buf, lsn, Self::OP_COMMIT, &[], None)?;
+        self.append(&buf)?;
+        self.last_lsn = lsn;
+        Ok(())
+    }
 
-            if pos + key_len > buffer.len() {
-                break;
+    fn apply(store: &mut KVStore, op_type: u8, key: &[u8], value: &[u8]) {
+        if op_type == Self::OP_KEYSPACE {
+            match std::str::from_utf8(key).map_err(Into::into).and_then(|name| store.create_keyspace(name)) {
+                Ok(id) => store.keyspace = id,
+                Err(e) => error!("WAL recovery keyspace switch failed: {}", e),
             }
-            let key = buffer[pos..pos + key_len].to_vec();
-            pos += key_len;
+            return;
+        }
 
-            if pos + 4 > buffer.len() {
-                break;
+        match op_type {
+            Self::OP_PUT => {
+                if let Err(e) = store.put_internal(key, value) {
+                    error!("WAL recovery put failed: {}", e);
+                }
             }
-            let value_len = u32::from_le_bytes([
-                buffer[pos],
-                buffer[pos + 1],
-                buffer[pos + 2],
-                buffer[pos + 3],
-            ]) as usize;
-            pos += 4;
-
-            if pos + value_len > buffer.len() {
-                break;
+            Self::OP_DELETE => {
+                if let Err(e) = store.delete_internal(key) {
+                    error!("WAL recovery delete failed: {}", e);
+                }
             }
-            let value = buffer[pos..pos + value_len].to_vec();
-            pos += value_len;
-
-            match op_type {
-                Self::OP_PUT => {
-                    if let Err(e) = store.put_internal(&key, &value, false) {
-                        error!("WAL recovery put failed: {}", e);
+            Self::OP_PUT_BLOB => {
+                let result = Self::decode_blob_ref(value).and_then(|value| {
+                    store.claim_pending_blob(&value);
+                    store.put_stored(key, &value, store.write_meta(None))
+                });
+                if let Err(e) = result {
+                    error!("WAL recovery blob put failed: {}", e);
+                }
+            }
+            Self::OP_PUT_TTL => {
+                let result = Self::decode_expiry(value)
+                    .and_then(|expires_at| store.put_expiring(key, &value[8..], expires_at));
+                if let Err(e) = result {
+                    error!("WAL recovery TTL put failed: {}", e);
+                }
+            }
+            Self::OP_EXPIRE => {
+                let result = Self::decode_expiry(value)
+                    .and_then(|expires_at| store.set_expiry(key, expires_at));
+                if let Err(e) = result {
+                    error!("WAL recovery expire failed: {}", e);
+                }
+            }
+            Self::OP_CREATE_KEYSPACE | Self::OP_CLEAR_KEYSPACE | Self::OP_DROP_KEYSPACE => {
+                let result = std::str::from_utf8(key).map_err(Into::into).and_then(|name| {
+                    match op_type {
+                        Self::OP_CREATE_KEYSPACE => store.create_keyspace(name).map(drop),
+                        Self::OP_CLEAR_KEYSPACE => store.clear_keyspace_internal(name).map(drop),
+                        _ => store.drop_keyspace_internal(name).map(drop),
                     }
+                });
+                if let Err(e) = result {
+                    error!("WAL recovery keyspace operation failed: {}", e);
                 }
-                Self::OP_DELETE => {
-                    if let Err(e) = store.delete_internal(&key, false) {
-                        error!("WAL recovery delete failed: {}", e);
+            }
+            Self::OP_CREATE_INDEX | Self::OP_DROP_INDEX => {
+                let result = std::str::from_utf8(key).map_err(Into::into).and_then(|name| {
+                    if op_type == Self::OP_DROP_INDEX {
+                        return store.drop_index_internal(name).map(drop);
                     }
+                    std::str::from_utf8(value)
+                        .map_err(Into::into)
+                        .and_then(|pointer| store.create_index_internal(name, pointer).map(drop))
+                });
+                if let Err(e) = result {
+                    error!("WAL recovery index operation failed: {}", e);
                 }
-                _ => {
-                    error!("Unknown WAL operation type: {}", op_type);
+            }
+            Self::OP_SET_COMPRESSION => {
+                let result = Compression::decode(value)
+                    .and_then(|compression| store.set_compression_internal(compression));
+                if let Err(e) = result {
+                    error!("WAL recovery compression setting failed: {}", e);
                 }
             }
+            _ => {
+                error!("Unknown WAL operation type: {}", op_type);
+            }
+        }
+        store.keyspace = DEFAULT_KEYSPACE_ID;
+    }
+
+    fn encode_blob_ref(len: u64, start: u32) -> [u8; 12] {
+        let mut buf = [0u8; 12];
+        buf[..8].copy_from_slice(&len.to_le_bytes());
+        buf[8..].copy_from_slice(&start.to_le_bytes());
+        buf
+    }
+
+    fn decode_blob_ref(value: &[u8]) -> Result<StoredValue<'static>> {
+        if value.len() != 12 {
+            bail!("Malformed blob reference in WAL");
+        }
+        let len = u64::from_le_bytes(value[..8].try_into()?);
+        let start = u32::from_le_bytes(value[8..].try_into()?);
+        Ok(StoredValue::Overflow {
+            len: usize::try_from(len)?,
+            start,
+        })
+    }
+
+    // 过期时间记录在值的前 8 字节，0 表示不过期
+    fn encode_expiry(expires_at: Option<u64>) -> [u8; 8] {
+        expires_at.unwrap_or(0).to_le_bytes()
+    }
+
+    fn decode_expiry(value: &[u8]) -> Result<Option<u64>> {
+        let bytes = value
+            .get(..8)
+            .ok_or_else(|| anyhow!("Malformed expiry in WAL"))?;
+        let expires_at = u64::from_le_bytes(bytes.try_into()?);
+        Ok((expires_at != 0).then_some(expires_at))
+    }
+
+    // 页面全部落盘后调用，此前的记录都不再需要
+    fn truncate(&mut self) -> Result<()> {
+        let mut writer = self.lock_writer()?;
+        writer.file = None;
+        writer.unsynced = 0;
+        if self.wal_path.exists() {
+            std::fs::remove_file(&self.wal_path)?;
+        }
+        drop(writer);
+        self.size = 0;
+        self.checkpoints += 1;
+        self.last_checkpoint = Instant::now();
+        Ok(())
+    }
+
+    // 写入结束后是否应当执行检查点，阈值为 0 时不按该条件触发
+    fn needs_checkpoint(&self, options: &KVStoreOptions) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let by_size = options.wal_checkpoint_size > 0 && self.size >= options.wal_checkpoint_size;
+        let by_time = !options.wal_checkpoint_interval.is_zero()
+            && self.last_checkpoint.elapsed() >= options.wal_checkpoint_interval;
+        by_size || by_time
+    }
+
+    fn stats(&self) -> WalStats {
+        WalStats {
+            size: self.size,
+            checkpoints: self.checkpoints,
+            since_checkpoint: self.last_checkpoint.elapsed(),
+        }
+    }
+
+    // 重放文件头记录的 LSN 之后的操作，已应用过的记录不会再执行一次
+    fn recover(self, store: &mut KVStore) -> Result<()> {
+        if !self.wal_path.exists() {
+            return Ok(());
+        }
+
+        let (records, last_lsn) = Self::read_committed(&self.wal_path, store.header.applied_lsn)?;
+        for (lsn, (op_type, key, value)) in records {
+            // 重放的写入使用原记录的 LSN 作为版本号
+            store.wal_manager.last_lsn = lsn;
+            Self::apply(store, op_type, &key, &value);
         }
+        store.keyspace = DEFAULT_KEYSPACE_ID;
 
-        // 删除WAL文件
+        // 重放的修改落盘后才能删除WAL文件
+        store.header.applied_lsn = last_lsn;
+        store.sync()?;
         std::fs::remove_file(&self.wal_path)?;
         Ok(())
     }
+
+    // 按顺序读出 LSN 大于 applied_lsn 的已生效操作：事务外的单条操作，以及带 COMMIT 标记的事务中的操作。
+    // 每个操作附带它生效时的 LSN，事务中的操作为 COMMIT 记录的 LSN，与提交时写入的版本号一致。
+    // 同时返回日志中最后一条完好记录的 LSN；旧格式的日志没有 LSN，其中的操作全部重放
+    fn read_committed(wal_path: &Path, applied_lsn: u64) -> Result<(Vec<(u64, WALRecord)>, u64)> {
+        let mut file = File::open(wal_path)?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+
+        let records = if buffer.starts_with(&WAL_MAGIC.to_le_bytes()) {
+            Self::read_frames(&buffer)?
+        } else {
+            Self::read_legacy(&buffer, applied_lsn)
+        };
+        let last_lsn = records.last().map_or(applied_lsn, |(lsn, _)| *lsn).max(applied_lsn);
+
+        let mut committed = Vec::new();
+        let mut transaction: Option<Vec<WALRecord>> = None;
+        for (lsn, record) in records {
+            match record.0 {
+                Self::OP_BEGIN => {
+                    if let Some(discarded) = transaction.replace(Vec::new()) {
+                        error!("WAL transaction without commit, {} operations discarded", discarded.len());
+                    }
+                }
+                Self::OP_COMMIT => match transaction.take() {
+                    Some(operations) => committed.extend(operations.into_iter().map(|record| (lsn, record))),
+                    None => error!("WAL commit without matching begin"),
+                },
+                _ if lsn <= applied_lsn => {}
+                _ => match transaction.as_mut() {
+                    Some(operations) => operations.push(record),
+                    None => committed.push((lsn, record)),
+                },
+            }
+        }
+
+        // 未提交的事务尾部直接丢弃
+        if let Some(discarded) = transaction {
+            error!("WAL ends inside a transaction, {} operations discarded", discarded.len());
+        }
+
+        Ok((committed, last_lsn))
+    }
+
+    // 逐帧读取，遇到第一条不完整、校验和不符或 LSN 不递增的记录即停止
+    fn read_frames(buffer: &[u8]) -> Result<Vec<(u64, WALRecord)>> {
+        let Some(version) = buffer.get(4..6) else {
+            return Ok(Vec::new());
+        };
+        let version = u16::from_le_bytes([version[0], version[1]]);
+        if version != WAL_VERSION {
+            bail!("Unsupported WAL version {}", version);
+        }
+
+        let mut pos = WAL_HEADER_SIZE;
+        let mut records: Vec<(u64, WALRecord)> = Vec::new();
+        while pos < buffer.len() {
+            let Some((lsn, record, next)) = Self::decode_frame(buffer, pos) else {
+                error!("WAL record at offset {} is torn or corrupt, recovery stops there", pos);
+                break;
+            };
+            if records.last().is_some_and(|(last, _)| lsn <= *last) {
+                error!("WAL record at offset {} has out-of-order LSN {}, recovery stops there", pos, lsn);
+                break;
+            }
+            records.push((lsn, record));
+            pos = next;
+        }
+        Ok(records)
+    }
+
+    fn decode_frame(buffer: &[u8], pos: usize) -> Option<(u64, WALRecord, usize)> {
+        let header = buffer.get(pos..pos + WAL_FRAME_HEADER_SIZE)?;
+        let checksum = u32::from_le_bytes(header[..4].try_into().ok()?);
+        let record_len = u32::from_le_bytes(header[4..8].try_into().ok()?) as usize;
+        let lsn = u64::from_le_bytes(header[8..].try_into().ok()?);
+
+        let end = (pos + WAL_FRAME_HEADER_SIZE).checked_add(record_len)?;
+        if crc32c::crc32c(buffer.get(pos + 4..end)?) != checksum {
+            return None;
+        }
+
+        let (record, consumed) = Self::decode_record(&buffer[pos + WAL_FRAME_HEADER_SIZE..end])?;
+        if consumed != record_len || record.0 > Self::OP_SET_COMPRESSION {
+            return None;
+        }
+        Some((lsn, record, end))
+    }
+
+    // 旧格式的日志是首尾相接的裸记录，按顺序编上 applied_lsn 之后的 LSN
+    fn read_legacy(buffer: &[u8], applied_lsn: u64) -> Vec<(u64, WALRecord)> {
+        let mut pos = 0;
+        let mut records = Vec::new();
+        while let Some((record, consumed)) = Self::decode_record(&buffer[pos..]) {
+            records.push((applied_lsn + records.len() as u64 + 1, record));
+            pos += consumed;
+        }
+        records
+    }
+
+    // 解析一条 操作 | 键长 u16 | 键 | 值长 u32 | 值，返回记录和占用的字节数
+    fn decode_record(data: &[u8]) -> Option<(WALRecord, usize)> {
+        let op_type = *data.first()?;
+        let key_len = u16::from_le_bytes(data.get(1..3)?.try_into().ok()?) as usize;
+        let key = data.get(3..3 + key_len)?.to_vec();
+        let pos = 3 + key_len;
+        let value_len = u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let value = data.get(pos + 4..pos + 4 + value_len)?.to_vec();
+        Some(((op_type, key, value), pos + 4 + value_len))
+    }
+}
+
+
+// 组提交时关闭前补做最后一批的 fsync
+impl Drop for WALManager {
+    fn drop(&mut self) {
+        if let Ok(mut writer) = self.writer.lock()
+            && let Err(e) = writer.sync()
+        {
+            error!("WAL sync on close failed: {}", e);
+        }
+    }
 }
 
 
@@ -341,55 +1346,108 @@ impl WALManager {
 pub struct KVStore {
     path: PathBuf,
     file: File,
-    mmap: MmapMut,
+    mmap: FileMap,
     header: DBHeader,
-    key_to_page: std::collections::HashMap<Vec<u8>, u32>,
-    page_cache: LRUCache,
+    page_cache: Mutex<LRUCache>,
     dirty_pages: HashSet<u32>,
     wal_manager: WALManager,
     last_used_page: u32,
+    options: KVStoreOptions,
+    // 当前键空间的编号，读写都作用于它的索引
+    keyspace: u16,
+    // 命名键空间目录，默认键空间不在其中
+    keyspaces: Vec<KeyspaceEntry>,
+    // 所有键空间上的二级索引
+    indexes: Vec<IndexDef>,
+    // 存活的快照，覆盖页面前要为它们保留旧内容
+    snapshots: Mutex<Vec<Weak<PinnedPages>>>,
+    // 仅快照视图使用：快照创建后被覆盖的页
+    pinned: Option<Arc<PinnedPages>>,
 }
 
 
 impl KVStore {
+    /// 打开数据库，文件不存在时新建
+    ///
+    /// 打开时对文件加独占锁，同一文件同时只能有一个读写句柄，锁被占用时返回
+    /// [`KvError::Locked`]，需要等待时用 [`KVStoreOptions::lock_timeout`]。
     pub fn open<P: AsRef<Path>>(path: P, db_identifier: Option<&str>) -> Result<Self> {
-        let path = path.as_ref();
-        let wal_manager = WALManager::new(path);
+        let mut options = KVStoreOptions::new(path);
+        if let Some(id) = db_identifier {
+            options = options.identifier(id);
+        }
+        Self::open_with_options(&options)
+    }
+
+
+    /// 只读打开已有的数据库，见 [`KVStoreOptions::read_only`]
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_options(&KVStoreOptions::new(path).read_only(true))
+    }
+
+
+    /// 按选项打开数据库，等价于 [`KVStoreOptions::open`]
+    pub fn open_with_options(options: &KVStoreOptions) -> Result<Self> {
+        let path = options.path.as_path();
+        // WAL 记录中键长为 u16
+        if options.max_key_size > u16::MAX as usize {
+            bail!("Maximum key size {} exceeds {} bytes", options.max_key_size, u16::MAX);
+        }
+        if let Durability::GroupCommit { interval_ms, max_batch } = options.durability
+            && (interval_ms == 0 || max_batch == 0)
+        {
+            bail!("Group commit interval and batch size must be greater than zero");
+        }
 
         if !path.exists() {
-            Self::create_new_db(path, db_identifier, wal_manager)
+            if options.read_only {
+                bail!("Database {} does not exist", path.display());
+            }
+            Self::create_new_db(path, options)
         } else {
-            Self::open_existing_db(path, db_identifier, wal_manager)
+            Self::open_existing_db(path, options)
         }
     }
 
 
-    fn create_new_db(
-        path: &Path,
-        db_identifier: Option<&str>,
-        wal_manager: WALManager,
-    ) -> Result<Self> {
+    fn create_new_db(path: &Path, options: &KVStoreOptions) -> Result<Self> {
+        let page_size = options.page_size;
+        check_page_size(page_size)?;
+
+        // 先加锁再清空，避免截断别人正在使用的文件
         let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(path)?;
-        let file = file; // 重新绑定为可变
-        file.set_len((HEADER_SIZE + PAGE_SIZE) as u64)?;
+        lock_file(&file, path, false, options.lock_timeout)?;
+        file.set_len(0)?;
+        file.set_len((HEADER_SIZE + page_size) as u64)?;
 
-        let mut mmap = unsafe { MmapOptions::new().map_mut(&file)? };
+        let mut mmap = FileMap::new(&file, false)?;
 
-        let identifier = db_identifier.unwrap_or("KVStore").to_string();
+        let identifier = options
+            .identifier
+            .as_deref()
+            .unwrap_or(DEFAULT_IDENTIFIER)
+            .to_string();
         let now = current_time_millis();
         let header = DBHeader {
             magic: MAGIC_NUMBER,
             db_identifier: identifier,
             create_time: now,
             modify_time: now,
-            page_size: PAGE_SIZE as u16,
+            page_size: page_size as u32,
             total_pages: 1,
             overflow_start: 0,
             free_page_head: 0,
+            format_version: FORMAT_VERSION,
+            index_root: 0,
+            applied_lsn: 0,
+            keyspace_catalog: 0,
+            compression: options.compression,
+            pending_blob: 0,
         };
 
         mmap[..DBHeader::SIZE].copy_from_slice(&header.pack());
@@ -400,8 +1458,12 @@ impl KVStore {
             kv_count: 0,
             data_len: 0,
             next_page: 0,
+            keyspace: DEFAULT_KEYSPACE_ID,
         };
-        mmap[HEADER_SIZE..HEADER_SIZE + PageHeader::SIZE].copy_from_slice(&page_header.pack());
+        let mut first_page = page_header.pack().to_vec();
+        first_page.resize(page_size, 0);
+        seal_page(&mut first_page);
+        mmap[HEADER_SIZE..HEADER_SIZE + page_size].copy_from_slice(&first_page);
 
         mmap.flush()?;
 
@@ -410,252 +1472,444 @@ impl KVStore {
             file,
             mmap,
             header,
-            key_to_page: std::collections::HashMap::new(),
-            page_cache: LRUCache::new(MAX_CACHE_SIZE),
+            page_cache: Mutex::new(LRUCache::new(options.cache_size)),
             dirty_pages: HashSet::new(),
-            wal_manager,
+            wal_manager: WALManager::new(path, options.durability),
             last_used_page: 1,
+            options: options.clone(),
+            keyspace: DEFAULT_KEYSPACE_ID,
+            keyspaces: Vec::new(),
+            indexes: Vec::new(),
+            snapshots: Mutex::new(Vec::new()),
+            pinned: None,
         })
     }
 
 
-    fn open_existing_db(
-        path: &Path,
-        db_identifier: Option<&str>,
-        wal_manager: WALManager,
-    ) -> Result<Self> {
-        // 文件需要可变，但变量名不需要 mut
-        let file = OpenOptions::new().read(true).write(true).open(path)?;
-        let file = file; // 重新绑定为可变
-        let mmap = unsafe { MmapOptions::new().map_mut(&file)? };
+    fn open_existing_db(path: &Path, options: &KVStoreOptions) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(!options.read_only)
+            .open(path)?;
+        lock_file(&file, path, options.read_only, options.lock_timeout)?;
+        let mmap = FileMap::new(&file, options.read_only)?;
 
         if mmap.len() < HEADER_SIZE {
             bail!("Database file too small");
         }
 
         let mut header = DBHeader::unpack(&mmap[..HEADER_SIZE])?;
-        if let Some(id) = db_identifier {
-            header.db_identifier = id.to_string();
+        if header.format_version > FORMAT_VERSION {
+            bail!(KvError::UnsupportedVersion(header.format_version));
+        }
+        check_page_size(header.page_size as usize)?;
+        if let Some(id) = &options.identifier {
+            header.db_identifier = id.clone();
         }
 
+        let last_used_page = header.total_pages;
         let mut store = Self {
             path: path.to_path_buf(),
             file,
             mmap,
             header,
-            key_to_page: std::collections::HashMap::new(),
-            page_cache: LRUCache::new(MAX_CACHE_SIZE),
+            page_cache: Mutex::new(LRUCache::new(options.cache_size)),
             dirty_pages: HashSet::new(),
-            wal_manager,
-            last_used_page: 0,
+            wal_manager: WALManager::new(path, options.durability),
+            last_used_page,
+            options: options.clone(),
+            keyspace: DEFAULT_KEYSPACE_ID,
+            keyspaces: Vec::new(),
+            indexes: Vec::new(),
+            snapshots: Mutex::new(Vec::new()),
+            pinned: None,
         };
+        store.load_keyspaces()?;
+
+        // 只读打开不升级也不重放 WAL，看到的是最后一次写入文件的状态
+        if options.read_only {
+            if store.header.format_version < FORMAT_VERSION {
+                bail!(
+                    "Database format version {} must be upgraded by a read-write open first",
+                    store.header.format_version
+                );
+            }
+            if store.wal_manager.wal_path.exists() {
+                warn!("{} has a WAL that is not replayed in read-only mode", path.display());
+            }
+            store.load_indexes()?;
+            return Ok(store);
+        }
 
-        store.build_index()?;
+        if store.header.format_version < CHECKSUM_VERSION {
+            store.upgrade_page_checksums()?;
+        }
+        if store.header.format_version < COMPRESSION_VERSION {
+            store.upgrade_entry_format()?;
+        }
+        // 索引目录保存在数据页中，要按当前格式解析
+        store.load_indexes()?;
+        if store.header.format_version < INDEX_NUMBER_VERSION {
+            store.upgrade_index_numbers()?;
+        }
 
         // 临时取出 wal_manager 进行恢复操作
         let wal_manager = mem::replace(
             &mut store.wal_manager,
-            WALManager::new(&store.path),
+            WALManager::new(path, options.durability),
         );
         wal_manager.recover(&mut store)?;
+        store.wal_manager.last_lsn = store.header.applied_lsn;
+        store.reclaim_pending_blob()?;
 
         Ok(store)
     }
 
 
     pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        self.check_key_size(key)?;
         self.wal_manager
             .log_operation(WALManager::OP_PUT, key, Some(value))?;
-        self.put_internal(key, value, true)
+        self.put_internal(key, value)?;
+        self.finish_write()
     }
 
 
-    fn put_internal(&mut self, key: &[u8], value: &[u8], update_index: bool) -> Result<()> {
-        if self.key_to_page.contains_key(key) {
-            self.update_existing(key, value)?;
-            return Ok(());
+    fn put_internal(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.put_expiring(key, value, None)
+    }
+
+
+    fn put_expiring(&mut self, key: &[u8], value: &[u8], expires_at: Option<u64>) -> Result<()> {
+        self.put_value(key, value, self.write_meta(expires_at))
+    }
+
+
+    // 本次写入的元数据。版本号取最后一条 WAL 记录的 LSN，所以必须先记录 WAL 再应用；
+    // 重放时为被重放记录的 LSN
+    fn write_meta(&self, expires_at: Option<u64>) -> EntryMeta {
+        EntryMeta {
+            expires_at,
+            version: self.wal_manager.last_lsn,
+            compressed: false,
         }
+    }
 
-        let required_space = 1 + key.len() + 2 + value.len();
 
-        if self.last_used_page != 0 {
-            let page_data = self.read_page(self.last_used_page)?;
-            let header = PageHeader::unpack(&page_data)?;
+    fn put_value(&mut self, key: &[u8], value: &[u8], mut meta: EntryMeta) -> Result<()> {
+        // 按当前键空间的设置压缩，是否溢出按压缩后的长度判断
+        let compressed = self.compress_value(value);
+        meta.compressed = compressed.is_some();
+        let value = compressed.as_deref().unwrap_or(value);
+
+        // 大值先整体写入溢出链
+        let value = if value.len() > overflow_threshold(self.page_size()) {
+            StoredValue::Overflow {
+                len: value.len(),
+                start: self.write_overflow(value)?,
+            }
+        } else {
+            StoredValue::Inline(value)
+        };
+        self.put_stored(key, &value, meta)
+    }
+
+
+    fn put_stored(&mut self, key: &[u8], value: &StoredValue, meta: EntryMeta) -> Result<()> {
+        if !self.has_indexes() {
+            return self.write_stored(key, value, meta);
+        }
+        let old = self.indexed_value(key)?;
+        self.write_stored(key, value, meta)?;
+        let new = self.indexed_value(key)?;
+        self.update_indexes(key, old.as_deref(), new.as_deref())
+    }
 
-            let free_space = PAGE_SIZE - PageHeader::SIZE - header.data_len as usize;
-            if required_space <= free_space {
-                self.insert_to_page(self.last_used_page, key, value, update_index)?;
+
+    fn write_stored(&mut self, key: &[u8], value: &StoredValue, meta: EntryMeta) -> Result<()> {
+        if let Some(page_num) = self.index_lookup(key)? {
+            // 索引与数据页不一致时（如崩溃后）按新键重新插入
+            let updated = self.update_existing(page_num, key, value, meta)?;
+            if updated {
                 return Ok(());
             }
         }
 
+        let required_space = encoded_key_len(key, self.page_size()) + value.encoded_len(meta);
+
+        if self.last_used_page != 0 && self.page_has_space(self.last_used_page, required_space)? {
+            return self.insert_to_page(self.last_used_page, key, value, meta);
+        }
+
         // 收集缓存的页面号副本
-        let cached_pages: Vec<u32> = self.page_cache.cache.keys().cloned().collect();
+        let cached_pages: Vec<u32> = self.cache().cache.keys().cloned().collect();
         for page_num in cached_pages {
             if page_num == self.last_used_page {
                 continue;
             }
 
-            let page_data = self.read_page(page_num)?;
-            let header = PageHeader::unpack(&page_data)?;
-
-            let free_space = PAGE_SIZE - PageHeader::SIZE - header.data_len as usize;
-            if required_space <= free_space {
-                self.insert_to_page(page_num, key, value, update_index)?;
-                return Ok(());
+            if self.page_has_space(page_num, required_space)? {
+                return self.insert_to_page(page_num, key, value, meta);
             }
         }
 
         // 分配新页
         let new_page = self.allocate_page()?;
-        self.insert_to_page(new_page, key, value, update_index)?;
+        self.insert_to_page(new_page, key, value, meta)
+    }
+
+
+    /// 是否以只读方式打开
+    pub fn is_read_only(&self) -> bool {
+        matches!(self.mmap, FileMap::ReadOnly(_))
+    }
+
+
+    fn check_writable(&self) -> Result<()> {
+        if self.is_read_only() {
+            bail!(KvError::ReadOnly);
+        }
+        Ok(())
+    }
+
+
+    fn check_key_size(&self, key: &[u8]) -> Result<()> {
+        let max = self.options.max_key_size;
+        if key.len() > max {
+            bail!(KvError::KeyTooLarge { len: key.len(), max });
+        }
         Ok(())
     }
-    
-    
-    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
 
+
+    // 页面是否为当前键空间的数据页且剩余空间足够
+    fn page_has_space(&self, page_num: u32, required_space: usize) -> Result<bool> {
+        let page_data = self.read_page(page_num)?;
+        let header = PageHeader::unpack(&page_data)?;
+        if !header.is_data_page() || header.keyspace != self.keyspace {
+            return Ok(false);
+        }
+
+        let free_space = self.page_size() - PageHeader::SIZE - header.data_len as usize;
+        Ok(required_space <= free_space)
+    }
+
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_at(self.keyspace, key)
+    }
+
+
+    // 读取键空间 id 中的键，不切换当前键空间
+    pub(super) fn get_at(&self, id: u16, key: &[u8]) -> Result<Option<Vec<u8>>> {
         if key.is_empty() {
             return Err(anyhow!("Empty key is reserved for internal use"));
         }
 
-        let page_num = match self.key_to_page.get(key) {
-            Some(num) => *num,
+        let page_num = match self.index_lookup_at(id, key)? {
+            Some(num) => num,
             None => return Ok(None),
         };
 
-        let page_data = self.read_page(page_num)?;
-        let header = PageHeader::unpack(&page_data)?;
-        let data_start = PageHeader::SIZE;
-        let data_end = data_start + header.data_len as usize;
-        let data = &page_data[data_start..data_end];
+        self.read_value(page_num, key)
+    }
 
-        let mut pos = 0;
-        for _ in 0..header.kv_count {
-            if pos >= data.len() {
-                break;
-            }
 
-            let klen = data[pos] as usize;
-            pos += 1;
+    // 条目的键是否等于 key：先比较长度和页内前缀，必要时才读取溢出链
+    fn entry_matches(&self, data: &[u8], entry: &EntrySpan, key: &[u8]) -> Result<bool> {
+        let inline = &data[entry.key.clone()];
+        if entry.key_len != key.len() || !key.starts_with(inline) {
+            return Ok(false);
+        }
+        if entry.key_overflow == 0 {
+            return Ok(true);
+        }
+        Ok(self.read_overflow(entry.key_overflow)? == key[inline.len()..])
+    }
 
-            if pos + klen > data.len() {
-                break;
-            }
-            let current_key = &data[pos..pos + klen];
-            pos += klen;
 
-            if pos + 2 > data.len() {
-                break;
+    // 在数据页的条目中查找键
+    fn find_entry(&self, data: &[u8], kv_count: u16, key: &[u8]) -> Result<Option<EntrySpan>> {
+        for entry in parse_entries(data, kv_count, self.page_size()) {
+            if self.entry_matches(data, &entry, key)? {
+                return Ok(Some(entry));
             }
-            let vlen = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
-            pos += 2;
+        }
+        Ok(None)
+    }
 
-            if pos + vlen > data.len() {
-                break;
-            }
 
-            if current_key == key {
-                let mut value = data[pos..pos + vlen].to_vec();
+    // 从指定数据页中读取键对应的值
+    fn read_value(&self, page_num: u32, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let page_data = self.read_page(page_num)?;
+        let header = PageHeader::unpack(&page_data)?;
+        let data_start = PageHeader::SIZE;
+        let data_end = data_start + header.data_len as usize;
+        let data = &page_data[data_start..data_end];
 
-                // 处理溢出
-                if header.flags & 0x02 != 0 {
-                    let overflow_data = self.read_overflow(header.next_page)?;
-                    value.extend_from_slice(&overflow_data);
-                }
+        match self.find_entry(data, header.kv_count, key)? {
+            Some(entry) if !self.is_expired(&entry) => Ok(Some(self.entry_value(data, &entry)?)),
+            _ => Ok(None),
+        }
+    }
 
-                return Ok(Some(value));
-            }
 
-            pos += vlen;
+    // 条目的完整值：值溢出时整个值都在溢出链中，压缩保存的值解压后返回
+    fn entry_value(&self, data: &[u8], entry: &EntrySpan) -> Result<Vec<u8>> {
+        let stored = if entry.value_overflow != 0 {
+            self.read_overflow(entry.value_overflow)?
+        } else {
+            data[entry.value.clone()].to_vec()
+        };
+        if entry.compressed {
+            return decompress(&stored);
         }
+        Ok(stored)
+    }
 
-        Ok(None)
+
+    // 条目是否已过期。快照按创建时的时间判断，保证重复读取的结果一致
+    fn is_expired(&self, entry: &EntrySpan) -> bool {
+        let now = match &self.pinned {
+            Some(pinned) => pinned.created_at,
+            None => current_time_millis(),
+        };
+        entry.expires_at.is_some_and(|expires_at| expires_at <= now)
     }
 
 
-    // 获取所有键值对
-    #[allow(dead_code)]
-    pub fn get_all(&mut self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
-        let mut result = Vec::new();
+    // 按键序获取所有键值对
+    pub fn get_all(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.cursor()?.collect()
+    }
 
-        // 遍历所有页面
-        for page_num in 1..=self.header.total_pages {
-            if self.check_free_page(page_num)? {
-                continue;
-            }
 
-            let page_data = self.read_page(page_num)?;
-            let header = PageHeader::unpack(&page_data)?;
+    /// 按键序遍历全部键值对的游标，逐页读取，不会一次性载入整个数据库
+    ///
+    /// ```no_run
+    /// # let mut store = wind_kvstore::KVStore::open("app.wkv", None)?;
+    /// for entry in store.cursor()? {
+    ///     let (key, value) = entry?;
+    ///     println!("{:?} => {:?}", key, value);
+    /// }
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn cursor(&self) -> Result<KVCursor<'_>> {
+        KVCursor::new(self, .., false)
+    }
 
-            // 跳过空页面
-            if header.kv_count == 0 {
-                continue;
-            }
 
-            let data_start = PageHeader::SIZE;
-            let data_end = data_start + header.data_len as usize;
-            let data = &page_data[data_start..data_end];
+    /// 遍历区间内键值对的游标，`reverse` 为 true 时按键的逆序遍历
+    pub fn cursor_range<'a, R: RangeBounds<&'a [u8]>>(
+        &self,
+        range: R,
+        reverse: bool,
+    ) -> Result<KVCursor<'_>> {
+        KVCursor::new(self, range, reverse)
+    }
 
-            let mut pos = 0;
-            for i in 0..header.kv_count {
-                if pos >= data.len() {
-                    break;
-                }
 
-                // 解析键长度
-                let klen = data[pos] as usize;
-                pos += 1;
+    /// 按键序返回区间内的键值对，`limit` 为 `None` 时不限数量
+    ///
+    /// ```no_run
+    /// # let mut store = wind_kvstore::KVStore::open("app.wkv", None)?;
+    /// let users = store.scan(b"user:".as_slice()..b"user;".as_slice(), Some(10))?;
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn scan<'a, R: RangeBounds<&'a [u8]>>(
+        &self,
+        range: R,
+        limit: Option<usize>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_internal(range, false, limit)
+    }
 
-                if pos + klen > data.len() {
-                    break;
-                }
 
-                // 解析键
-                let key = &data[pos..pos + klen];
-                pos += klen;
+    /// 与 [`KVStore::scan`] 相同，但按键的逆序返回
+    pub fn scan_rev<'a, R: RangeBounds<&'a [u8]>>(
+        &self,
+        range: R,
+        limit: Option<usize>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_internal(range, true, limit)
+    }
 
-                if pos + 2 > data.len() {
-                    break;
-                }
 
-                // 解析值长度
-                let vlen = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
-                pos += 2;
+    /// 按键序返回所有以 `prefix` 开头的键值对
+    pub fn scan_prefix(
+        &self,
+        prefix: &[u8],
+        limit: Option<usize>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let end = prefix_end(prefix);
+        self.scan_internal(prefix_range(prefix, &end), false, limit)
+    }
 
-                if pos + vlen > data.len() {
-                    break;
-                }
 
-                // 解析值
-                let mut value = data[pos..pos + vlen].to_vec();
-                pos += vlen;
+    /// 与 [`KVStore::scan_prefix`] 相同，但按键的逆序返回
+    pub fn scan_prefix_rev(
+        &self,
+        prefix: &[u8],
+        limit: Option<usize>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let end = prefix_end(prefix);
+        self.scan_internal(prefix_range(prefix, &end), true, limit)
+    }
 
-                // 处理溢出数据（仅对第一个键值对有效）
-                if i == 0 && header.flags & 0x02 != 0 {
-                    let overflow_data = self.read_overflow(header.next_page)?;
-                    value.extend_from_slice(&overflow_data);
-                }
 
-                result.push((key.to_vec(), value));
-            }
-        }
+    fn scan_internal<'a, R: RangeBounds<&'a [u8]>>(
+        &self,
+        range: R,
+        reverse: bool,
+        limit: Option<usize>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        KVCursor::new(self, range, reverse)?
+            .take(limit.unwrap_or(usize::MAX))
+            .collect()
+    }
 
-        Ok(result)
+
+    /// 开始一个事务，提交前的写入只在事务内可见
+    ///
+    /// ```no_run
+    /// # let mut store = wind_kvstore::KVStore::open("app.wkv", None)?;
+    /// let mut txn = store.transaction();
+    /// txn.put(b"balance:alice", b"90")?;
+    /// txn.put(b"balance:bob", b"110")?;
+    /// txn.commit()?;
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn transaction(&mut self) -> Transaction<'_> {
+        Transaction::new(self)
     }
 
 
     pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.check_writable()?;
         self.wal_manager
             .log_operation(WALManager::OP_DELETE, key, None)?;
-        self.delete_internal(key, true)
+        self.delete_internal(key)?;
+        self.finish_write()
+    }
+
+    fn delete_internal(&mut self, key: &[u8]) -> Result<()> {
+        if !self.has_indexes() {
+            return self.delete_entry(key, true);
+        }
+        let old = self.indexed_value(key)?;
+        self.delete_entry(key, true)?;
+        self.update_indexes(key, old.as_deref(), None)
     }
 
-    fn delete_internal(&mut self, key: &[u8], update_index: bool) -> Result<()> {
-        let page_num = match self.key_to_page.get(key) {
-            Some(num) => *num,
+
+    // 删除键所在的条目，free_value 为 false 时保留值的溢出链
+    fn delete_entry(&mut self, key: &[u8], free_value: bool) -> Result<()> {
+        let page_num = match self.index_lookup(key)? {
+            Some(num) => num,
             None => return Ok(()),
         };
+        self.index_remove(key)?;
 
         let page_data = self.read_page(page_num)?;
         let mut header = PageHeader::unpack(&page_data)?;
@@ -663,50 +1917,19 @@ impl KVStore {
         let data_end = data_start + header.data_len as usize;
         let data = &page_data[data_start..data_end];
 
-        let mut pos = 0;
         let mut found = false;
         let mut kv_ranges = Vec::new();
 
-        for i in 0..header.kv_count {
-            let start_pos = pos;
-
-            if pos >= data.len() {
-                break;
-            }
-
-            let klen = data[pos] as usize;
-            pos += 1;
-
-            if pos + klen > data.len() {
-                break;
-            }
-            let current_key = &data[pos..pos + klen];
-            pos += klen;
-
-            if pos + 2 > data.len() {
-                break;
-            }
-            let vlen = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
-            pos += 2;
-
-            if pos + vlen > data.len() {
-                break;
-            }
-
-            let end_pos = pos + vlen;
-
-            if current_key != key {
-                kv_ranges.push((start_pos, end_pos));
+        for entry in parse_entries(data, header.kv_count, self.page_size()) {
+            if !self.entry_matches(data, &entry, key)? {
+                kv_ranges.push((entry.start, entry.end()));
             } else {
                 found = true;
-                if i == 0 && header.flags & 0x02 != 0 {
-                    self.free_overflow(header.next_page)?;
-                    header.flags &= !0x02;
-                    header.next_page = 0;
+                self.free_overflow(entry.key_overflow)?;
+                if free_value {
+                    self.free_overflow(entry.value_overflow)?;
                 }
             }
-
-            pos = end_pos;
         }
 
         if !found {
@@ -721,10 +1944,6 @@ impl KVStore {
         header.kv_count = kv_ranges.len() as u16;
         header.data_len = new_data.len() as u16;
 
-        if update_index {
-            self.build_index()?;
-        }
-
         if header.kv_count == 0 {
             self.free_page(page_num)?;
             return Ok(());
@@ -732,7 +1951,7 @@ impl KVStore {
 
         let mut new_page_data = header.pack().to_vec();
         new_page_data.extend_from_slice(&new_data);
-        new_page_data.resize(PAGE_SIZE, 0);
+        new_page_data.resize(self.page_size(), 0);
 
         self.write_page(page_num, &new_page_data)?;
         Ok(())
@@ -740,73 +1959,74 @@ impl KVStore {
 
 
     pub fn compact(&mut self) -> Result<()> {
+        self.check_writable()?;
         let temp_path = self.path.with_extension("tmp");
-        let mut temp_db = KVStore::create_new_db(
-            &temp_path,
-            Some(&self.header.db_identifier),
-            WALManager::new(&temp_path),
-        )?;
-
-        for page_num in 1..=self.header.total_pages {
-            if self.check_free_page(page_num)? {
-                continue;
+        let temp_options = KVStoreOptions {
+            path: temp_path.clone(),
+            identifier: Some(self.header.db_identifier.clone()),
+            page_size: self.page_size(),
+            ..self.options.clone()
+        };
+        let mut temp_db = KVStore::create_new_db(&temp_path, &temp_options)?;
+        temp_db.header.compression = self.header.compression;
+
+        // 新文件中的键空间沿用原来的编号，逐个复制
+        temp_db.keyspaces = self
+            .keyspaces
+            .iter()
+            .map(|entry| KeyspaceEntry { root: 0, ..entry.clone() })
+            .collect();
+        temp_db.write_keyspaces()?;
+        let active = self.keyspace;
+        let mut copied = Ok(());
+        for id in self.keyspace_ids() {
+            self.keyspace = id;
+            temp_db.keyspace = id;
+            copied = self.copy_entries(&mut temp_db);
+            if copied.is_err() {
+                break;
             }
+        }
+        self.keyspace = active;
+        copied?;
+        // 新文件接着使用原来的 LSN，之后写入的版本号不会与保留下来的重复
+        temp_db.wal_manager.last_lsn = self.wal_manager.last_lsn;
+        temp_db.commit()?;
 
-            let page_data = self.read_page(page_num)?;
-            let header = PageHeader::unpack(&page_data)?;
-            let data_start = PageHeader::SIZE;
-            let data_end = data_start + header.data_len as usize;
-            let data = &page_data[data_start..data_end];
-
-            let mut pos = 0;
-            for _ in 0..header.kv_count {
-                if pos >= data.len() {
-                    break;
-                }
-
-                let klen = data[pos] as usize;
-                pos += 1;
-
-                if pos + klen > data.len() {
-                    break;
-                }
-                let key = &data[pos..pos + klen];
-                pos += klen;
+        // 关闭当前数据库，WAL 中的修改已在新文件中，不能再重放到新文件上
+        self.checkpoint()?;
 
-                if pos + 2 > data.len() {
-                    break;
-                }
-                let vlen = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
-                pos += 2;
+        // 替换文件后直接接管新文件，它的锁一直由 temp_db 持有，其他进程没有机会在替换时打开它
+        std::fs::rename(&temp_path, &self.path)?;
+        temp_db.path = self.path.clone();
+        temp_db.options.path = self.path.clone();
+        temp_db.wal_manager = WALManager::new(&self.path, self.options.durability);
+        temp_db.wal_manager.last_lsn = temp_db.header.applied_lsn;
+        temp_db.select_keyspace(active);
+        // 键空间编号保持不变，索引定义直接沿用，条目已随隐藏键空间复制
+        temp_db.indexes = mem::take(&mut self.indexes);
+        *self = temp_db;
+        Ok(())
+    }
 
-                if pos + vlen > data.len() {
-                    break;
-                }
-                let value = &data[pos..pos + vlen];
-                pos += vlen;
 
-                if let Ok(Some(existing)) = temp_db.get(key) {
-                    temp_db.put(key, &existing)?;
-                } else {
-                    temp_db.put(key, value)?;
-                }
-            }
+    // 把当前键空间的键复制到 target 的当前键空间。
+    // 游标跳过已过期的键，未过期的键保留原来的过期时间和版本号
+    fn copy_entries(&self, target: &mut KVStore) -> Result<()> {
+        for entry in self.cursor()? {
+            let (key, value) = entry?;
+            let meta = self
+                .find_stored_entry(&key)?
+                .map_or_else(EntryMeta::default, |entry| entry.meta());
+            target.put_value(&key, &value, meta)?;
         }
+        Ok(())
+    }
 
-        // 关闭当前数据库
-        self.flush_pages()?;
-        self.update_header()?;
-        self.file.sync_all()?;
-
-        // 替换文件
-        std::fs::rename(&temp_path, &self.path)?;
 
-        *self = KVStore::open_existing_db(
-            &self.path,
-            Some(&self.header.db_identifier),
-            WALManager::new(&self.path),
-        )?;
-        Ok(())
+    /// 页大小（字节），新建时由 [`KVStoreOptions::page_size`] 决定
+    pub fn page_size(&self) -> usize {
+        self.header.page_size as usize
     }
 
 
@@ -816,74 +2036,164 @@ impl KVStore {
 
 
     pub fn set_identifier(&mut self, identifier: &str) -> Result<()> {
+        self.check_writable()?;
         self.header.db_identifier = identifier.to_string();
         self.update_header()
     }
 
 
-    fn build_index(&mut self) -> Result<()> {
-        self.key_to_page.clear();
+    // 升级到当前的条目格式（版本 4 起键长为变长编码，版本 5 起值的溢出链由条目自己引用，
+    // 版本 6 起值字段可带过期时间，版本 7 起条目带版本号，版本 9 起值字段带压缩标志）：
+    // 逐页重写数据页条目，把页级溢出链转为条目引用，丢弃所有键空间的旧索引并按新的节点格式重建。
+    // 旧格式没有索引的文件也走这里。重写后放不下的条目移出原页，最后在所属键空间中重新写入。
+    fn upgrade_entry_format(&mut self) -> Result<()> {
+        let version = self.header.format_version;
+        let page_size = self.page_size();
+        let capacity = page_size - PageHeader::SIZE;
+        let threshold = overflow_threshold(page_size);
+        let limit = key_inline_limit(page_size);
+        let total_pages = self.header.total_pages;
 
-        for page_num in 1..=self.header.total_pages {
-            if self.check_free_page(page_num)? {
+        for page_num in 1..=total_pages {
+            let page_data = self.read_page(page_num)?;
+            let header = PageHeader::unpack(&page_data)?;
+            if header.flags & (PAGE_FLAG_INDEX_LEAF | PAGE_FLAG_INDEX_BRANCH) == 0 {
                 continue;
             }
+            // 版本 4 的节点中长键有自己的溢出链
+            if version >= ENTRY_FORMAT_VERSION {
+                let node = Node::unpack(&page_data)?;
+                let spills = match &node {
+                    Node::Leaf { spills, .. } | Node::Branch { spills, .. } => spills,
+                };
+                for spill in spills {
+                    self.free_overflow(*spill)?;
+                }
+            }
+            self.free_page(page_num)?;
+        }
+        self.header.index_root = 0;
+        for entry in &mut self.keyspaces {
+            entry.root = 0;
+        }
 
+        let mut displaced = Vec::new();
+        for page_num in 1..=total_pages {
             let page_data = self.read_page(page_num)?;
-            let header = PageHeader::unpack(&page_data)?;
-            let data_start = PageHeader::SIZE;
-            let data_end = data_start + header.data_len as usize;
-            let data = &page_data[data_start..data_end];
+            let mut header = PageHeader::unpack(&page_data)?;
+            if !header.is_data_page() {
+                continue;
+            }
 
-            let mut pos = 0;
-            for _ in 0..header.kv_count {
-                if pos >= data.len() {
-                    break;
+            let data = &page_data[PageHeader::SIZE..PageHeader::SIZE + header.data_len as usize];
+            let mut new_data = Vec::new();
+            let mut kept = Vec::new();
+            for entry in parse_entries_as(data, header.kv_count, page_size, version) {
+                let mut key = data[entry.key.clone()].to_vec();
+                if entry.key_overflow != 0 {
+                    key.extend_from_slice(&self.read_overflow(entry.key_overflow)?);
                 }
 
-                let klen = data[pos] as usize;
-                pos += 1;
-
-                if pos + klen > data.len() {
-                    break;
+                // 页级溢出链属于截断到阈值长度的那个值
+                let mut value = data[entry.value.clone()].to_vec();
+                if header.flags & PAGE_FLAG_OVERFLOW != 0 && header.next_page != 0 && value.len() == threshold {
+                    value.extend_from_slice(&self.read_overflow(header.next_page)?);
+                    self.free_overflow(header.next_page)?;
+                    header.next_page = 0;
                 }
-                let key = &data[pos..pos + klen];
-                pos += klen;
 
-                if pos + 2 > data.len() {
-                    break;
+                // 版本 5 起的条目已经引用自己的溢出链，沿用即可
+                let chain = (entry.value_overflow != 0).then_some(StoredValue::Overflow {
+                    len: entry.value_len,
+                    start: entry.value_overflow,
+                });
+                let meta = entry.meta();
+                let size = match &chain {
+                    Some(chain) => encoded_key_len(&key, page_size) + chain.encoded_len(meta),
+                    None => entry_size(&key, &value, meta, page_size),
+                };
+                if new_data.len() + size > capacity {
+                    self.free_overflow(entry.key_overflow)?;
+                    displaced.push((header.keyspace, key, value, chain, meta));
+                    continue;
                 }
-                let vlen = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
-                pos += 2 + vlen;
 
-                self.key_to_page.insert(key.to_vec(), page_num);
+                let key_overflow = if entry.key_overflow != 0 || key.len() <= limit {
+                    entry.key_overflow
+                } else {
+                    self.write_overflow(&key[limit..])?
+                };
+                let stored = match chain {
+                    Some(chain) => chain,
+                    None if value.len() > threshold => StoredValue::Overflow {
+                        len: value.len(),
+                        start: self.write_overflow(&value)?,
+                    },
+                    None => StoredValue::Inline(&value),
+                };
+                encode_entry(&mut new_data, &key, key_overflow, &stored, meta, page_size);
+                kept.push(key);
+            }
+
+            // 没有找到所属条目的页级溢出链无法读取，直接释放
+            if header.flags & PAGE_FLAG_OVERFLOW != 0 {
+                self.free_overflow(header.next_page)?;
+                header.flags &= !PAGE_FLAG_OVERFLOW;
+                header.next_page = 0;
             }
+
+            if kept.is_empty() {
+                self.free_page(page_num)?;
+                continue;
+            }
+
+            header.kv_count = kept.len() as u16;
+            header.data_len = new_data.len() as u16;
+            let mut new_page_data = header.pack().to_vec();
+            new_page_data.extend_from_slice(&new_data);
+            new_page_data.resize(page_size, 0);
+            self.write_page(page_num, &new_page_data)?;
+
+            self.in_keyspace(header.keyspace, |store| {
+                kept.iter().try_for_each(|key| store.index_insert(key, page_num))
+            })?;
         }
 
-        if self.header.total_pages > 0 {
-            self.last_used_page = self.header.total_pages;
+        for (keyspace, key, value, chain, meta) in displaced {
+            self.in_keyspace(keyspace, |store| match chain {
+                Some(chain) => store.put_stored(&key, &chain, meta),
+                None => store.put_value(&key, &value, meta),
+            })?;
         }
 
-        Ok(())
+        // 键空间目录按新格式重写
+        self.header.format_version = COMPRESSION_VERSION;
+        self.write_keyspaces()?;
+        self.sync()
     }
 
 
-    fn check_free_page(&mut self, page_num: u32) -> Result<bool> {
-        let mut current = self.header.free_page_head;
-        while current != 0 {
-            if current == page_num {
-                return Ok(true);
-            }
-            let page_data = self.read_page(current)?;
-            let header = PageHeader::unpack(&page_data)?;
-            current = header.next_page;
+    // 为旧文件的每一页补写校验和
+    fn upgrade_page_checksums(&mut self) -> Result<()> {
+        for page_num in 1..=self.header.total_pages {
+            let page_data = self.read_page(page_num)?;
+            self.write_page(page_num, &page_data)?;
         }
-        Ok(false)
+
+        self.header.format_version = CHECKSUM_VERSION;
+        self.sync()
+    }
+
+
+    // 页缓存加锁后才能访问，读操作因此只需要 &self。
+    // 缓存中只有完整的页，持锁线程 panic 也不会留下半写的内容，中毒时直接沿用
+    fn cache(&self) -> MutexGuard<'_, LRUCache> {
+        self.page_cache.lock().unwrap_or_else(PoisonError::into_inner)
     }
 
 
-    fn read_page(&mut self, page_num: u32) -> Result<Vec<u8>> {
-        if let Some(cached) = self.page_cache.get(page_num) {
+    fn read_page(&self, page_num: u32) -> Result<Vec<u8>> {
+        if let Some(cached) = self.cache().get(page_num) {
             return Ok(cached);
         }
 
@@ -891,14 +2201,21 @@ impl KVStore {
             bail!("Page number out of range");
         }
 
-        let offset = HEADER_SIZE + (page_num - 1) as usize * PAGE_SIZE;
-        if offset + PAGE_SIZE > self.mmap.len() {
+        let page_size = self.page_size();
+        let offset = HEADER_SIZE + (page_num - 1) as usize * page_size;
+        if offset + page_size > self.mmap.len() {
             bail!("Page offset out of range");
         }
 
-        let page_data = self.mmap[offset..offset + PAGE_SIZE].to_vec();
-        self.page_cache.put(page_num, page_data.clone());
-        self.last_used_page = page_num;
+        let range = offset..offset + page_size;
+        let page_data = match &self.pinned {
+            Some(pinned) => pinned.read(page_num, &self.mmap, range),
+            None => self.mmap[range].to_vec(),
+        };
+        if self.header.format_version >= CHECKSUM_VERSION && !verify_page(&page_data) {
+            bail!(KvError::Corruption { page: page_num });
+        }
+        self.cache().put(page_num, page_data.clone());
 
         Ok(page_data)
     }
@@ -909,17 +2226,22 @@ impl KVStore {
             bail!("Page number out of range");
         }
 
-        let offset = HEADER_SIZE + (page_num - 1) as usize * PAGE_SIZE;
-        if offset + PAGE_SIZE > self.mmap.len() {
+        let page_size = self.page_size();
+        let offset = HEADER_SIZE + (page_num - 1) as usize * page_size;
+        if offset + page_size > self.mmap.len() {
             // 需要扩展文件
-            let new_size = (offset + PAGE_SIZE) as u64;
+            let new_size = (offset + page_size) as u64;
             self.file.set_len(new_size)?;
-            self.mmap = unsafe { MmapOptions::new().map_mut(&self.file)? };
+            self.mmap = FileMap::new(&self.file, false)?;
         }
 
-        self.mmap[offset..offset + PAGE_SIZE].copy_from_slice(data);
+        self.pin_for_snapshots(page_num, offset..offset + page_size);
+
+        let mut page_data = data.to_vec();
+        seal_page(&mut page_data);
+        self.mmap[offset..offset + page_size].copy_from_slice(&page_data);
         self.dirty_pages.insert(page_num);
-        self.page_cache.put(page_num, data.to_vec());
+        self.cache().put(page_num, page_data);
 
         Ok(())
     }
@@ -931,10 +2253,10 @@ impl KVStore {
         }
 
         let max_page = *self.dirty_pages.iter().max().unwrap();
-        let required_size = HEADER_SIZE + (max_page as usize) * PAGE_SIZE;
+        let required_size = HEADER_SIZE + (max_page as usize) * self.page_size();
         if required_size > self.mmap.len() {
             self.file.set_len(required_size as u64)?;
-            self.mmap = unsafe { MmapOptions::new().map_mut(&self.file)? };
+            self.mmap = FileMap::new(&self.file, false)?;
         }
 
         self.mmap.flush()?;
@@ -964,9 +2286,10 @@ impl KVStore {
             header.kv_count = 0;
             header.data_len = 0;
             header.next_page = 0;
+            header.keyspace = DEFAULT_KEYSPACE_ID;
 
             let mut new_page_data = header.pack().to_vec();
-            new_page_data.resize(PAGE_SIZE, 0);
+            new_page_data.resize(self.page_size(), 0);
             self.write_page(page_num, &new_page_data)?;
 
             return Ok(page_num);
@@ -980,10 +2303,11 @@ impl KVStore {
             kv_count: 0,
             data_len: 0,
             next_page: 0,
+            keyspace: DEFAULT_KEYSPACE_ID,
         };
 
         let mut page_data = header.pack().to_vec();
-        page_data.resize(PAGE_SIZE, 0);
+        page_data.resize(self.page_size(), 0);
 
         self.write_page(page_num, &page_data)?;
         Ok(page_num)
@@ -992,14 +2316,15 @@ impl KVStore {
 
     fn free_page(&mut self, page_num: u32) -> Result<()> {
         let header = PageHeader {
-            flags: 0x04,
+            flags: PAGE_FLAG_FREE,
             kv_count: 0,
             data_len: 0,
             next_page: self.header.free_page_head,
+            keyspace: DEFAULT_KEYSPACE_ID,
         };
 
         let mut page_data = header.pack().to_vec();
-        page_data.resize(PAGE_SIZE, 0);
+        page_data.resize(self.page_size(), 0);
         self.write_page(page_num, &page_data)?;
 
         self.header.free_page_head = page_num;
@@ -1007,7 +2332,7 @@ impl KVStore {
     }
 
 
-    fn read_overflow(&mut self, start_page: u32) -> Result<Vec<u8>> {
+    fn read_overflow(&self, start_page: u32) -> Result<Vec<u8>> {
         let mut data = Vec::new();
         let mut current = start_page;
 
@@ -1024,53 +2349,37 @@ impl KVStore {
 
 
     fn write_overflow(&mut self, data: &[u8]) -> Result<u32> {
-        let mut start_page = 0;
-        let mut current_page = 0;
-        let mut data = data;
-
-        while !data.is_empty() {
-            let page_num = self.allocate_page()?;
-            if start_page == 0 {
-                start_page = page_num;
-            }
-
-            let chunk_size = std::cmp::min(PAGE_SIZE - PageHeader::SIZE, data.len());
-            let chunk = &data[..chunk_size];
-            data = &data[chunk_size..];
-
-            let next_page = if data.is_empty() { 0 } else { self.allocate_page()? };
-
-            let header = PageHeader {
-                flags: 0x02,
-                kv_count: 0,
-                data_len: chunk_size as u16,
-                next_page,
-            };
+        let chunk_size = self.page_size() - PageHeader::SIZE;
+        let chunks: Vec<&[u8]> = data.chunks(chunk_size).collect();
 
-            let mut page_data = header.pack().to_vec();
-            page_data.extend_from_slice(chunk);
-            page_data.resize(PAGE_SIZE, 0);
+        // 先分配整条链，每页写入时就能填好 next_page
+        let mut pages = Vec::with_capacity(chunks.len());
+        for _ in 0..chunks.len() {
+            pages.push(self.allocate_page()?);
+        }
 
-            self.write_page(page_num, &page_data)?;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let next_page = pages.get(i + 1).copied().unwrap_or(0);
+            self.write_overflow_page(pages[i], chunk, next_page)?;
+        }
 
-            // 更新前一页的next_page指针
-            if current_page != 0 {
-                let prev_page_data = self.read_page(current_page)?;
-                let mut prev_header = PageHeader::unpack(&prev_page_data)?;
-                prev_header.next_page = page_num;
+        Ok(pages.first().copied().unwrap_or(0))
+    }
 
-                let mut new_prev_data = prev_header.pack().to_vec();
-                new_prev_data.extend_from_slice(
-                    &prev_page_data[PageHeader::SIZE..PageHeader::SIZE + prev_header.data_len as usize],
-                );
-                new_prev_data.resize(PAGE_SIZE, 0);
-                self.write_page(current_page, &new_prev_data)?;
-            }
 
-            current_page = page_num;
-        }
+    fn write_overflow_page(&mut self, page_num: u32, chunk: &[u8], next_page: u32) -> Result<()> {
+        let header = PageHeader {
+            flags: PAGE_FLAG_OVERFLOW,
+            kv_count: 0,
+            data_len: chunk.len() as u16,
+            next_page,
+            keyspace: DEFAULT_KEYSPACE_ID,
+        };
 
-        Ok(start_page)
+        let mut page_data = header.pack().to_vec();
+        page_data.extend_from_slice(chunk);
+        page_data.resize(self.page_size(), 0);
+        self.write_page(page_num, &page_data)
     }
 
 
@@ -1078,8 +2387,8 @@ impl KVStore {
         &mut self,
         page_num: u32,
         key: &[u8],
-        value: &[u8],
-        update_index: bool,
+        value: &StoredValue,
+        meta: EntryMeta,
     ) -> Result<()> {
         let page_data = self.read_page(page_num)?;
         let mut header = PageHeader::unpack(&page_data)?;
@@ -1087,29 +2396,18 @@ impl KVStore {
         let data_end = data_start + header.data_len as usize;
         let data = &page_data[data_start..data_end];
 
-        // 处理溢出
-        let overflow_start = if value.len() > OVERFLOW_THRESHOLD {
-            let start = self.write_overflow(&value[OVERFLOW_THRESHOLD..])?;
-            header.flags |= 0x02;
-            start
+        // 长键的其余部分写入溢出链
+        let page_size = self.page_size();
+        let limit = key_inline_limit(page_size);
+        let key_overflow = if key.len() > limit {
+            self.write_overflow(&key[limit..])?
         } else {
-            header.flags &= !0x02;
             0
         };
-        header.next_page = overflow_start;
-
-        let value_to_store = if value.len() > OVERFLOW_THRESHOLD {
-            &value[..OVERFLOW_THRESHOLD]
-        } else {
-            value
-        };
 
         // 创建KV条目
         let mut kv_data = Vec::new();
-        kv_data.push(key.len() as u8);
-        kv_data.extend_from_slice(key);
-        kv_data.write_u16::<LittleEndian>(value_to_store.len() as u16)?;
-        kv_data.extend_from_slice(value_to_store);
+        encode_entry(&mut kv_data, key, key_overflow, value, meta, page_size);
 
         // 创建新数据
         let mut new_data = data.to_vec();
@@ -1117,98 +2415,65 @@ impl KVStore {
 
         header.kv_count += 1;
         header.data_len = new_data.len() as u16;
+        // 只会选用当前键空间的页或空页
+        header.keyspace = self.keyspace;
 
         // 更新页数据
         let mut new_page_data = header.pack().to_vec();
         new_page_data.extend_from_slice(&new_data);
-        new_page_data.resize(PAGE_SIZE, 0);
+        new_page_data.resize(page_size, 0);
 
         self.write_page(page_num, &new_page_data)?;
 
-        if update_index {
-            self.key_to_page.insert(key.to_vec(), page_num);
-        }
+        self.index_insert(key, page_num)?;
         self.last_used_page = page_num;
 
         Ok(())
     }
 
 
-    fn update_existing(&mut self, key: &[u8], new_value: &[u8]) -> Result<()> {
-        let page_num = *self
-            .key_to_page
-            .get(key)
-            .ok_or_else(|| anyhow!("Key not found"))?;
-
+    // 在键所在的数据页中原地替换值，页中找不到该键时返回 false。
+    // 新值放不下时把条目移出该页并返回 false，由调用方重新插入
+    fn update_existing(
+        &mut self,
+        page_num: u32,
+        key: &[u8],
+        new_value: &StoredValue,
+        meta: EntryMeta,
+    ) -> Result<bool> {
         let page_data = self.read_page(page_num)?;
         let mut header = PageHeader::unpack(&page_data)?;
         let data_start = PageHeader::SIZE;
         let data_end = data_start + header.data_len as usize;
         let data = &page_data[data_start..data_end];
 
-        let mut pos = 0;
-        let mut found = false;
-        let mut value_start = 0;
-        let mut value_len = 0;
-        for _ in 0..header.kv_count {
-            let klen = data[pos] as usize;
-            pos += 1;
-
-            if pos + klen > data.len() {
-                break;
-            }
-            let current_key = &data[pos..pos + klen];
-            pos += klen;
-
-            if pos + 2 > data.len() {
-                break;
-            }
-            value_len = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
-            pos += 2;
-
-            if current_key == key {
-                value_start = pos;
-                found = true;
-                break;
-            }
+        let entry = match self.find_entry(data, header.kv_count, key)? {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
 
-            pos += value_len;
-        }
+        // 重放 WAL 或只修改过期时间时，新值可能就是条目已引用的那条溢出链，不能释放
+        let same_chain = matches!(new_value, StoredValue::Overflow { start, .. } if *start == entry.value_overflow);
 
-        if !found {
-            bail!(KvError::KeyNotFound(String::from_utf8_lossy(key).to_string()));
+        let page_size = self.page_size();
+        let new_len = data.len() - (entry.end() - entry.value_field) + new_value.encoded_len(meta);
+        if new_len > page_size - PageHeader::SIZE {
+            self.delete_entry(key, !same_chain)?;
+            return Ok(false);
         }
 
-        // 释放现有溢出页（如果有）
-        if header.flags & 0x02 != 0 {
-            self.free_overflow(header.next_page)?;
-            header.flags &= !0x02;
+        // 释放旧值的溢出链
+        if !same_chain {
+            self.free_overflow(entry.value_overflow)?;
         }
 
-        // 处理新溢出
-        let overflow_start = if new_value.len() > OVERFLOW_THRESHOLD {
-            let start = self.write_overflow(&new_value[OVERFLOW_THRESHOLD..])?;
-            header.flags |= 0x02;
-            start
-        } else {
-            0
-        };
-        header.next_page = overflow_start;
-
-        let value_to_store = if new_value.len() > OVERFLOW_THRESHOLD {
-            &new_value[..OVERFLOW_THRESHOLD]
-        } else {
-            new_value
-        };
-
         // 创建新值数据
         let mut new_value_data = Vec::new();
-        new_value_data.write_u16::<LittleEndian>(value_to_store.len() as u16)?;
-        new_value_data.extend_from_slice(value_to_store);
+        new_value.encode(&mut new_value_data, meta);
 
         // 替换旧值
-        let before = &data[..value_start - 2];
-        let after = &data[value_start + value_len..];
+        let before = &data[..entry.value_field];
+        let after = &data[entry.end()..];
         let new_data = [before, &new_value_data, after].concat();
 
         header.data_len = new_data.len() as u16;
@@ -1216,10 +2481,10 @@ impl KVStore {
         // 更新页数据
         let mut new_page_data = header.pack().to_vec();
         new_page_data.extend_from_slice(&new_data);
-        new_page_data.resize(PAGE_SIZE, 0);
+        new_page_data.resize(page_size, 0);
 
         self.write_page(page_num, &new_page_data)?;
-        Ok(())
+        Ok(true)
     }
 
 
@@ -1236,16 +2501,60 @@ impl KVStore {
     }
 
 
+    /// 把所有修改写入数据库文件并清空 WAL，等同于 [`KVStore::checkpoint`]
     pub fn commit(&mut self) -> Result<()> {
+        self.checkpoint()
+    }
+
+
+    /// 执行检查点：脏页与文件头落盘并 fsync 后清空 WAL
+    ///
+    /// 写入过程中会按 [`KVStoreOptions::wal_checkpoint_size`] 和
+    /// [`KVStoreOptions::wal_checkpoint_interval`] 自动触发，长时间空闲前可手动调用。
+    /// 文件头同时记录已落盘的最后一条 WAL 记录的 LSN，打开时只重放它之后的记录，
+    /// 遇到残缺或校验和不匹配的记录即停止。
+    pub fn checkpoint(&mut self) -> Result<()> {
+        if self.is_read_only() {
+            return Ok(());
+        }
+        self.header.applied_lsn = self.wal_manager.last_lsn;
+        self.sync()?;
+        self.wal_manager.truncate()
+    }
+
+
+    /// 当前 WAL 的大小与检查点统计
+    pub fn wal_stats(&self) -> WalStats {
+        self.wal_manager.stats()
+    }
+
+
+    // 每次写入结束时调用。文件头写入 mmap 但不落盘，进程崩溃后文件头与页面仍然一致。
+    // applied_lsn 只在检查点把页面落盘后才提高：mmap 中的页面随时可能先于或晚于文件头写回磁盘，
+    // 断电后只能假定上次检查点之后的记录都没有生效，重放时在当前页面上把它们全部重新执行一遍
+    fn finish_write(&mut self) -> Result<()> {
+        self.mmap[..DBHeader::SIZE].copy_from_slice(&self.header.pack());
+        if self.wal_manager.needs_checkpoint(&self.options) {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+
+    // 只把页面落盘，不动 WAL；升级和恢复时 WAL 尚未重放，不能清空
+    fn sync(&mut self) -> Result<()> {
         self.flush_pages()?;
         self.update_header()?;
         self.mmap.flush()?;
         self.file.sync_all()?;
-        self.page_cache.clear();
+        self.cache().clear();
         Ok(())
     }
 
 
+    /// 提交所有修改并释放文件锁
+    ///
+    /// 不调用 `close` 直接丢弃 store 时不提交，上次检查点之后的写入在下次打开时从 WAL 恢复。
     pub fn close(mut self) -> Result<()> {
         self.commit()?;
         Ok(())
@@ -1253,6 +2562,28 @@ impl KVStore {
 }
 
 
+// 大于所有以 prefix 开头的键的最小键；prefix 全为 0xFF（或为空）时没有上界
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+
+fn prefix_range<'a>(prefix: &'a [u8], end: &'a Option<Vec<u8>>) -> (Bound<&'a [u8]>, Bound<&'a [u8]>) {
+    let end = match end {
+        Some(end) => Bound::Excluded(end.as_slice()),
+        None => Bound::Unbounded,
+    };
+    (Bound::Included(prefix), end)
+}
+
+
 #[allow(unused_doc_comments)]
 fn current_time_millis() -> u64 {
     let data = SystemTime::now()
@@ -1265,3 +2596,395 @@ fn current_time_millis() -> u64 {
     // println!("[TIME] {}", data);
     data
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn applied_lsn_on_disk(path: &Path) -> u64 {
+        let data = std::fs::read(path).unwrap();
+        DBHeader::unpack(&data[..DBHeader::SIZE]).unwrap().applied_lsn
+    }
+
+    // 检查点之后写入 a=3、b、c 并删除 b，然后不提交就丢弃 store，模拟进程崩溃。
+    // 返回检查点时的文件内容
+    fn crash_after_checkpoint(path: &Path) -> Result<Vec<u8>> {
+        let mut store = KVStore::open(path, None)?;
+        store.put(b"a", b"1")?;
+        store.put(b"b", b"2")?;
+        store.checkpoint()?;
+        let checkpointed = applied_lsn_on_disk(path);
+        let snapshot = std::fs::read(path)?;
+
+        store.put(b"a", b"3")?;
+        store.delete(b"b")?;
+        store.put(b"c", b"4")?;
+        // 页面落盘之前，文件头中的 applied_lsn 不能超过检查点
+        assert_eq!(applied_lsn_on_disk(path), checkpointed);
+        drop(store);
+        Ok(snapshot)
+    }
+
+    fn assert_recovered(path: &Path) -> Result<()> {
+        let store = KVStore::open(path, None)?;
+        assert_eq!(store.get(b"a")?, Some(b"3".to_vec()));
+        assert_eq!(store.get(b"b")?, None);
+        assert_eq!(store.get(b"c")?, Some(b"4".to_vec()));
+        assert!(store.verify()?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn replays_wal_over_stale_header() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("stale.wkv");
+        let snapshot = crash_after_checkpoint(&path)?;
+        // 检查点之后修改的页面和文件头都没有写回磁盘，只剩 WAL
+        std::fs::write(&path, snapshot)?;
+        assert_recovered(&path)
+    }
+
+    #[test]
+    fn replays_wal_over_applied_pages() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("applied.wkv");
+        // 页面已经写回，文件头仍停在检查点，重放的记录再执行一次结果不变
+        crash_after_checkpoint(&path)?;
+        assert_recovered(&path)
+    }
+
+    #[test]
+    fn checkpoints_when_wal_grows() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("checkpoint.wkv");
+        let mut store = KVStoreOptions::new(&path)
+            .wal_checkpoint_size(4096)
+            .wal_checkpoint_interval(Duration::ZERO)
+            .open()?;
+        for i in 0..200 {
+            store.put(format!("key-{}", i).as_bytes(), &[b'v'; 64])?;
+        }
+        let stats = store.wal_stats();
+        assert!(stats.checkpoints > 0);
+        assert!(stats.size < 4096);
+        // 检查点之后的写入只在 WAL 中，崩溃后重放
+        drop(store);
+        let store = KVStore::open(&path, None)?;
+        assert_eq!(store.scan(.., None)?.len(), 200);
+        Ok(())
+    }
+
+    // 检查点之后写入 a、b、c 后崩溃，只留下检查点时的文件和 WAL，返回 WAL 的路径和内容
+    fn crash_with_wal(path: &Path) -> Result<(PathBuf, Vec<u8>)> {
+        let mut store = KVStore::open(path, None)?;
+        store.checkpoint()?;
+        let snapshot = std::fs::read(path)?;
+        store.put(b"a", b"1")?;
+        store.put(b"b", b"2")?;
+        store.put(b"c", b"3")?;
+        drop(store);
+        std::fs::write(path, snapshot)?;
+        let wal_path = path.with_extension(WAL_FILE_EXT);
+        let wal = std::fs::read(&wal_path)?;
+        Ok((wal_path, wal))
+    }
+
+    // 第 n 帧（从 0 开始）在 WAL 中的起始位置
+    fn frame_offset(wal: &[u8], n: usize) -> usize {
+        let mut pos = WAL_HEADER_SIZE;
+        for _ in 0..n {
+            let len = u32::from_le_bytes(wal[pos + 4..pos + 8].try_into().unwrap()) as usize;
+            pos += WAL_FRAME_HEADER_SIZE + len;
+        }
+        pos
+    }
+
+    fn recovered_keys(path: &Path) -> Result<Vec<Vec<u8>>> {
+        let store = KVStore::open(path, None)?;
+        Ok(store.scan(.., None)?.into_iter().map(|(key, _)| key).collect())
+    }
+
+    #[test]
+    fn recovery_stops_at_torn_tail() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("torn.wkv");
+        let (wal_path, wal) = crash_with_wal(&path)?;
+        // 最后一帧只写了一半
+        std::fs::write(&wal_path, &wal[..wal.len() - 3])?;
+        assert_eq!(recovered_keys(&path)?, vec![b"a".to_vec(), b"b".to_vec()]);
+        // 恢复后 WAL 已清空，新的写入不受残缺记录影响
+        assert!(!wal_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn recovery_stops_at_checksum_mismatch() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("crc.wkv");
+        let (wal_path, mut wal) = crash_with_wal(&path)?;
+        // 第二帧的记录内容损坏，它和之后的帧都不再重放
+        let pos = frame_offset(&wal, 1) + WAL_FRAME_HEADER_SIZE;
+        wal[pos] ^= 0xFF;
+        std::fs::write(&wal_path, &wal)?;
+        assert_eq!(recovered_keys(&path)?, vec![b"a".to_vec()]);
+        Ok(())
+    }
+
+    #[test]
+    fn recovery_skips_records_up_to_applied_lsn() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("lsn.wkv");
+        let (_, wal) = crash_with_wal(&path)?;
+        // 文件头记录第一帧已经应用，重放时跳过它
+        let lsn = u64::from_le_bytes(wal[WAL_HEADER_SIZE + 8..WAL_HEADER_SIZE + 16].try_into()?);
+        let mut data = std::fs::read(&path)?;
+        let mut header = DBHeader::unpack(&data[..DBHeader::SIZE])?;
+        header.applied_lsn = lsn;
+        data[..DBHeader::SIZE].copy_from_slice(&header.pack());
+        std::fs::write(&path, data)?;
+        assert_eq!(recovered_keys(&path)?, vec![b"b".to_vec(), b"c".to_vec()]);
+
+        // 之后写入的版本号接在重放的最后一条记录之后
+        let mut store = KVStore::open(&path, None)?;
+        let last = store.version(b"c")?.unwrap();
+        store.put(b"d", b"4")?;
+        assert!(store.version(b"d")?.unwrap() > last);
+        Ok(())
+    }
+
+    fn large_value(seed: u8, len: usize) -> Vec<u8> {
+        (0..len).map(|i| seed.wrapping_add((i % 251) as u8)).collect()
+    }
+
+    #[test]
+    fn large_values_keep_separate_overflow_chains() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("overflow.wkv");
+        let mut store = KVStoreOptions::new(&path).page_size(1024).open()?;
+        let values = [
+            (b"a".to_vec(), large_value(1, 3000)),
+            (b"b".to_vec(), large_value(2, 5000)),
+            (b"c".to_vec(), large_value(3, 2000)),
+            // 超过 data_len 能表示的 64KB
+            (b"d".to_vec(), large_value(4, 100 * 1024)),
+        ];
+        for (key, value) in &values {
+            store.put(key, value)?;
+        }
+        // 引用溢出链的条目很短，共用同一个数据页
+        assert_eq!(store.index_lookup(b"a")?, store.index_lookup(b"d")?);
+        for (key, value) in &values {
+            assert_eq!(store.get(key)?.as_ref(), Some(value));
+        }
+
+        store.put(b"b", b"small")?;
+        store.delete(b"c")?;
+        store.close()?;
+
+        let store = KVStore::open(&path, None)?;
+        assert_eq!(store.get(b"a")?, Some(values[0].1.clone()));
+        assert_eq!(store.get(b"b")?, Some(b"small".to_vec()));
+        assert_eq!(store.get(b"c")?, None);
+        assert_eq!(store.get(b"d")?, Some(values[3].1.clone()));
+
+        // 覆盖和删除释放的溢出链没有泄漏，也没有被两个条目共用
+        let report = store.verify()?;
+        assert!(
+            !report.issues.iter().any(|issue| matches!(
+                issue,
+                VerifyIssue::OrphanPage { .. } | VerifyIssue::MultiplyReferenced { .. }
+            )),
+            "{}",
+            report
+        );
+        assert!(report.is_ok(), "{}", report);
+        Ok(())
+    }
+
+    fn corruption_page(err: &anyhow::Error) -> Option<u32> {
+        match err.downcast_ref::<KvError>() {
+            Some(KvError::Corruption { page }) => Some(*page),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn detects_corrupt_data_page() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("page-crc.wkv");
+        let mut store = KVStore::open(&path, None)?;
+        store.put(b"a", b"1")?;
+        let page = store.index_lookup(b"a")?.unwrap();
+        store.close()?;
+
+        let mut data = std::fs::read(&path)?;
+        data[HEADER_SIZE + (page - 1) as usize * DEFAULT_PAGE_SIZE + PageHeader::SIZE] ^= 0x01;
+        std::fs::write(&path, data)?;
+
+        let store = KVStore::open(&path, None)?;
+        let err = store.get(b"a").unwrap_err();
+        assert_eq!(corruption_page(&err), Some(page));
+        Ok(())
+    }
+
+    #[test]
+    fn detects_corrupt_header() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("header-crc.wkv");
+        let mut store = KVStore::open(&path, Some("checked"))?;
+        store.put(b"a", b"1")?;
+        store.close()?;
+
+        // 改动标识中的一个字节
+        let mut data = std::fs::read(&path)?;
+        data[4] ^= 0x01;
+        std::fs::write(&path, data)?;
+
+        let err = match KVStore::open(&path, None) {
+            Ok(_) => panic!("corrupt header was accepted"),
+            Err(e) => e,
+        };
+        assert_eq!(corruption_page(&err), Some(0));
+        Ok(())
+    }
+
+    fn is_locked<T>(result: Result<T>) -> bool {
+        match result {
+            Ok(_) => false,
+            Err(e) => matches!(e.downcast_ref::<KvError>(), Some(KvError::Locked(_))),
+        }
+    }
+
+    #[test]
+    fn second_writer_is_locked_out() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("locked.wkv");
+        let store = KVStore::open(&path, None)?;
+        assert!(is_locked(KVStore::open(&path, None)));
+        // 等待超时后同样失败
+        let waiting = KVStoreOptions::new(&path).lock_timeout(Duration::from_millis(50));
+        assert!(is_locked(waiting.open()));
+
+        // 关闭后锁随文件一起释放
+        store.close()?;
+        let store = KVStore::open(&path, None)?;
+        drop(store);
+        KVStore::open(&path, None)?;
+        Ok(())
+    }
+
+    fn is_read_only_error<T>(result: Result<T>) -> bool {
+        match result {
+            Ok(_) => false,
+            Err(e) => matches!(e.downcast_ref::<KvError>(), Some(KvError::ReadOnly)),
+        }
+    }
+
+    #[test]
+    fn read_only_handles_share_the_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("read-only.wkv");
+        let mut store = KVStore::open(&path, None)?;
+        store.put(b"a", b"1")?;
+        store.close()?;
+        let before = std::fs::read(&path)?;
+
+        let mut first = KVStore::open_read_only(&path)?;
+        let second = KVStoreOptions::new(&path).read_only(true).open()?;
+        assert_eq!(first.get(b"a")?, Some(b"1".to_vec()));
+        assert_eq!(second.get(b"a")?, Some(b"1".to_vec()));
+        // 只读句柄存在时不能以读写方式打开
+        assert!(is_locked(KVStore::open(&path, None)));
+
+        assert!(is_read_only_error(first.put(b"b", b"2")));
+        assert!(is_read_only_error(first.delete(b"a")));
+        assert!(is_read_only_error(first.incr_by(b"n", 1)));
+        assert!(is_read_only_error(first.put_stream(b"blob", &[0u8; 4096][..])));
+        first.commit()?;
+        drop(first);
+        drop(second);
+
+        // 写入被拒绝时没有碰文件，也没有留下 WAL
+        assert_eq!(std::fs::read(&path)?, before);
+        assert!(!path.with_extension(WAL_FILE_EXT).exists());
+        let mut store = KVStore::open(&path, None)?;
+        assert_eq!(store.get(b"b")?, None);
+        store.put(b"b", b"2")?;
+        Ok(())
+    }
+
+    fn scan_keys(entries: Vec<(Vec<u8>, Vec<u8>)>) -> Vec<Vec<u8>> {
+        entries.into_iter().map(|(key, _)| key).collect()
+    }
+
+    fn numbered(range: impl Iterator<Item = usize>) -> Vec<Vec<u8>> {
+        range.map(|i| format!("key{:03}", i).into_bytes()).collect()
+    }
+
+    #[test]
+    fn scan_honours_bounds_limit_and_order() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = KVStore::open(dir.path().join("scan.wkv"), None)?;
+        // 足够多的键，区间跨越多个叶子
+        for key in numbered(0..300) {
+            store.put(&key, &[b'v'; 40])?;
+        }
+        let (lo, hi) = (b"key100".as_slice(), b"key200".as_slice());
+
+        assert_eq!(scan_keys(store.scan(lo..hi, None)?), numbered(100..200));
+        assert_eq!(scan_keys(store.scan(lo..=hi, None)?), numbered(100..201));
+        assert_eq!(
+            scan_keys(store.scan((Bound::Excluded(lo), Bound::Included(hi)), None)?),
+            numbered(101..201)
+        );
+        assert_eq!(scan_keys(store.scan(..lo, None)?), numbered(0..100));
+        assert_eq!(scan_keys(store.scan(hi.., Some(5))?), numbered(200..205));
+        // 区间端点不必是已有的键
+        assert_eq!(scan_keys(store.scan(b"key0995".as_slice()..b"key1015".as_slice(), None)?), numbered(100..102));
+        assert!(store.scan(hi..lo, None)?.is_empty());
+        assert!(store.scan(.., Some(0))?.is_empty());
+
+        let mut reversed = numbered(100..201);
+        reversed.reverse();
+        assert_eq!(scan_keys(store.scan_rev(lo..=hi, None)?), reversed);
+        assert_eq!(scan_keys(store.scan_rev(lo..=hi, Some(3))?), reversed[..3].to_vec());
+        assert_eq!(scan_keys(store.scan_rev(.., Some(1))?), numbered(299..300));
+
+        assert_eq!(scan_keys(store.scan_prefix(b"key1", None)?), numbered(100..200));
+        assert_eq!(scan_keys(store.scan_prefix(b"key1", Some(2))?), numbered(100..102));
+        assert_eq!(scan_keys(store.scan_prefix_rev(b"key29", Some(2))?), vec![b"key299".to_vec(), b"key298".to_vec()]);
+        assert!(store.scan_prefix(b"nope", None)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn scan_prefix_ending_in_ff() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = KVStore::open(dir.path().join("prefix.wkv"), None)?;
+        let keys: [&[u8]; 7] = [
+            &[0x01, 0xFE],
+            &[0x01, 0xFF],
+            &[0x01, 0xFF, 0x00],
+            &[0x01, 0xFF, 0xFF],
+            &[0x02],
+            &[0xFF],
+            &[0xFF, 0xFF, 0x01],
+        ];
+        for key in keys {
+            store.put(key, b"v")?;
+        }
+
+        let expected: Vec<Vec<u8>> = keys[1..4].iter().map(|key| key.to_vec()).collect();
+        assert_eq!(scan_keys(store.scan_prefix(&[0x01, 0xFF], None)?), expected);
+        let mut reversed = expected.clone();
+        reversed.reverse();
+        assert_eq!(scan_keys(store.scan_prefix_rev(&[0x01, 0xFF], None)?), reversed);
+        // 全为 0xFF 的前缀没有上界
+        let expected: Vec<Vec<u8>> = keys[5..].iter().map(|key| key.to_vec()).collect();
+        assert_eq!(scan_keys(store.scan_prefix(&[0xFF], None)?), expected);
+        assert_eq!(scan_keys(store.scan_prefix(&[0xFF, 0xFF], None)?), vec![vec![0xFF, 0xFF, 0x01]]);
+        assert_eq!(store.scan_prefix(&[], None)?.len(), keys.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,437 @@
+// src/kvstore/blob.rs
+// 大值的流式读写：put_stream 边读边写溢出页，get_reader 按需读取溢出链，
+// 两者都不会把整个值放进内存。
+//
+// 流式写入的值在 WAL 中只记录溢出链的位置。这类记录只有在它引用的页已经提交、
+// 且之后没有再提交过别的修改时才能安全重放，所以 put_stream 在记录前提交溢出链，
+// 应用后再次提交（提交即检查点，会清空 WAL）。
+// 溢出链提交时在文件头中记为待引用，写入 WAL 记录的进程若在两次提交之间崩溃，
+// 重放时由该记录认领；没有记录认领的链在打开时释放，不会泄漏。
+use super::{overflow_threshold, KVStore, PageHeader, StoredValue, WALManager};
+use anyhow::{anyhow, bail, Result};
+use log::warn;
+use std::io::{self, Read, Seek, SeekFrom};
+
+
+// 从 reader 读满 size 字节，遇到结尾时返回较短的块
+fn read_chunk<R: Read>(reader: &mut R, size: usize) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(size);
+    reader.take(size as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+
+enum BlobSource {
+    Inline(Vec<u8>),
+    // 已经遍历到的溢出页，next 为链上尚未遍历的下一页
+    Chain { pages: Vec<u32>, next: u32 },
+}
+
+
+/// 按需读取一个值的 [`Read`] + [`Seek`] 实现，由 [`KVStore::get_reader`] 创建
+///
+/// 溢出链上的页在读到时才载入（经过页缓存），向后跳转需要沿链遍历中间的页头。
+pub struct BlobReader<'a> {
+    store: &'a KVStore,
+    source: BlobSource,
+    len: u64,
+    pos: u64,
+}
+
+
+impl BlobReader<'_> {
+    /// 值的总长度
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+
+    // 第 index 个溢出页的页号，必要时沿链继续遍历
+    fn chain_page(&mut self, index: usize) -> Result<u32> {
+        let BlobSource::Chain { pages, next } = &mut self.source else {
+            unreachable!("inline blob has no pages");
+        };
+        while pages.len() <= index {
+            if *next == 0 {
+                bail!("Overflow chain ends before the value length");
+            }
+            let page_data = self.store.read_page(*next)?;
+            pages.push(*next);
+            *next = PageHeader::unpack(&page_data)?.next_page;
+        }
+        Ok(pages[index])
+    }
+
+
+    fn read_chain(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let chunk_size = (self.store.page_size() - PageHeader::SIZE) as u64;
+        let index = (self.pos / chunk_size) as usize;
+        let offset = (self.pos % chunk_size) as usize;
+
+        let page_num = self.chain_page(index)?;
+        let page_data = self.store.read_page(page_num)?;
+        let header = PageHeader::unpack(&page_data)?;
+        let chunk = &page_data[PageHeader::SIZE..PageHeader::SIZE + header.data_len as usize];
+
+        let available = chunk.len().saturating_sub(offset);
+        let n = buf.len().min(available).min((self.len - self.pos) as usize);
+        if n == 0 {
+            bail!("Overflow page {} is shorter than expected", page_num);
+        }
+        buf[..n].copy_from_slice(&chunk[offset..offset + n]);
+        Ok(n)
+    }
+}
+
+
+impl Read for BlobReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+
+        let n = match &self.source {
+            BlobSource::Inline(value) => {
+                let rest = &value[self.pos as usize..];
+                let n = buf.len().min(rest.len());
+                buf[..n].copy_from_slice(&rest[..n]);
+                n
+            }
+            BlobSource::Chain { .. } => self.read_chain(buf).map_err(io::Error::other)?,
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+
+impl Seek for BlobReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        match target {
+            Some(target) => {
+                self.pos = target;
+                Ok(target)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+
+impl KVStore {
+    /// 从 `reader` 流式写入一个值，返回写入的字节数
+    ///
+    /// 超过溢出阈值的值边读边写入溢出页，内存中只保留一页数据，
+    /// WAL 中也只记录溢出链的位置而不是原始数据。
+    /// 为保证崩溃恢复时引用的页有效，记录 WAL 前先提交溢出链，写入完成后再提交一次。
+    /// `reader` 出错时已写入的页会被释放，原有的值保持不变。
+    ///
+    /// 流式写入的值不压缩，也不带过期时间（原有的过期时间被清除）；
+    /// 二级索引与 [`KVStore::put`] 一样随写入更新。
+    ///
+    /// ```no_run
+    /// # let mut store = wind_kvstore::KVStore::open("app.wkv", None)?;
+    /// let file = std::fs::File::open("artifact.bin")?;
+    /// store.put_stream(b"artifact", file)?;
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn put_stream<R: Read>(&mut self, key: &[u8], mut reader: R) -> Result<u64> {
+        self.check_writable()?;
+        self.check_key_size(key)?;
+
+        // 不超过阈值的值按普通写入处理
+        let threshold = overflow_threshold(self.page_size());
+        let head = read_chunk(&mut reader, threshold + 1)?;
+        if head.len() <= threshold {
+            self.put(key, &head)?;
+            return Ok(head.len() as u64);
+        }
+
+        let (len, start) = self.write_overflow_stream(head.as_slice().chain(reader))?;
+        self.header.pending_blob = start;
+        self.commit()?;
+
+        let blob_ref = WALManager::encode_blob_ref(len, start);
+        self.wal_manager
+            .log_operation(WALManager::OP_PUT_BLOB, key, Some(&blob_ref))?;
+        self.put_stored(
+            key,
+            &StoredValue::Overflow {
+                len: len as usize,
+                start,
+            },
+            self.write_meta(None),
+        )?;
+
+        self.header.pending_blob = 0;
+        self.commit()?;
+        Ok(len)
+    }
+
+
+    // 重放的 WAL 记录引用了待引用的溢出链，该链不再需要回收
+    pub(super) fn claim_pending_blob(&mut self, value: &StoredValue) {
+        if matches!(value, StoredValue::Overflow { start, .. } if *start == self.header.pending_blob) {
+            self.header.pending_blob = 0;
+        }
+    }
+
+
+    // 打开时释放已提交但没有 WAL 记录引用的溢出链（put_stream 在两次提交之间崩溃）
+    pub(super) fn reclaim_pending_blob(&mut self) -> Result<()> {
+        let start = self.header.pending_blob;
+        if start == 0 {
+            return Ok(());
+        }
+        warn!("Freeing overflow chain at page {} left by an interrupted put_stream", start);
+        self.free_overflow(start)?;
+        self.header.pending_blob = 0;
+        self.checkpoint()
+    }
+
+
+    // 把 reader 中的全部数据写成一条溢出链，返回 (总长度, 起始页)
+    fn write_overflow_stream<R: Read>(&mut self, mut reader: R) -> Result<(u64, u32)> {
+        let chunk_size = self.page_size() - PageHeader::SIZE;
+        let mut chunk = read_chunk(&mut reader, chunk_size)?;
+        let start = self.allocate_page()?;
+        let mut page_num = start;
+        let mut len = 0u64;
+
+        loop {
+            // 先读下一块才能知道当前页是否为链尾
+            let next_chunk = match read_chunk(&mut reader, chunk_size) {
+                Ok(next_chunk) => next_chunk,
+                Err(e) => {
+                    self.write_overflow_page(page_num, &chunk, 0)?;
+                    self.free_overflow(start)?;
+                    return Err(e.into());
+                }
+            };
+            let next_page = if next_chunk.is_empty() {
+                0
+            } else {
+                self.allocate_page()?
+            };
+
+            self.write_overflow_page(page_num, &chunk, next_page)?;
+            len += chunk.len() as u64;
+            if next_page == 0 {
+                return Ok((len, start));
+            }
+            page_num = next_page;
+            chunk = next_chunk;
+        }
+    }
+
+
+    /// 以 [`Read`] + [`Seek`] 的方式读取一个值，不存在时返回 `None`
+    ///
+    /// 适合读取用 [`KVStore::put_stream`] 写入的大值，读取器存活期间 store 不能写入。
+    pub fn get_reader(&self, key: &[u8]) -> Result<Option<BlobReader<'_>>> {
+        if key.is_empty() {
+            return Err(anyhow!("Empty key is reserved for internal use"));
+        }
+
+        let page_num = match self.index_lookup(key)? {
+            Some(num) => num,
+            None => return Ok(None),
+        };
+
+        let page_data = self.read_page(page_num)?;
+        let header = PageHeader::unpack(&page_data)?;
+        let data = &page_data[PageHeader::SIZE..PageHeader::SIZE + header.data_len as usize];
+        let entry = match self.find_entry(data, header.kv_count, key)? {
+            Some(entry) if !self.is_expired(&entry) => entry,
+            _ => return Ok(None),
+        };
+
+        // 压缩的值只能整体解压，解压后从内存中读取
+        let (source, len) = if entry.compressed {
+            let value = self.entry_value(data, &entry)?;
+            let len = value.len() as u64;
+            (BlobSource::Inline(value), len)
+        } else if entry.value_overflow != 0 {
+            let source = BlobSource::Chain {
+                pages: Vec::new(),
+                next: entry.value_overflow,
+            };
+            (source, entry.value_len as u64)
+        } else {
+            (BlobSource::Inline(data[entry.value.clone()].to_vec()), entry.value_len as u64)
+        };
+        Ok(Some(BlobReader {
+            store: self,
+            source,
+            len,
+            pos: 0,
+        }))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::Cursor;
+
+
+    fn blob(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+
+    // 先返回 `data`，之后每次读取都出错
+    struct FailingReader {
+        data: Cursor<Vec<u8>>,
+    }
+
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.data.read(buf)? {
+                0 => Err(io::Error::other("source went away")),
+                n => Ok(n),
+            }
+        }
+    }
+
+
+    #[test]
+    fn reader_seeks_within_streamed_value() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = KVStore::open(dir.path().join("blob.wkv"), None)?;
+        let value = blob(10_000);
+        assert_eq!(store.put_stream(b"blob", value.as_slice())?, 10_000);
+        assert_eq!(store.get(b"blob")?, Some(value.clone()));
+
+        let mut reader = store.get_reader(b"blob")?.unwrap();
+        assert_eq!(reader.len(), 10_000);
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all)?;
+        assert_eq!(all, value);
+
+        // 跨越溢出页边界读取
+        let mut buf = [0u8; 2000];
+        reader.seek(SeekFrom::Start(3000))?;
+        reader.read_exact(&mut buf)?;
+        assert_eq!(&buf[..], &value[3000..5000]);
+
+        // 向回跳转需要重新定位到前面的页
+        reader.seek(SeekFrom::Current(-4500))?;
+        reader.read_exact(&mut buf[..100])?;
+        assert_eq!(&buf[..100], &value[500..600]);
+
+        reader.seek(SeekFrom::End(-10))?;
+        reader.read_exact(&mut buf[..10])?;
+        assert_eq!(&buf[..10], &value[9990..]);
+
+        // 越过结尾后读不到数据
+        reader.seek(SeekFrom::Start(20_000))?;
+        assert_eq!(reader.read(&mut buf)?, 0);
+        assert!(reader.read_exact(&mut buf[..1]).is_err());
+        assert!(reader.seek(SeekFrom::Current(-30_000)).is_err());
+        Ok(())
+    }
+
+
+    #[test]
+    fn failing_reader_keeps_old_value() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("failing.wkv");
+        let mut store = KVStore::open(&path, None)?;
+        let old = blob(5000);
+        store.put_stream(b"blob", old.as_slice())?;
+
+        let reader = FailingReader {
+            data: Cursor::new(blob(3000)),
+        };
+        assert!(store.put_stream(b"blob", reader).is_err());
+        assert_eq!(store.get(b"blob")?, Some(old.clone()));
+        // 写了一半的溢出链已经释放
+        assert!(store.verify()?.is_ok());
+        store.close()?;
+
+        let store = KVStore::open(&path, None)?;
+        assert_eq!(store.get(b"blob")?, Some(old));
+        Ok(())
+    }
+
+
+    #[test]
+    fn interrupted_stream_frees_its_chain() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("interrupted.wkv");
+        let mut store = KVStore::open(&path, None)?;
+        store.put(b"a", b"1")?;
+
+        // put_stream 提交溢出链后、写入 WAL 记录前崩溃
+        let (_, start) = store.write_overflow_stream(blob(5000).as_slice())?;
+        store.header.pending_blob = start;
+        store.commit()?;
+        drop(store);
+        // 待引用的链不算泄漏
+        assert!(KVStore::verify_file(&path)?.is_ok());
+
+        let store = KVStore::open(&path, None)?;
+        assert_eq!(store.header.pending_blob, 0);
+        assert_eq!(store.get(b"a")?, Some(b"1".to_vec()));
+        let report = store.verify()?;
+        assert!(report.is_ok(), "{}", report);
+        assert!(report.free_pages > 0);
+        Ok(())
+    }
+
+
+    #[test]
+    fn replayed_stream_claims_its_chain() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("replayed.wkv");
+        let mut store = KVStore::open(&path, None)?;
+        let value = blob(5000);
+
+        // WAL 记录已写入，第二次提交前崩溃
+        let (len, start) = store.write_overflow_stream(value.as_slice())?;
+        store.header.pending_blob = start;
+        store.commit()?;
+        store
+            .wal_manager
+            .log_operation(WALManager::OP_PUT_BLOB, b"blob", Some(&WALManager::encode_blob_ref(len, start)))?;
+        drop(store);
+
+        let store = KVStore::open(&path, None)?;
+        assert_eq!(store.header.pending_blob, 0);
+        assert_eq!(store.get(b"blob")?, Some(value));
+        let report = store.verify()?;
+        assert!(report.is_ok(), "{}", report);
+        Ok(())
+    }
+
+
+    #[test]
+    fn streamed_value_is_indexed() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = KVStore::open(dir.path().join("indexed.wkv"), None)?;
+        store.create_index("by_kind", "/kind")?;
+        let document = json!({"kind": "report", "body": "x".repeat(5000)}).to_string();
+        store.put_stream(b"doc", document.as_bytes())?;
+
+        let found = store.find_by_index("by_kind", &json!("report"))?;
+        assert_eq!(found, vec![(b"doc".to_vec(), document.into_bytes())]);
+        Ok(())
+    }
+}
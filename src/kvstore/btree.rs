@@ -0,0 +1,613 @@
+// src/kvstore/btree.rs
+// 持久化 B+ 树索引：键 -> 数据页号
+//
+// 节点各占一页，页头 flags 区分叶子节点与内部节点，kv_count 为节点中的键数。
+// 叶子节点数据：[key | page u32]*
+// 内部节点数据：child0 u32 | [key | child u32]*
+// 键字段与数据页条目相同（见 encode_key），长键的其余部分放在节点自己的溢出链中，
+// 同一个键在数据页和各个节点中的溢出链互相独立。
+// 格式版本 4 之前键字段为 klen u16 | key，只在校验旧文件时解析。
+// 删除不做节点合并，空叶子保留在树中，COMPACT 时整体重建。
+use super::{
+    decode_key, encode_key, encoded_key_len, key_inline_limit, KVStore, PageHeader,
+    PAGE_FLAG_INDEX_BRANCH, PAGE_FLAG_INDEX_LEAF,
+};
+use anyhow::{anyhow, bail, Result};
+use byteorder::{LittleEndian, WriteBytesExt};
+
+
+#[derive(Debug)]
+pub(super) enum Node {
+    // 叶子节点：keys[i] 所在的数据页为 pages[i]
+    Leaf {
+        keys: Vec<Vec<u8>>,
+        pages: Vec<u32>,
+        // keys[i] 的溢出链起始页，0 表示键未溢出或溢出链尚未写入
+        spills: Vec<u32>,
+    },
+    // 内部节点：children[i] 中的键 < keys[i] <= children[i + 1] 中的键
+    Branch {
+        keys: Vec<Vec<u8>>,
+        children: Vec<u32>,
+        spills: Vec<u32>,
+    },
+}
+
+
+// 分裂出的右兄弟
+struct Split {
+    separator: Vec<u8>,
+    spill: u32,
+    page: u32,
+}
+
+
+impl Node {
+    fn capacity(page_size: usize) -> usize {
+        page_size - PageHeader::SIZE
+    }
+
+    fn entry_size(key: &[u8], page_size: usize) -> usize {
+        encoded_key_len(key, page_size) + 4
+    }
+
+    fn keys(&self) -> &[Vec<u8>] {
+        match self {
+            Node::Leaf { keys, .. } | Node::Branch { keys, .. } => keys,
+        }
+    }
+
+    fn spills_mut(&mut self) -> &mut Vec<u32> {
+        match self {
+            Node::Leaf { spills, .. } | Node::Branch { spills, .. } => spills,
+        }
+    }
+
+    fn data_len(&self, page_size: usize) -> usize {
+        let keys: usize = self.keys().iter().map(|k| Self::entry_size(k, page_size)).sum();
+        match self {
+            Node::Leaf { .. } => keys,
+            Node::Branch { .. } => 4 + keys,
+        }
+    }
+
+    // 打包前需已为每个溢出的键写好溢出链
+    fn pack(&self, page_size: usize, keyspace: u16) -> Result<Vec<u8>> {
+        let data_len = self.data_len(page_size);
+        if data_len > Self::capacity(page_size) {
+            bail!("Index node exceeds page capacity");
+        }
+
+        let (flags, keys) = match self {
+            Node::Leaf { keys, .. } => (PAGE_FLAG_INDEX_LEAF, keys),
+            Node::Branch { keys, .. } => (PAGE_FLAG_INDEX_BRANCH, keys),
+        };
+        let header = PageHeader {
+            flags,
+            kv_count: keys.len() as u16,
+            data_len: data_len as u16,
+            next_page: 0,
+            keyspace,
+        };
+
+        let mut buf = header.pack().to_vec();
+        match self {
+            Node::Leaf { keys, pages, spills } => {
+                for ((key, page), spill) in keys.iter().zip(pages).zip(spills) {
+                    encode_key(&mut buf, key, *spill, page_size);
+                    buf.write_u32::<LittleEndian>(*page)?;
+                }
+            }
+            Node::Branch { keys, children, spills } => {
+                buf.write_u32::<LittleEndian>(children[0])?;
+                for ((key, child), spill) in keys.iter().zip(&children[1..]).zip(spills) {
+                    encode_key(&mut buf, key, *spill, page_size);
+                    buf.write_u32::<LittleEndian>(*child)?;
+                }
+            }
+        }
+        buf.resize(page_size, 0);
+        Ok(buf)
+    }
+
+    // 解析节点页，溢出的键只含页内前缀，需按 spills 读取溢出链补全
+    pub(super) fn unpack(page_data: &[u8]) -> Result<Self> {
+        let page_size = page_data.len();
+        Self::decode(page_data, |data, pos| {
+            let (_, key, spill) = decode_key(data, pos, page_size)?;
+            Some((data[key].to_vec(), spill))
+        })
+    }
+
+    // 解析格式版本 4 之前的节点页
+    pub(super) fn unpack_legacy(page_data: &[u8]) -> Result<Self> {
+        Self::decode(page_data, |data, pos| {
+            let klen = u16::from_le_bytes(data.get(*pos..*pos + 2)?.try_into().ok()?) as usize;
+            let key = data.get(*pos + 2..*pos + 2 + klen)?.to_vec();
+            *pos += 2 + klen;
+            Some((key, 0))
+        })
+    }
+
+    fn decode<F>(page_data: &[u8], read_key: F) -> Result<Self>
+    where
+        F: Fn(&[u8], &mut usize) -> Option<(Vec<u8>, u32)>,
+    {
+        let header = PageHeader::unpack(page_data)?;
+        let data_end = PageHeader::SIZE + header.data_len as usize;
+        if data_end > page_data.len() {
+            bail!("Index node data out of range");
+        }
+        let data = &page_data[PageHeader::SIZE..data_end];
+        let mut pos = 0;
+
+        let read_u32 = |pos: &mut usize| -> Result<u32> {
+            let bytes = data
+                .get(*pos..*pos + 4)
+                .ok_or_else(|| anyhow!("Index node truncated"))?;
+            *pos += 4;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+        let read_entry = |pos: &mut usize| -> Result<(Vec<u8>, u32, u32)> {
+            let (key, spill) =
+                read_key(data, pos).ok_or_else(|| anyhow!("Index node truncated"))?;
+            Ok((key, spill, read_u32(pos)?))
+        };
+
+        let count = header.kv_count as usize;
+        let mut keys = Vec::with_capacity(count);
+        let mut spills = Vec::with_capacity(count);
+        if header.flags & PAGE_FLAG_INDEX_LEAF != 0 {
+            let mut pages = Vec::with_capacity(count);
+            for _ in 0..count {
+                let (key, spill, page) = read_entry(&mut pos)?;
+                keys.push(key);
+                spills.push(spill);
+                pages.push(page);
+            }
+            Ok(Node::Leaf { keys, pages, spills })
+        } else if header.flags & PAGE_FLAG_INDEX_BRANCH != 0 {
+            let mut children = Vec::with_capacity(count + 1);
+            children.push(read_u32(&mut pos)?);
+            for _ in 0..count {
+                let (key, spill, child) = read_entry(&mut pos)?;
+                keys.push(key);
+                spills.push(spill);
+                children.push(child);
+            }
+            Ok(Node::Branch { keys, children, spills })
+        } else {
+            bail!("Page is not an index node");
+        }
+    }
+}
+
+
+// 按字节数寻找分裂点，保证两侧都至少保留一个键
+fn split_point(keys: &[Vec<u8>], page_size: usize) -> usize {
+    let total: usize = keys.iter().map(|k| Node::entry_size(k, page_size)).sum();
+    let mut acc = 0;
+    for (i, key) in keys.iter().enumerate() {
+        acc += Node::entry_size(key, page_size);
+        if acc * 2 >= total {
+            return (i + 1).clamp(1, keys.len() - 1);
+        }
+    }
+    keys.len() / 2
+}
+
+
+impl KVStore {
+    // 分配一个空的叶子节点作为根
+    fn index_create_root(&mut self) -> Result<u32> {
+        let page_num = self.allocate_page()?;
+        self.write_node(
+            page_num,
+            &mut Node::Leaf {
+                keys: Vec::new(),
+                pages: Vec::new(),
+                spills: Vec::new(),
+            },
+        )?;
+        Ok(page_num)
+    }
+
+
+    // 读取节点并补全溢出的键
+    pub(super) fn read_node(&self, page_num: u32) -> Result<Node> {
+        let page_data = self.read_page(page_num)?;
+        let mut node = Node::unpack(&page_data)?;
+
+        let spills = node.spills_mut().clone();
+        let keys = match &mut node {
+            Node::Leaf { keys, .. } | Node::Branch { keys, .. } => keys,
+        };
+        for (key, spill) in keys.iter_mut().zip(spills) {
+            if spill != 0 {
+                key.extend_from_slice(&self.read_overflow(spill)?);
+            }
+        }
+        Ok(node)
+    }
+
+
+    // 为尚未写入溢出链的长键写入溢出链，然后写入节点页
+    fn write_node(&mut self, page_num: u32, node: &mut Node) -> Result<()> {
+        let limit = key_inline_limit(self.page_size());
+        for i in 0..node.keys().len() {
+            if node.keys()[i].len() > limit && node.spills_mut()[i] == 0 {
+                let spill = self.write_overflow(&node.keys()[i][limit..])?;
+                node.spills_mut()[i] = spill;
+            }
+        }
+
+        let page_data = node.pack(self.page_size(), self.keyspace)?;
+        self.write_page(page_num, &page_data)
+    }
+
+
+    pub(super) fn index_lookup(&self, key: &[u8]) -> Result<Option<u32>> {
+        self.index_lookup_at(self.keyspace, key)
+    }
+
+
+    // 在键空间 id 的索引中查找，不切换当前键空间
+    pub(super) fn index_lookup_at(&self, id: u16, key: &[u8]) -> Result<Option<u32>> {
+        let mut current = self.tree_root(id);
+        if current == 0 {
+            return Ok(None);
+        }
+
+        loop {
+            match self.read_node(current)? {
+                Node::Branch { keys, children, .. } => {
+                    let idx = keys.partition_point(|k| k.as_slice() <= key);
+                    current = children[idx];
+                }
+                Node::Leaf { keys, pages, .. } => {
+                    return Ok(keys
+                        .binary_search_by(|k| k.as_slice().cmp(key))
+                        .ok()
+                        .map(|idx| pages[idx]));
+                }
+            }
+        }
+    }
+
+
+    // 插入或更新键所在的数据页
+    pub(super) fn index_insert(&mut self, key: &[u8], page: u32) -> Result<()> {
+        if self.index_root() == 0 {
+            let root = self.index_create_root()?;
+            self.set_index_root(root)?;
+        }
+
+        let root = self.index_root();
+        if let Some(split) = self.index_insert_into(root, key, page)? {
+            // 根节点分裂，树高加一
+            let new_root = self.allocate_page()?;
+            self.write_node(
+                new_root,
+                &mut Node::Branch {
+                    keys: vec![split.separator],
+                    children: vec![root, split.page],
+                    spills: vec![split.spill],
+                },
+            )?;
+            self.set_index_root(new_root)?;
+        }
+        Ok(())
+    }
+
+
+    fn index_insert_into(&mut self, node_page: u32, key: &[u8], page: u32) -> Result<Option<Split>> {
+        let mut node = self.read_node(node_page)?;
+
+        match &mut node {
+            Node::Leaf { keys, pages, spills } => {
+                match keys.binary_search_by(|k| k.as_slice().cmp(key)) {
+                    Ok(idx) => {
+                        if pages[idx] == page {
+                            return Ok(None);
+                        }
+                        pages[idx] = page;
+                    }
+                    Err(idx) => {
+                        keys.insert(idx, key.to_vec());
+                        pages.insert(idx, page);
+                        spills.insert(idx, 0);
+                    }
+                }
+            }
+            Node::Branch { keys, children, spills } => {
+                let idx = keys.partition_point(|k| k.as_slice() <= key);
+                match self.index_insert_into(children[idx], key, page)? {
+                    Some(split) => {
+                        keys.insert(idx, split.separator);
+                        children.insert(idx + 1, split.page);
+                        spills.insert(idx, split.spill);
+                    }
+                    None => return Ok(None),
+                }
+            }
+        }
+
+        let page_size = self.page_size();
+        if node.data_len(page_size) <= Node::capacity(page_size) {
+            self.write_node(node_page, &mut node)?;
+            return Ok(None);
+        }
+
+        // 节点溢出，分裂为两页
+        let (mut left, mut right, separator, spill) = match node {
+            Node::Leaf {
+                mut keys,
+                mut pages,
+                mut spills,
+            } => {
+                let at = split_point(&keys, page_size);
+                let right_keys = keys.split_off(at);
+                let right_pages = pages.split_off(at);
+                let right_spills = spills.split_off(at);
+                // 叶子保留自己的键，分隔键是一份副本，溢出链另行写入
+                let separator = right_keys[0].clone();
+                (
+                    Node::Leaf { keys, pages, spills },
+                    Node::Leaf {
+                        keys: right_keys,
+                        pages: right_pages,
+                        spills: right_spills,
+                    },
+                    separator,
+                    0,
+                )
+            }
+            Node::Branch {
+                mut keys,
+                mut children,
+                mut spills,
+            } => {
+                let at = split_point(&keys, page_size).min(keys.len() - 1);
+                let mut right_keys = keys.split_off(at);
+                let right_children = children.split_off(at + 1);
+                let mut right_spills = spills.split_off(at);
+                // 分隔键连同溢出链一起移到父节点
+                let separator = right_keys.remove(0);
+                let spill = right_spills.remove(0);
+                (
+                    Node::Branch { keys, children, spills },
+                    Node::Branch {
+                        keys: right_keys,
+                        children: right_children,
+                        spills: right_spills,
+                    },
+                    separator,
+                    spill,
+                )
+            }
+        };
+
+        let right_page = self.allocate_page()?;
+        self.write_node(node_page, &mut left)?;
+        self.write_node(right_page, &mut right)?;
+        Ok(Some(Split {
+            separator,
+            spill,
+            page: right_page,
+        }))
+    }
+
+
+    pub(super) fn index_remove(&mut self, key: &[u8]) -> Result<bool> {
+        let mut current = self.index_root();
+        if current == 0 {
+            return Ok(false);
+        }
+
+        loop {
+            match self.read_node(current)? {
+                Node::Branch { keys, children, .. } => {
+                    let idx = keys.partition_point(|k| k.as_slice() <= key);
+                    current = children[idx];
+                }
+                Node::Leaf {
+                    mut keys,
+                    mut pages,
+                    mut spills,
+                } => {
+                    let idx = match keys.binary_search_by(|k| k.as_slice().cmp(key)) {
+                        Ok(idx) => idx,
+                        Err(_) => return Ok(false),
+                    };
+                    keys.remove(idx);
+                    pages.remove(idx);
+                    let spill = spills.remove(idx);
+                    self.write_node(current, &mut Node::Leaf { keys, pages, spills })?;
+                    self.free_overflow(spill)?;
+                    return Ok(true);
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::KvError;
+    use super::*;
+    use crate::options::KVStoreOptions;
+
+    // 40 字节的键，1KB 页的节点只能放下二十多个，很快分裂出多层
+    fn key(i: usize) -> Vec<u8> {
+        format!("{:05}-{}", i, "k".repeat(34)).into_bytes()
+    }
+
+    fn value(i: usize) -> Vec<u8> {
+        format!("value-{}", i).into_bytes()
+    }
+
+    // 树的高度，只有一个叶子时为 1
+    fn height(store: &KVStore) -> Result<usize> {
+        let mut height = 1;
+        let mut current = store.index_root();
+        while let Node::Branch { children, .. } = store.read_node(current)? {
+            height += 1;
+            current = children[0];
+        }
+        Ok(height)
+    }
+
+    fn open(dir: &tempfile::TempDir) -> Result<KVStore> {
+        KVStore::open(dir.path().join("btree.wkv"), None)
+    }
+
+    fn assert_scan(store: &KVStore, expected: &[usize]) -> Result<()> {
+        let keys: Vec<Vec<u8>> = store.scan(.., None)?.into_iter().map(|(k, _)| k).collect();
+        let expected: Vec<Vec<u8>> = expected.iter().map(|&i| key(i)).collect();
+        assert_eq!(keys, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn splits_into_multiple_levels() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = open(&dir)?;
+        // 先写奇数再写偶数，分裂既发生在节点末尾也发生在中间
+        let order: Vec<usize> = (1..3000).step_by(2).chain((0..3000).step_by(2)).collect();
+        for &i in &order {
+            store.put(&key(i), &value(i))?;
+        }
+        assert!(height(&store)? >= 3);
+        store.close()?;
+
+        let store = open(&dir)?;
+        for i in 0..3000 {
+            assert_eq!(store.get(&key(i))?, Some(value(i)), "key {}", i);
+        }
+        assert_eq!(store.get(&key(3000))?, None);
+        assert_scan(&store, &(0..3000).collect::<Vec<_>>())?;
+        assert!(store.verify()?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn removes_across_leaves() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = open(&dir)?;
+        for i in 0..2000 {
+            store.put(&key(i), &value(i))?;
+        }
+        // 500..1500 整段删除会清空许多相邻的叶子，其余的键隔一个删一个
+        let removed = |i: usize| (500..1500).contains(&i) || i % 2 == 1;
+        for i in (0..2000).filter(|&i| removed(i)) {
+            store.delete(&key(i))?;
+        }
+        let kept: Vec<usize> = (0..2000).filter(|&i| !removed(i)).collect();
+        for i in 0..2000 {
+            let expected = (!removed(i)).then(|| value(i));
+            assert_eq!(store.get(&key(i))?, expected, "key {}", i);
+        }
+        assert_scan(&store, &kept)?;
+        assert!(store.verify()?.is_ok());
+
+        // 空叶子保留在树中，重新写入的键落回原来的位置
+        for i in 700..800 {
+            store.put(&key(i), &value(i))?;
+        }
+        let mut expected = kept.clone();
+        expected.extend(700..800);
+        expected.sort_unstable();
+        assert_scan(&store, &expected)?;
+
+        // 全部删除后树为空
+        for &i in &expected {
+            store.delete(&key(i))?;
+        }
+        assert!(store.scan(.., None)?.is_empty());
+        assert!(store.verify()?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn long_keys_spill_from_nodes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = open(&dir)?;
+        // 键只在超出页内前缀的部分不同，比较和分裂都要读取节点的溢出链
+        let long_key = |i: usize| format!("{}{:05}", "p".repeat(200), i).into_bytes();
+        assert!(long_key(0).len() > key_inline_limit(store.page_size()));
+        for i in 0..300 {
+            store.put(&long_key(i), &value(i))?;
+        }
+        assert!(height(&store)? >= 2);
+        for i in (0..300).step_by(3) {
+            store.delete(&long_key(i))?;
+        }
+        for i in 0..300 {
+            let expected = (i % 3 != 0).then(|| value(i));
+            assert_eq!(store.get(&long_key(i))?, expected, "key {}", i);
+        }
+        assert!(store.verify()?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn long_keys_at_leaf_level() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = open(&dir)?;
+        let long = |tail: &str| format!("{}{}", "p".repeat(3000 - tail.len()), tail).into_bytes();
+        let mut keys = vec![
+            long("a"),
+            long("b"),
+            // 在页内前缀中就已经不同的长键
+            format!("{}o{}", "p".repeat(50), "x".repeat(2949)).into_bytes(),
+            b"p".to_vec(),
+            "p".repeat(100).into_bytes(),
+            b"q".to_vec(),
+        ];
+        assert!(keys[0].len() > key_inline_limit(store.page_size()));
+        for (i, key) in keys.iter().enumerate() {
+            store.put(key, &value(i))?;
+        }
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(store.get(key)?, Some(value(i)));
+        }
+        assert_eq!(store.get(&long("c"))?, None);
+
+        store.put(&long("a"), b"overwritten")?;
+        store.delete(&long("b"))?;
+        keys.remove(1);
+        keys.sort();
+        let scanned: Vec<Vec<u8>> = store.scan(.., None)?.into_iter().map(|(k, _)| k).collect();
+        assert_eq!(scanned, keys);
+        let prefixed = store.scan_prefix("p".repeat(2999).as_bytes(), None)?;
+        assert_eq!(prefixed, vec![(long("a"), b"overwritten".to_vec())]);
+        store.close()?;
+
+        let store = open(&dir)?;
+        assert_eq!(store.get(&long("a"))?, Some(b"overwritten".to_vec()));
+        assert_eq!(store.get(&long("b"))?, None);
+        assert_eq!(store.get(b"q")?, Some(value(5)));
+        let scanned: Vec<Vec<u8>> = store.scan(.., None)?.into_iter().map(|(k, _)| k).collect();
+        assert_eq!(scanned, keys);
+        assert!(store.verify()?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_keys_above_max_key_size() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = KVStoreOptions::new(dir.path().join("limit.wkv"))
+            .max_key_size(1000)
+            .open()?;
+        store.put(&[b'k'; 1000], b"fits")?;
+        let err = store.put(&[b'k'; 1001], b"too long").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<KvError>(),
+            Some(KvError::KeyTooLarge { .. })
+        ));
+        assert_eq!(store.get(&[b'k'; 1000])?, Some(b"fits".to_vec()));
+        assert_eq!(store.scan(.., None)?.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+// src/kvstore/compression.rs
+// 透明的值压缩：写入时按当前键空间的设置压缩达到阈值的值，读取、扫描时自动解压。
+//
+// 压缩过的条目在值字段中带压缩标志，保存的内容为 算法 u8 | varint(原始长度) | 压缩数据，
+// 条目记录的值长是压缩后的长度，放入页内还是溢出链也按压缩后的长度决定。压缩后没有变小的值按原样保存。
+// 数据库的默认设置记在文件头，命名键空间可以在目录中单独设置，没有设置的沿用数据库的默认设置。
+// 修改设置只影响之后写入的值，compact 时所有值按新设置重写。流式写入的值不压缩。
+use super::keyspace::DEFAULT_KEYSPACE_ID;
+use super::{parse_entries, read_varint, write_varint, KVStore, PageHeader, WALManager};
+use anyhow::{anyhow, bail, Result};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+
+/// 未指定时只压缩不小于该字节数的值
+const DEFAULT_COMPRESSION_THRESHOLD: u32 = 128;
+const ZSTD_LEVEL: i32 = 3;
+
+
+/// 值压缩算法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    #[default]
+    None,
+    /// 速度优先
+    Lz4,
+    /// 压缩率优先
+    Zstd,
+}
+
+
+impl Codec {
+    // 文件头、键空间目录和压缩数据的第一个字节中使用的编号
+    pub(super) fn id(self) -> u8 {
+        match self {
+            Codec::None => 0,
+            Codec::Lz4 => 1,
+            Codec::Zstd => 2,
+        }
+    }
+
+    pub(super) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Codec::None),
+            1 => Some(Codec::Lz4),
+            2 => Some(Codec::Zstd),
+            _ => None,
+        }
+    }
+
+    // 压缩数据最多能还原成多少倍的长度：LZ4 每个字节最多展开 255 字节，
+    // zstd 的 RLE 块用 4 个字节表示最多 128KB。解压前按它检查从磁盘读到的原始长度
+    fn max_ratio(self) -> usize {
+        match self {
+            Codec::None => 1,
+            Codec::Lz4 => 255,
+            Codec::Zstd => 32 * 1024,
+        }
+    }
+}
+
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Codec::None => "none",
+            Codec::Lz4 => "lz4",
+            Codec::Zstd => "zstd",
+        };
+        f.write_str(name)
+    }
+}
+
+
+impl FromStr for Codec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Codec::None),
+            "lz4" => Ok(Codec::Lz4),
+            "zstd" => Ok(Codec::Zstd),
+            _ => bail!("Unknown compression codec {:?}, expected none, lz4 or zstd", s),
+        }
+    }
+}
+
+
+/// 值压缩设置，见 [`KVStore::set_compression`]
+///
+/// ```
+/// use wind_kvstore::{Codec, Compression};
+///
+/// let compression = Compression::zstd().threshold(1024);
+/// assert_eq!(compression.codec, Codec::Zstd);
+/// assert_eq!(compression.to_string(), "zstd (threshold 1024 bytes)");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub codec: Codec,
+    /// 只压缩不小于该字节数的值
+    pub threshold: u32,
+}
+
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+
+impl Compression {
+    /// 不压缩
+    pub const NONE: Self = Self {
+        codec: Codec::None,
+        threshold: DEFAULT_COMPRESSION_THRESHOLD,
+    };
+
+    pub fn lz4() -> Self {
+        Self {
+            codec: Codec::Lz4,
+            ..Self::NONE
+        }
+    }
+
+    pub fn zstd() -> Self {
+        Self {
+            codec: Codec::Zstd,
+            ..Self::NONE
+        }
+    }
+
+    /// 修改压缩阈值，默认 128 字节
+    pub fn threshold(mut self, bytes: u32) -> Self {
+        self.threshold = bytes;
+        self
+    }
+
+    // WAL 与键空间目录中的格式：算法 u8 | 阈值 u32
+    pub(super) fn encode(&self) -> [u8; 5] {
+        let mut buf = [0u8; 5];
+        buf[0] = self.codec.id();
+        buf[1..].copy_from_slice(&self.threshold.to_le_bytes());
+        buf
+    }
+
+    pub(super) fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() != 5 {
+            bail!("Malformed compression setting");
+        }
+        let codec = Codec::from_id(data[0]).ok_or_else(|| anyhow!("Unknown compression codec {}", data[0]))?;
+        Ok(Self {
+            codec,
+            threshold: u32::from_le_bytes(data[1..].try_into()?),
+        })
+    }
+}
+
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.codec {
+            Codec::None => write!(f, "none"),
+            codec => write!(f, "{} (threshold {} bytes)", codec, self.threshold),
+        }
+    }
+}
+
+
+// 按设置压缩值，不需要压缩或压缩后没有变小时返回 None
+fn compress(value: &[u8], compression: Compression) -> Option<Vec<u8>> {
+    if compression.codec == Codec::None || value.len() < compression.threshold as usize {
+        return None;
+    }
+    let mut buf = vec![compression.codec.id()];
+    write_varint(&mut buf, value.len() as u64);
+    match compression.codec {
+        Codec::None => return None,
+        Codec::Lz4 => buf.extend_from_slice(&lz4_flex::block::compress(value)),
+        Codec::Zstd => buf.extend_from_slice(&zstd::bulk::compress(value, ZSTD_LEVEL).ok()?),
+    }
+    (buf.len() < value.len()).then_some(buf)
+}
+
+
+// 解压条目中保存的压缩值
+pub(super) fn decompress(stored: &[u8]) -> Result<Vec<u8>> {
+    let (codec, len, pos) = compressed_header(stored).ok_or_else(|| anyhow!("Malformed compressed value"))?;
+    if len > (stored.len() - pos).saturating_mul(codec.max_ratio()) {
+        bail!("Malformed compressed value: original length {} is too large for {} compressed bytes", len, stored.len() - pos);
+    }
+    let value = match codec {
+        Codec::Lz4 => lz4_flex::block::decompress(&stored[pos..], len)?,
+        Codec::Zstd => zstd::bulk::decompress(&stored[pos..], len)?,
+        Codec::None => bail!("Malformed compressed value"),
+    };
+    if value.len() != len {
+        bail!("Compressed value has length {}, expected {}", value.len(), len);
+    }
+    Ok(value)
+}
+
+
+// 压缩值的算法、原始长度和压缩数据的起始位置
+fn compressed_header(stored: &[u8]) -> Option<(Codec, usize, usize)> {
+    let codec = Codec::from_id(*stored.first()?)?;
+    let mut pos = 1;
+    let len = usize::try_from(read_varint(stored, &mut pos)?).ok()?;
+    Some((codec, len, pos))
+}
+
+
+impl KVStore {
+    /// 设置当前键空间的值压缩
+    ///
+    /// 在默认键空间中设置的是数据库的默认值，记录在文件头中，
+    /// 没有单独设置过的命名键空间也使用它。只影响之后写入的值，
+    /// 已有的值在被覆盖或 [`compact`](KVStore::compact) 时按新设置重写。
+    /// 读取和扫描时自动解压，调用方看到的总是原始值。
+    ///
+    /// ```no_run
+    /// use wind_kvstore::Compression;
+    ///
+    /// # let mut store = wind_kvstore::KVStore::open("app.wkv", None)?;
+    /// store.set_compression(Compression::lz4())?;
+    /// store.keyspace("logs")?.set_compression(Compression::zstd().threshold(512))?;
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn set_compression(&mut self, compression: Compression) -> Result<()> {
+        self.check_writable()?;
+        self.wal_manager
+            .log_operation(WALManager::OP_SET_COMPRESSION, &[], Some(&compression.encode()))?;
+        self.set_compression_internal(compression)?;
+        self.finish_write()
+    }
+
+
+    /// 当前键空间生效的值压缩设置
+    pub fn compression(&self) -> Compression {
+        self.keyspaces
+            .iter()
+            .find(|entry| entry.id == self.keyspace)
+            .and_then(|entry| entry.compression)
+            .unwrap_or(self.header.compression)
+    }
+
+
+    pub(super) fn set_compression_internal(&mut self, compression: Compression) -> Result<()> {
+        if self.keyspace == DEFAULT_KEYSPACE_ID {
+            self.header.compression = compression;
+            return Ok(());
+        }
+        let entry = self
+            .keyspaces
+            .iter_mut()
+            .find(|entry| entry.id == self.keyspace)
+            .ok_or_else(|| anyhow!("Keyspace {} no longer exists", self.keyspace))?;
+        entry.compression = Some(compression);
+        self.write_keyspaces()
+    }
+
+
+    // 按当前键空间的设置压缩要写入的值
+    pub(super) fn compress_value(&self, value: &[u8]) -> Option<Vec<u8>> {
+        compress(value, self.compression())
+    }
+
+
+    // 数据页中值的 (保存的字节数, 原始字节数)。
+    // 压缩值的原始长度记在压缩数据开头，溢出的值只需读取溢出链的第一页
+    pub(super) fn value_bytes(&self, data_pages: &BTreeSet<u32>) -> Result<(u64, u64)> {
+        let (mut stored, mut raw) = (0, 0);
+        for &page_num in data_pages {
+            let page_data = self.read_page(page_num)?;
+            let header = PageHeader::unpack(&page_data)?;
+            let data = &page_data[PageHeader::SIZE..PageHeader::SIZE + header.data_len as usize];
+            for entry in parse_entries(data, header.kv_count, self.page_size()) {
+                stored += entry.value_len as u64;
+                if !entry.compressed {
+                    raw += entry.value_len as u64;
+                    continue;
+                }
+                let first = if entry.value_overflow != 0 {
+                    let overflow = self.read_page(entry.value_overflow)?;
+                    let header = PageHeader::unpack(&overflow)?;
+                    overflow[PageHeader::SIZE..PageHeader::SIZE + header.data_len as usize].to_vec()
+                } else {
+                    data[entry.value.clone()].to_vec()
+                };
+                let (_, len, _) = compressed_header(&first)
+                    .ok_or_else(|| anyhow!("Malformed compressed value in page {}", page_num))?;
+                raw += len as u64;
+            }
+        }
+        Ok((stored, raw))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let value = b"wind-kvstore ".repeat(64);
+        for compression in [Compression::lz4(), Compression::zstd()] {
+            let stored = compress(&value, compression).unwrap();
+            assert!(stored.len() < value.len());
+            assert_eq!(decompress(&stored).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_length() {
+        for codec in [Codec::Lz4, Codec::Zstd] {
+            let mut stored = vec![codec.id()];
+            write_varint(&mut stored, 1 << 40);
+            stored.extend_from_slice(&[0u8; 8]);
+            let err = decompress(&stored).unwrap_err();
+            assert!(err.to_string().contains("Malformed compressed value"), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,184 @@
+// src/kvstore/conditional.rs
+// 条件写入：在同一次 &mut 借用内先检查键的当前值或版本号，条件成立才写入，
+// 通过 SharedKVStore 调用时检查和写入在同一把写锁内完成。
+// 版本号是最后一次写入该键的 WAL 记录的 LSN，删除后重新写入的键不会得到用过的版本号。
+use super::{KVStore, PageHeader};
+use anyhow::{anyhow, Result};
+
+
+impl KVStore {
+    /// 键不存在（或已过期）时写入，返回是否写入
+    pub fn put_if_absent(&mut self, key: &[u8], value: &[u8]) -> Result<bool> {
+        self.compare_and_swap(key, None, value)
+    }
+
+
+    /// 键存在时才覆盖，返回是否写入
+    pub fn replace_if_present(&mut self, key: &[u8], value: &[u8]) -> Result<bool> {
+        if self.version(key)?.is_none() {
+            return Ok(false);
+        }
+        self.put(key, value)?;
+        Ok(true)
+    }
+
+
+    /// 键的当前值等于 `expected` 时写入 `new`，返回是否写入
+    ///
+    /// `expected` 为 `None` 表示要求键不存在。
+    ///
+    /// ```no_run
+    /// # let mut store = wind_kvstore::KVStore::open("app.wkv", None)?;
+    /// store.put(b"leader", b"node-1")?;
+    /// assert!(store.compare_and_swap(b"leader", Some(b"node-1"), b"node-2")?);
+    /// assert!(!store.compare_and_swap(b"leader", Some(b"node-1"), b"node-3")?);
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn compare_and_swap(&mut self, key: &[u8], expected: Option<&[u8]>, new: &[u8]) -> Result<bool> {
+        if self.get(key)?.as_deref() != expected {
+            return Ok(false);
+        }
+        self.put(key, new)?;
+        Ok(true)
+    }
+
+
+    /// 键的当前版本号，键不存在或已过期时返回 `None`
+    ///
+    /// 每次写入（包括修改过期时间）都会得到更大的版本号。
+    /// 升级自旧格式、之后没有再写入过的键版本号为 0。
+    /// 版本号即最后一次写入的 WAL 记录的 LSN，检查点、压缩以及删除后重新写入都不会让它变小。
+    pub fn version(&self, key: &[u8]) -> Result<Option<u64>> {
+        Ok(self.find_live_entry(key)?.map(|entry| entry.version))
+    }
+
+
+    /// 同时读取值和版本号，用于之后的 [`KVStore::put_if_version`]
+    pub fn get_with_version(&self, key: &[u8]) -> Result<Option<(Vec<u8>, u64)>> {
+        self.get_with_version_at(self.keyspace, key)
+    }
+
+
+    pub(super) fn get_with_version_at(&self, id: u16, key: &[u8]) -> Result<Option<(Vec<u8>, u64)>> {
+        if key.is_empty() {
+            return Err(anyhow!("Empty key is reserved for internal use"));
+        }
+
+        let page_num = match self.index_lookup_at(id, key)? {
+            Some(num) => num,
+            None => return Ok(None),
+        };
+        let page_data = self.read_page(page_num)?;
+        let header = PageHeader::unpack(&page_data)?;
+        let data = &page_data[PageHeader::SIZE..PageHeader::SIZE + header.data_len as usize];
+        let entry = match self.find_entry(data, header.kv_count, key)? {
+            Some(entry) if !self.is_expired(&entry) => entry,
+            _ => return Ok(None),
+        };
+
+        Ok(Some((self.entry_value(data, &entry)?, entry.version)))
+    }
+
+
+    /// 键存在且版本号等于 `version` 时写入，返回是否写入
+    ///
+    /// ```no_run
+    /// # let mut store = wind_kvstore::KVStore::open("app.wkv", None)?;
+    /// if let Some((value, version)) = store.get_with_version(b"visits")? {
+    ///     let count: u64 = String::from_utf8(value)?.parse()?;
+    ///     let updated = (count + 1).to_string();
+    ///     // 读取之后有其他写入时返回 false，由调用方重试
+    ///     store.put_if_version(b"visits", version, updated.as_bytes())?;
+    /// }
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn put_if_version(&mut self, key: &[u8], version: u64, value: &[u8]) -> Result<bool> {
+        if self.version(key)? != Some(version) {
+            return Ok(false);
+        }
+        self.put(key, value)?;
+        Ok(true)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn open(dir: &tempfile::TempDir) -> Result<KVStore> {
+        KVStore::open(dir.path().join("conditional.wkv"), None)
+    }
+
+
+    #[test]
+    fn put_if_absent_and_replace_if_present() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = open(&dir)?;
+        assert!(!store.replace_if_present(b"k", b"0")?);
+        assert_eq!(store.get(b"k")?, None);
+
+        assert!(store.put_if_absent(b"k", b"1")?);
+        assert!(!store.put_if_absent(b"k", b"2")?);
+        assert_eq!(store.get(b"k")?, Some(b"1".to_vec()));
+
+        assert!(store.replace_if_present(b"k", b"3")?);
+        assert_eq!(store.get(b"k")?, Some(b"3".to_vec()));
+
+        // 删除后重新视为不存在
+        store.delete(b"k")?;
+        assert!(!store.replace_if_present(b"k", b"4")?);
+        assert!(store.put_if_absent(b"k", b"5")?);
+        assert_eq!(store.get(b"k")?, Some(b"5".to_vec()));
+        Ok(())
+    }
+
+
+    #[test]
+    fn compare_and_swap_checks_current_value() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = open(&dir)?;
+        // 期望不存在
+        assert!(store.compare_and_swap(b"k", None, b"1")?);
+        assert!(!store.compare_and_swap(b"k", None, b"2")?);
+        assert_eq!(store.get(b"k")?, Some(b"1".to_vec()));
+
+        // 期望某个值
+        assert!(!store.compare_and_swap(b"k", Some(b"0"), b"2")?);
+        assert_eq!(store.get(b"k")?, Some(b"1".to_vec()));
+        assert!(store.compare_and_swap(b"k", Some(b"1"), b"2")?);
+        assert_eq!(store.get(b"k")?, Some(b"2".to_vec()));
+        assert!(!store.compare_and_swap(b"missing", Some(b"2"), b"3")?);
+        assert_eq!(store.get(b"missing")?, None);
+        Ok(())
+    }
+
+
+    #[test]
+    fn put_if_version_rejects_stale_versions() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = open(&dir)?;
+        store.put(b"k", b"1")?;
+        let (value, stale) = store.get_with_version(b"k")?.unwrap();
+        assert_eq!(value, b"1");
+        store.put(b"k", b"2")?;
+        let current = store.version(b"k")?.unwrap();
+        assert!(current > stale);
+
+        assert!(!store.put_if_version(b"k", stale, b"3")?);
+        assert_eq!(store.get(b"k")?, Some(b"2".to_vec()));
+        assert!(store.put_if_version(b"k", current, b"3")?);
+        assert_eq!(store.get(b"k")?, Some(b"3".to_vec()));
+        assert!(!store.put_if_version(b"missing", current, b"4")?);
+        let current = store.version(b"k")?.unwrap();
+        store.close()?;
+
+        // 版本号随条目一起保存，重新打开后不变
+        let mut store = open(&dir)?;
+        assert_eq!(store.version(b"k")?, Some(current));
+        assert!(store.put_if_version(b"k", current, b"4")?);
+        assert!(store.version(b"k")?.unwrap() > current);
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+// src/kvstore/counter.rs
+// 计数器：值以十进制文本保存，读取、计算和写回在同一次 &mut 借用内完成，
+// 通过 SharedKVStore 调用时多个线程的自增不会互相覆盖。
+// WAL 中记录的是运算后的值而不是增量，重放多少次结果都一样。
+use super::{KVStore, KvError};
+use anyhow::Result;
+
+
+fn not_numeric(key: &[u8]) -> KvError {
+    KvError::NotNumeric(String::from_utf8_lossy(key).into_owned())
+}
+
+
+fn overflow(key: &[u8]) -> KvError {
+    KvError::CounterOverflow(String::from_utf8_lossy(key).into_owned())
+}
+
+
+impl KVStore {
+    /// 把键的整数值加上 `delta`，返回新值
+    ///
+    /// 键不存在（或已过期）时从 0 开始。值必须是十进制整数文本，否则返回
+    /// [`KvError::NotNumeric`]；结果超出 `i64` 时返回 [`KvError::CounterOverflow`]，
+    /// 两种情况下值都保持不变。新值总是写成规范的十进制形式（如 `+07` 变为 `7`），
+    /// 键原有的过期时间保持不变。WAL 中记录的是新值而不是增量，重放时不会重复累加。
+    ///
+    /// ```no_run
+    /// # let mut store = wind_kvstore::KVStore::open("app.wkv", None)?;
+    /// store.put(b"visits", b"41")?;
+    /// assert_eq!(store.incr_by(b"visits", 1)?, 42);
+    /// assert_eq!(store.decr_by(b"visits", 2)?, 40);
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn incr_by(&mut self, key: &[u8], delta: i64) -> Result<i64> {
+        let (current, expires_at) = match self.read_counter(key)? {
+            Some((text, expires_at)) => {
+                (text.parse::<i64>().map_err(|_| not_numeric(key))?, expires_at)
+            }
+            None => (0, None),
+        };
+        let value = current.checked_add(delta).ok_or_else(|| overflow(key))?;
+        self.write_counter(key, &value.to_string(), expires_at)?;
+        Ok(value)
+    }
+
+
+    /// 把键的整数值减去 `delta`，返回新值，见 [`KVStore::incr_by`]
+    pub fn decr_by(&mut self, key: &[u8], delta: i64) -> Result<i64> {
+        let delta = delta.checked_neg().ok_or_else(|| overflow(key))?;
+        self.incr_by(key, delta)
+    }
+
+
+    /// 把键的数值按浮点数加上 `delta`，返回新值
+    ///
+    /// 整数文本也可以作为浮点数参与运算。`NaN` 和无穷大不是合法的值：
+    /// `delta` 不是有限数时返回 [`KvError::NonFiniteDelta`]，
+    /// 结果不是有限数时返回 [`KvError::CounterOverflow`]。
+    pub fn incr_by_float(&mut self, key: &[u8], delta: f64) -> Result<f64> {
+        if !delta.is_finite() {
+            return Err(KvError::NonFiniteDelta(delta).into());
+        }
+        let (current, expires_at) = match self.read_counter(key)? {
+            Some((text, expires_at)) => {
+                let current = text
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|value| value.is_finite())
+                    .ok_or_else(|| not_numeric(key))?;
+                (current, expires_at)
+            }
+            None => (0.0, None),
+        };
+        let value = current + delta;
+        if !value.is_finite() {
+            return Err(overflow(key).into());
+        }
+        self.write_counter(key, &value.to_string(), expires_at)?;
+        Ok(value)
+    }
+
+
+    /// 把键的数值按浮点数减去 `delta`，返回新值，见 [`KVStore::incr_by_float`]
+    pub fn decr_by_float(&mut self, key: &[u8], delta: f64) -> Result<f64> {
+        self.incr_by_float(key, -delta)
+    }
+
+
+    // 读取计数器当前的文本和过期时间，键不存在或已过期时返回 None
+    fn read_counter(&self, key: &[u8]) -> Result<Option<(String, Option<u64>)>> {
+        let expires_at = match self.find_live_entry(key)? {
+            Some(entry) => entry.expires_at,
+            None => return Ok(None),
+        };
+        let value = self.get(key)?.unwrap_or_default();
+        let text = String::from_utf8(value).map_err(|_| not_numeric(key))?;
+        Ok(Some((text, expires_at)))
+    }
+
+
+    // 写回计数器，保留原来的过期时间
+    fn write_counter(&mut self, key: &[u8], value: &str, expires_at: Option<u64>) -> Result<()> {
+        match expires_at {
+            Some(expires_at) => self.put_expiring_at(key, value.as_bytes(), expires_at),
+            None => self.put(key, value.as_bytes()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn float_delta_must_be_finite() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = KVStore::open(dir.path().join("counter.wkv"), None)?;
+        store.put(b"f", b"1.5")?;
+        for delta in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let err = store.incr_by_float(b"f", delta).unwrap_err();
+            assert!(matches!(err.downcast_ref::<KvError>(), Some(KvError::NonFiniteDelta(_))));
+            let err = store.decr_by_float(b"f", delta).unwrap_err();
+            assert!(matches!(err.downcast_ref::<KvError>(), Some(KvError::NonFiniteDelta(_))));
+        }
+        let err = store.incr_by_float(b"f", f64::MAX).and_then(|_| store.incr_by_float(b"f", f64::MAX)).unwrap_err();
+        assert!(matches!(err.downcast_ref::<KvError>(), Some(KvError::CounterOverflow(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn integer_counters_start_at_zero() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = KVStore::open(dir.path().join("counter.wkv"), None)?;
+        assert_eq!(store.incr_by(b"up", 5)?, 5);
+        assert_eq!(store.incr_by(b"up", 2)?, 7);
+        assert_eq!(store.get(b"up")?, Some(b"7".to_vec()));
+        assert_eq!(store.decr_by(b"down", 3)?, -3);
+        assert_eq!(store.get(b"down")?, Some(b"-3".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn rejected_increments_leave_value_unchanged() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = KVStore::open(dir.path().join("counter.wkv"), None)?;
+        store.put(b"name", b"alice")?;
+        let err = store.incr_by(b"name", 1).unwrap_err();
+        assert!(matches!(err.downcast_ref::<KvError>(), Some(KvError::NotNumeric(_))));
+        assert_eq!(store.get(b"name")?, Some(b"alice".to_vec()));
+
+        store.put(b"max", i64::MAX.to_string().as_bytes())?;
+        let err = store.incr_by(b"max", 1).unwrap_err();
+        assert!(matches!(err.downcast_ref::<KvError>(), Some(KvError::CounterOverflow(_))));
+        assert_eq!(store.get(b"max")?, Some(i64::MAX.to_string().into_bytes()));
+        let err = store.decr_by(b"max", i64::MIN).unwrap_err();
+        assert!(matches!(err.downcast_ref::<KvError>(), Some(KvError::CounterOverflow(_))));
+        assert_eq!(store.get(b"max")?, Some(i64::MAX.to_string().into_bytes()));
+        Ok(())
+    }
+}
@@ -0,0 +1,332 @@
+// src/kvstore/cursor.rs
+// 沿 B+ 树逐页遍历的游标：内存中只保留根到当前叶子的路径和一个叶子节点，
+// 值（包括溢出链）在迭代到该键时才读取。
+use super::btree::Node;
+use super::KVStore;
+use anyhow::Result;
+use std::ops::{Bound, RangeBounds};
+
+
+// 下降到叶子时选择子节点的方式
+enum Target<'k> {
+    First,
+    Last,
+    Key(&'k [u8]),
+}
+
+
+/// 按键序遍历键值对的游标，由 [`KVStore::cursor`] 或 [`KVStore::cursor_range`] 创建
+///
+/// 每次迭代返回 `Result<(key, value)>`，出错后迭代结束。
+pub struct KVCursor<'a> {
+    store: &'a KVStore,
+    // 遍历的索引树，创建时取当前键空间的根
+    root: u32,
+    // 从根到当前叶子经过的内部节点及所选子节点下标
+    path: Vec<(u32, usize)>,
+    leaf_keys: Vec<Vec<u8>>,
+    leaf_pages: Vec<u32>,
+    // 正序时为下一个返回的下标；逆序时下一个返回 pos - 1
+    pos: usize,
+    reverse: bool,
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+    done: bool,
+}
+
+
+impl<'a> KVCursor<'a> {
+    pub(super) fn new<'k, R: RangeBounds<&'k [u8]>>(
+        store: &'a KVStore,
+        range: R,
+        reverse: bool,
+    ) -> Result<Self> {
+        Self::with_root(store, store.index_root(), range, reverse)
+    }
+
+
+    // 遍历指定的索引树，用于读取当前键空间之外的键空间
+    pub(super) fn with_root<'k, R: RangeBounds<&'k [u8]>>(
+        store: &'a KVStore,
+        root: u32,
+        range: R,
+        reverse: bool,
+    ) -> Result<Self> {
+        let lower = range.start_bound().map(|k| k.to_vec());
+        let upper = range.end_bound().map(|k| k.to_vec());
+        let mut cursor = Self {
+            store,
+            root,
+            path: Vec::new(),
+            leaf_keys: Vec::new(),
+            leaf_pages: Vec::new(),
+            pos: 0,
+            reverse,
+            lower,
+            upper,
+            done: false,
+        };
+        cursor.rewind()?;
+        Ok(cursor)
+    }
+
+
+    // 回到区间的起点（逆序时为终点）
+    fn rewind(&mut self) -> Result<()> {
+        let bound = if self.reverse {
+            self.upper.clone()
+        } else {
+            self.lower.clone()
+        };
+        match &bound {
+            Bound::Included(key) | Bound::Excluded(key) => self.seek(key),
+            Bound::Unbounded if self.reverse => self.descend_from_root(Target::Last),
+            Bound::Unbounded => self.descend_from_root(Target::First),
+        }
+    }
+
+
+    /// 定位游标：正序时下一个返回的是第一个 >= `key` 的键，逆序时是最后一个 <= `key` 的键
+    ///
+    /// 游标仍受创建时的区间限制。
+    pub fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.done = false;
+        self.descend_from_root(Target::Key(key))?;
+        let idx = self.leaf_keys.partition_point(|k| k.as_slice() < key);
+        self.pos = if self.reverse && self.leaf_keys.get(idx).is_some_and(|k| k == key) {
+            idx + 1
+        } else {
+            idx
+        };
+        Ok(())
+    }
+
+
+    fn descend_from_root(&mut self, target: Target) -> Result<()> {
+        self.path.clear();
+        self.leaf_keys.clear();
+        self.leaf_pages.clear();
+        self.pos = 0;
+
+        if self.root == 0 {
+            self.done = true;
+            return Ok(());
+        }
+        self.descend(self.root, target)
+    }
+
+
+    fn descend(&mut self, mut page_num: u32, target: Target) -> Result<()> {
+        loop {
+            match self.store.read_node(page_num)? {
+                Node::Branch { keys, children, .. } => {
+                    let idx = match target {
+                        Target::First => 0,
+                        Target::Last => children.len() - 1,
+                        Target::Key(key) => keys.partition_point(|k| k.as_slice() <= key),
+                    };
+                    self.path.push((page_num, idx));
+                    page_num = children[idx];
+                }
+                Node::Leaf { keys, pages, .. } => {
+                    self.pos = if self.reverse { keys.len() } else { 0 };
+                    self.leaf_keys = keys;
+                    self.leaf_pages = pages;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+
+    // 移动到相邻叶子，没有更多叶子时返回 false
+    fn step_leaf(&mut self) -> Result<bool> {
+        while let Some((page_num, idx)) = self.path.pop() {
+            let children = match self.store.read_node(page_num)? {
+                Node::Branch { children, .. } => children,
+                Node::Leaf { .. } => unreachable!("index path contains a leaf"),
+            };
+
+            let next = if self.reverse {
+                idx.checked_sub(1)
+            } else {
+                Some(idx + 1).filter(|i| *i < children.len())
+            };
+            if let Some(next) = next {
+                self.path.push((page_num, next));
+                let target = if self.reverse { Target::Last } else { Target::First };
+                self.descend(children[next], target)?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+
+    fn next_entry(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        loop {
+            let idx = if self.reverse {
+                match self.pos.checked_sub(1) {
+                    Some(idx) => idx,
+                    None => {
+                        if !self.step_leaf()? {
+                            return Ok(None);
+                        }
+                        continue;
+                    }
+                }
+            } else if self.pos < self.leaf_keys.len() {
+                self.pos
+            } else {
+                if !self.step_leaf()? {
+                    return Ok(None);
+                }
+                continue;
+            };
+            self.pos = if self.reverse { idx } else { idx + 1 };
+
+            let key = &self.leaf_keys[idx];
+            let in_range = (self.lower.as_ref(), self.upper.as_ref()).contains(key);
+            if !in_range {
+                // 越过遍历方向上的边界即结束，否则是 seek 到了起点之前，继续前进
+                let past_end = if self.reverse {
+                    !(self.lower.as_ref(), Bound::Unbounded).contains(key)
+                } else {
+                    !(Bound::Unbounded, self.upper.as_ref()).contains(key)
+                };
+                if past_end {
+                    return Ok(None);
+                }
+                continue;
+            }
+
+            let key = key.clone();
+            if let Some(value) = self.store.read_value(self.leaf_pages[idx], &key)? {
+                return Ok(Some((key, value)));
+            }
+        }
+    }
+}
+
+
+impl Iterator for KVCursor<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.next_entry() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:03}", i).into_bytes()
+    }
+
+
+    fn next_key(cursor: &mut KVCursor) -> Result<Option<Vec<u8>>> {
+        cursor.next().transpose().map(|entry| entry.map(|(key, _)| key))
+    }
+
+
+    fn store(dir: &tempfile::TempDir) -> Result<KVStore> {
+        let mut store = KVStore::open(dir.path().join("cursor.wkv"), None)?;
+        // 足够多的键，seek 会落到不同的叶子
+        for i in 0..300 {
+            store.put(&key(i), &[b'v'; 40])?;
+        }
+        Ok(store)
+    }
+
+
+    #[test]
+    fn seek_forward() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let store = store(&dir)?;
+        let mut cursor = store.cursor()?;
+        assert_eq!(next_key(&mut cursor)?, Some(key(0)));
+
+        cursor.seek(&key(150))?;
+        assert_eq!(next_key(&mut cursor)?, Some(key(150)));
+        assert_eq!(next_key(&mut cursor)?, Some(key(151)));
+        // 不存在的键定位到下一个键
+        cursor.seek(b"key1505")?;
+        assert_eq!(next_key(&mut cursor)?, Some(key(151)));
+
+        // 越过最后一个键后迭代结束，再次 seek 可以继续
+        cursor.seek(b"key999")?;
+        assert_eq!(next_key(&mut cursor)?, None);
+        assert_eq!(next_key(&mut cursor)?, None);
+        cursor.seek(&key(299))?;
+        assert_eq!(next_key(&mut cursor)?, Some(key(299)));
+        assert_eq!(next_key(&mut cursor)?, None);
+        cursor.seek(b"a")?;
+        assert_eq!(next_key(&mut cursor)?, Some(key(0)));
+        Ok(())
+    }
+
+
+    #[test]
+    fn seek_reverse() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let store = store(&dir)?;
+        let mut cursor = store.cursor_range(.., true)?;
+        assert_eq!(next_key(&mut cursor)?, Some(key(299)));
+
+        cursor.seek(&key(150))?;
+        assert_eq!(next_key(&mut cursor)?, Some(key(150)));
+        assert_eq!(next_key(&mut cursor)?, Some(key(149)));
+        cursor.seek(b"key1505")?;
+        assert_eq!(next_key(&mut cursor)?, Some(key(150)));
+
+        // 越过最后一个键时从最后一个键开始，越过第一个键时结束
+        cursor.seek(b"key999")?;
+        assert_eq!(next_key(&mut cursor)?, Some(key(299)));
+        cursor.seek(b"a")?;
+        assert_eq!(next_key(&mut cursor)?, None);
+        Ok(())
+    }
+
+
+    #[test]
+    fn seek_stays_within_range() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let store = store(&dir)?;
+        let (lo, hi) = (key(100), key(200));
+        let mut cursor = store.cursor_range(lo.as_slice()..hi.as_slice(), false)?;
+        // 定位到区间起点之前时从起点开始
+        cursor.seek(&key(50))?;
+        assert_eq!(next_key(&mut cursor)?, Some(key(100)));
+        cursor.seek(&key(199))?;
+        assert_eq!(next_key(&mut cursor)?, Some(key(199)));
+        assert_eq!(next_key(&mut cursor)?, None);
+        cursor.seek(&key(250))?;
+        assert_eq!(next_key(&mut cursor)?, None);
+
+        let mut cursor = store.cursor_range(lo.as_slice()..=hi.as_slice(), true)?;
+        cursor.seek(&key(250))?;
+        assert_eq!(next_key(&mut cursor)?, Some(key(200)));
+        cursor.seek(&key(100))?;
+        assert_eq!(next_key(&mut cursor)?, Some(key(100)));
+        assert_eq!(next_key(&mut cursor)?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,633 @@
+// src/kvstore/keyspace.rs
+// 命名键空间：同一个文件中互相独立的键集合，每个键空间有自己的 B+ 树索引和数据页。
+//
+// 默认键空间的索引根在文件头中；命名键空间的编号、名称和索引根记录在键空间目录里，
+// 目录序列化后存放在一条溢出链中，起始页记在文件头。
+// 数据页和索引页的页头记录所属键空间的编号，写入只会选用当前键空间的数据页，
+// 所以清空或删除一个键空间只需沿它的索引释放页面，不会触及其他键空间。
+// WAL 中命名键空间的每条操作前都有一条 OP_KEYSPACE 记录，只作用于紧随其后的那条记录。
+// 名称以控制字符开头的是引擎内部的隐藏键空间（如二级索引），用户无法打开，也不会被列出。
+use super::btree::Node;
+use super::{parse_entries, Compression, IndexInfo, KVStore, PageHeader, WALManager, COMPRESSION_VERSION};
+use anyhow::{anyhow, bail, Result};
+use byteorder::{LittleEndian, WriteBytesExt};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::{Deref, DerefMut, RangeBounds};
+use std::time::Duration;
+
+
+/// 默认键空间的名称，没有切换键空间时的读写都作用于它
+pub const DEFAULT_KEYSPACE: &str = "default";
+pub(super) const DEFAULT_KEYSPACE_ID: u16 = 0;
+const MAX_KEYSPACE_NAME: usize = 64; // 键空间名称的最大字节数
+pub(super) const HIDDEN_KEYSPACE_PREFIX: char = '\u{1}';
+const INHERIT_COMPRESSION: u8 = 0xFF; // 目录中表示沿用数据库默认压缩设置的算法编号
+
+
+// 键空间目录中的一项
+#[derive(Debug, Clone)]
+pub(super) struct KeyspaceEntry {
+    pub(super) id: u16,
+    pub(super) name: String,
+    pub(super) root: u32, // 索引根页，0 表示键空间为空
+    pub(super) compression: Option<Compression>, // None 表示沿用数据库的默认设置
+}
+
+
+impl KeyspaceEntry {
+    // 目录格式：[编号 u16 | 索引根 u32 | 压缩设置 5 字节 | 名称长 u16 | 名称]*，
+    // 版本 9 之前没有压缩设置
+    fn encode_all(entries: &[Self]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for entry in entries {
+            buf.write_u16::<LittleEndian>(entry.id).unwrap();
+            buf.write_u32::<LittleEndian>(entry.root).unwrap();
+            match entry.compression {
+                Some(compression) => buf.extend_from_slice(&compression.encode()),
+                None => buf.extend_from_slice(&[INHERIT_COMPRESSION, 0, 0, 0, 0]),
+            }
+            buf.write_u16::<LittleEndian>(entry.name.len() as u16).unwrap();
+            buf.extend_from_slice(entry.name.as_bytes());
+        }
+        buf
+    }
+
+    // 目录无法完整解析时返回 None
+    pub(super) fn decode_all(data: &[u8], version: u16) -> Option<Vec<Self>> {
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let id = u16::from_le_bytes(data.get(pos..pos + 2)?.try_into().ok()?);
+            let root = u32::from_le_bytes(data.get(pos + 2..pos + 6)?.try_into().ok()?);
+            pos += 6;
+            let compression = if version >= COMPRESSION_VERSION {
+                let setting = data.get(pos..pos + 5)?;
+                pos += 5;
+                match setting[0] {
+                    INHERIT_COMPRESSION => None,
+                    _ => Some(Compression::decode(setting).ok()?),
+                }
+            } else {
+                None
+            };
+            let len = u16::from_le_bytes(data.get(pos..pos + 2)?.try_into().ok()?) as usize;
+            let name = std::str::from_utf8(data.get(pos + 2..pos + 2 + len)?).ok()?;
+            entries.push(Self {
+                id,
+                name: name.to_string(),
+                root,
+                compression,
+            });
+            pos += 2 + len;
+        }
+        Some(entries)
+    }
+}
+
+
+fn check_keyspace_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_KEYSPACE_NAME || name.chars().any(char::is_control) {
+        bail!(
+            "Invalid keyspace name {:?}, expected 1 to {} bytes without control characters",
+            name,
+            MAX_KEYSPACE_NAME
+        );
+    }
+    Ok(())
+}
+
+
+/// 键空间的统计信息，由 [`KVStore::keyspace_stats`] 返回
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyspaceStats {
+    /// 键数，包括已过期但尚未清除的键
+    pub keys: u64,
+    pub data_pages: u32,
+    pub index_pages: u32,
+    /// 值占用的字节数，压缩的值按压缩后的长度计
+    pub value_bytes: u64,
+    /// 值解压后的总字节数
+    pub raw_value_bytes: u64,
+}
+
+
+impl KeyspaceStats {
+    /// 压缩比（解压后的字节数 / 保存的字节数），没有值时为 1
+    pub fn compression_ratio(&self) -> f64 {
+        if self.value_bytes == 0 {
+            return 1.0;
+        }
+        self.raw_value_bytes as f64 / self.value_bytes as f64
+    }
+}
+
+
+impl fmt::Display for KeyspaceStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} keys, {} data pages, {} index pages, {} value bytes ({:.2}x compression)",
+            self.keys,
+            self.data_pages,
+            self.index_pages,
+            self.value_bytes,
+            self.compression_ratio()
+        )
+    }
+}
+
+
+// 沿一棵索引树收集到的页面
+#[derive(Default)]
+struct TreePages {
+    // 索引节点页及其中溢出键的溢出链
+    nodes: Vec<(u32, Vec<u32>)>,
+    data_pages: BTreeSet<u32>,
+    keys: u64,
+}
+
+
+/// 由 [`KVStore::keyspace`] 创建的键空间句柄
+///
+/// 通过 `Deref` 调用 [`KVStore`] 的方法：读写、扫描、游标、事务、过期时间和计数器
+/// 都只作用于这个键空间；`compact`、`checkpoint`、`purge_expired` 仍然针对整个文件。
+/// 句柄释放时 store 切换回原来的键空间。
+pub struct Keyspace<'a> {
+    store: &'a mut KVStore,
+    previous: u16,
+}
+
+
+impl Keyspace<'_> {
+    pub fn name(&self) -> &str {
+        self.store.current_keyspace()
+    }
+}
+
+
+impl Deref for Keyspace<'_> {
+    type Target = KVStore;
+
+    fn deref(&self) -> &KVStore {
+        self.store
+    }
+}
+
+
+impl DerefMut for Keyspace<'_> {
+    fn deref_mut(&mut self) -> &mut KVStore {
+        self.store
+    }
+}
+
+
+impl Drop for Keyspace<'_> {
+    fn drop(&mut self) {
+        // 之前的键空间可能已经通过这个句柄被删除，这时回到默认键空间
+        let previous = match self.store.keyspace_name(self.previous) {
+            Some(_) => self.previous,
+            None => DEFAULT_KEYSPACE_ID,
+        };
+        self.store.select_keyspace(previous);
+    }
+}
+
+
+impl KVStore {
+    /// 取得名为 `name` 的键空间，不存在时创建
+    ///
+    /// 每个键空间有自己的索引，不同键空间中的同名键互不影响。
+    /// [`DEFAULT_KEYSPACE`] 指默认键空间。
+    ///
+    /// ```no_run
+    /// # let mut store = wind_kvstore::KVStore::open("app.wkv", None)?;
+    /// store.keyspace("users")?.put(b"42", b"alice")?;
+    /// store.keyspace("orders")?.put(b"42", b"3 items")?;
+    /// assert_eq!(store.keyspace("users")?.get(b"42")?, Some(b"alice".to_vec()));
+    /// assert_eq!(store.get(b"42")?, None);
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn keyspace(&mut self, name: &str) -> Result<Keyspace<'_>> {
+        let id = self.open_keyspace(name)?;
+        let previous = self.keyspace;
+        self.select_keyspace(id);
+        Ok(Keyspace {
+            store: self,
+            previous,
+        })
+    }
+
+
+    /// 切换当前键空间（不存在时创建），之后的操作都作用于它，直到再次切换
+    pub fn use_keyspace(&mut self, name: &str) -> Result<()> {
+        let id = self.open_keyspace(name)?;
+        self.select_keyspace(id);
+        Ok(())
+    }
+
+
+    /// 当前键空间的名称
+    pub fn current_keyspace(&self) -> &str {
+        self.keyspace_name(self.keyspace).unwrap_or(DEFAULT_KEYSPACE)
+    }
+
+
+    /// 所有键空间的名称，默认键空间在最前，其余按名称排序
+    pub fn keyspaces(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .keyspaces
+            .iter()
+            .filter(|entry| !entry.name.starts_with(HIDDEN_KEYSPACE_PREFIX))
+            .map(|entry| entry.name.clone())
+            .collect();
+        names.sort();
+        names.insert(0, DEFAULT_KEYSPACE.to_string());
+        names
+    }
+
+
+    /// 键空间的键数、页数和值的压缩比，键空间不存在时返回 `None`
+    ///
+    /// 沿键空间的索引统计，耗时与它占用的页数成正比。
+    pub fn keyspace_stats(&self, name: &str) -> Result<Option<KeyspaceStats>> {
+        let id = match self.user_keyspace_id(name) {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        let pages = self.tree_pages(self.tree_root(id))?;
+        let (value_bytes, raw_value_bytes) = self.value_bytes(&pages.data_pages)?;
+        Ok(Some(KeyspaceStats {
+            keys: pages.keys,
+            data_pages: pages.data_pages.len() as u32,
+            index_pages: pages.nodes.len() as u32,
+            value_bytes,
+            raw_value_bytes,
+        }))
+    }
+
+
+    /// 读取键空间 `keyspace` 中的键，不切换当前键空间
+    ///
+    /// 和 [`KVStore::keyspace`] 不同，这一组 `*_in` 方法只需要 `&self`，
+    /// 在 [`SharedKVStore::read`](super::SharedKVStore::read) 的读锁下也能读取其他键空间。
+    /// 键空间不存在时返回错误，不会创建它。
+    pub fn get_in(&self, keyspace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_at(self.existing_keyspace(keyspace)?, key)
+    }
+
+
+    /// 见 [`KVStore::get_in`] 和 [`KVStore::get_with_version`]
+    pub fn get_with_version_in(&self, keyspace: &str, key: &[u8]) -> Result<Option<(Vec<u8>, u64)>> {
+        self.get_with_version_at(self.existing_keyspace(keyspace)?, key)
+    }
+
+
+    /// 见 [`KVStore::get_in`] 和 [`KVStore::ttl`]
+    pub fn ttl_in(&self, keyspace: &str, key: &[u8]) -> Result<Option<Duration>> {
+        self.ttl_at(self.existing_keyspace(keyspace)?, key)
+    }
+
+
+    /// 见 [`KVStore::get_in`] 和 [`KVStore::indexes`]
+    pub fn indexes_in(&self, keyspace: &str) -> Result<Vec<IndexInfo>> {
+        Ok(self.indexes_at(self.existing_keyspace(keyspace)?))
+    }
+
+
+    /// 见 [`KVStore::get_in`] 和 [`KVStore::find_by_index_range`]
+    pub fn find_by_index_range_in<R: RangeBounds<Value>>(
+        &self,
+        keyspace: &str,
+        name: &str,
+        range: R,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.find_by_index_range_at(self.existing_keyspace(keyspace)?, name, range)
+    }
+
+
+    /// 删除键空间中的所有键，键空间本身保留，不存在时返回 false
+    ///
+    /// 只释放该键空间的索引和数据页，耗时与它占用的页数成正比，其他键空间不受影响。
+    pub fn clear_keyspace(&mut self, name: &str) -> Result<bool> {
+        self.check_writable()?;
+        if self.user_keyspace_id(name).is_none() {
+            return Ok(false);
+        }
+        self.wal_manager
+            .log_operation(WALManager::OP_CLEAR_KEYSPACE, name.as_bytes(), None)?;
+        self.clear_keyspace_internal(name)?;
+        self.finish_write()?;
+        Ok(true)
+    }
+
+
+    /// 删除键空间及其中的所有键，不存在时返回 false
+    ///
+    /// 默认键空间和当前键空间不能删除。
+    pub fn drop_keyspace(&mut self, name: &str) -> Result<bool> {
+        self.check_writable()?;
+        let id = match self.user_keyspace_id(name) {
+            Some(id) => id,
+            None => return Ok(false),
+        };
+        if id == DEFAULT_KEYSPACE_ID {
+            bail!("The default keyspace cannot be dropped");
+        }
+        if id == self.keyspace {
+            bail!("Keyspace {} is in use", name);
+        }
+        self.wal_manager
+            .log_operation(WALManager::OP_DROP_KEYSPACE, name.as_bytes(), None)?;
+        self.drop_keyspace_internal(name)?;
+        self.finish_write()?;
+        Ok(true)
+    }
+
+
+    // 查找键空间，不存在时记录 WAL 后创建
+    fn open_keyspace(&mut self, name: &str) -> Result<u16> {
+        check_keyspace_name(name)?;
+        if let Some(id) = self.keyspace_id(name) {
+            return Ok(id);
+        }
+        self.check_writable()?;
+        self.wal_manager
+            .log_operation(WALManager::OP_CREATE_KEYSPACE, name.as_bytes(), None)?;
+        let id = self.create_keyspace(name)?;
+        self.finish_write()?;
+        Ok(id)
+    }
+
+
+    // 创建键空间并写入目录，已存在时直接返回编号。名称由调用方检查
+    pub(super) fn create_keyspace(&mut self, name: &str) -> Result<u16> {
+        if let Some(id) = self.keyspace_id(name) {
+            return Ok(id);
+        }
+
+        let id = self
+            .keyspaces
+            .iter()
+            .map(|entry| entry.id)
+            .max()
+            .unwrap_or(DEFAULT_KEYSPACE_ID)
+            .checked_add(1)
+            .ok_or_else(|| anyhow!("Too many keyspaces"))?;
+        self.keyspaces.push(KeyspaceEntry {
+            id,
+            name: name.to_string(),
+            root: 0,
+            compression: None,
+        });
+        self.write_keyspaces()?;
+        Ok(id)
+    }
+
+
+    // 释放键空间的全部索引页、数据页和它们引用的溢出链，并清空它的二级索引
+    pub(super) fn clear_keyspace_internal(&mut self, name: &str) -> Result<bool> {
+        let id = match self.keyspace_id(name) {
+            Some(id) => id,
+            None => return Ok(false),
+        };
+        let pages = self.tree_pages(self.tree_root(id))?;
+
+        for (page_num, spills) in pages.nodes {
+            for spill in spills {
+                self.free_overflow(spill)?;
+            }
+            self.free_page(page_num)?;
+        }
+        for page_num in pages.data_pages {
+            let page_data = self.read_page(page_num)?;
+            let header = PageHeader::unpack(&page_data)?;
+            let data = &page_data[PageHeader::SIZE..PageHeader::SIZE + header.data_len as usize];
+            for entry in parse_entries(data, header.kv_count, self.page_size()) {
+                self.free_overflow(entry.key_overflow)?;
+                self.free_overflow(entry.value_overflow)?;
+            }
+            self.free_page(page_num)?;
+        }
+
+        self.set_tree_root(id, 0)?;
+        self.clear_indexes_of(id)?;
+        Ok(true)
+    }
+
+
+    pub(super) fn drop_keyspace_internal(&mut self, name: &str) -> Result<bool> {
+        let id = match self.keyspace_id(name) {
+            Some(id) => id,
+            None => return Ok(false),
+        };
+        self.drop_indexes_of(id)?;
+        self.clear_keyspace_internal(name)?;
+        self.keyspaces.retain(|entry| entry.name != name);
+        self.write_keyspaces()?;
+        Ok(true)
+    }
+
+
+    // 切换当前键空间，之后写入 WAL 的操作都带上它的名称
+    pub(super) fn select_keyspace(&mut self, id: u16) {
+        self.keyspace = id;
+        self.wal_manager.keyspace = match id {
+            DEFAULT_KEYSPACE_ID => None,
+            id => self.keyspace_name(id).map(str::to_string),
+        };
+    }
+
+
+    // 临时切换到键空间 id 执行 f，之后切换回来。不记录 WAL，只用于引擎内部维护的数据
+    pub(super) fn in_keyspace<T>(&mut self, id: u16, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let previous = self.keyspace;
+        self.keyspace = id;
+        let result = f(self);
+        self.keyspace = previous;
+        result
+    }
+
+
+    // 用户可见的键空间，隐藏键空间视为不存在
+    fn user_keyspace_id(&self, name: &str) -> Option<u16> {
+        if name.starts_with(HIDDEN_KEYSPACE_PREFIX) {
+            return None;
+        }
+        self.keyspace_id(name)
+    }
+
+
+    fn existing_keyspace(&self, name: &str) -> Result<u16> {
+        self.user_keyspace_id(name)
+            .ok_or_else(|| anyhow!("Keyspace {} not found", name))
+    }
+
+
+    pub(super) fn keyspace_id(&self, name: &str) -> Option<u16> {
+        if name == DEFAULT_KEYSPACE {
+            return Some(DEFAULT_KEYSPACE_ID);
+        }
+        self.keyspaces
+            .iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.id)
+    }
+
+
+    pub(super) fn keyspace_name(&self, id: u16) -> Option<&str> {
+        if id == DEFAULT_KEYSPACE_ID {
+            return Some(DEFAULT_KEYSPACE);
+        }
+        self.keyspaces
+            .iter()
+            .find(|entry| entry.id == id)
+            .map(|entry| entry.name.as_str())
+    }
+
+
+    // 所有键空间的编号，默认键空间在最前
+    pub(super) fn keyspace_ids(&self) -> Vec<u16> {
+        let mut ids = vec![DEFAULT_KEYSPACE_ID];
+        ids.extend(self.keyspaces.iter().map(|entry| entry.id));
+        ids
+    }
+
+
+    // 当前键空间的索引根页
+    pub(super) fn index_root(&self) -> u32 {
+        self.tree_root(self.keyspace)
+    }
+
+
+    pub(super) fn set_index_root(&mut self, root: u32) -> Result<()> {
+        self.set_tree_root(self.keyspace, root)
+    }
+
+
+    pub(super) fn tree_root(&self, id: u16) -> u32 {
+        if id == DEFAULT_KEYSPACE_ID {
+            return self.header.index_root;
+        }
+        self.keyspaces
+            .iter()
+            .find(|entry| entry.id == id)
+            .map_or(0, |entry| entry.root)
+    }
+
+
+    fn set_tree_root(&mut self, id: u16, root: u32) -> Result<()> {
+        if id == DEFAULT_KEYSPACE_ID {
+            self.header.index_root = root;
+            return Ok(());
+        }
+        let entry = self
+            .keyspaces
+            .iter_mut()
+            .find(|entry| entry.id == id)
+            .ok_or_else(|| anyhow!("Keyspace {} no longer exists", id))?;
+        entry.root = root;
+        self.write_keyspaces()
+    }
+
+
+    // 打开时读取键空间目录
+    pub(super) fn load_keyspaces(&mut self) -> Result<()> {
+        let start = self.header.keyspace_catalog;
+        if start == 0 {
+            return Ok(());
+        }
+        let data = self.read_overflow(start)?;
+        self.keyspaces = KeyspaceEntry::decode_all(&data, self.header.format_version)
+            .ok_or_else(|| anyhow!("Keyspace catalog is malformed"))?;
+        Ok(())
+    }
+
+
+    // 把目录整体写入一条新的溢出链，再释放旧链
+    pub(super) fn write_keyspaces(&mut self) -> Result<()> {
+        let old = self.header.keyspace_catalog;
+        self.header.keyspace_catalog = if self.keyspaces.is_empty() {
+            0
+        } else {
+            self.write_overflow(&KeyspaceEntry::encode_all(&self.keyspaces))?
+        };
+        self.free_overflow(old)
+    }
+
+
+    // 从根开始遍历索引树，收集节点页和叶子引用的数据页
+    fn tree_pages(&self, root: u32) -> Result<TreePages> {
+        let mut pages = TreePages::default();
+        let mut stack = Vec::new();
+        if root != 0 {
+            stack.push(root);
+        }
+
+        while let Some(page_num) = stack.pop() {
+            match Node::unpack(&self.read_page(page_num)?)? {
+                Node::Branch { children, spills, .. } => {
+                    pages.nodes.push((page_num, spills));
+                    stack.extend(children);
+                }
+                Node::Leaf { pages: data_pages, spills, .. } => {
+                    pages.keys += data_pages.len() as u64;
+                    pages.data_pages.extend(data_pages);
+                    pages.nodes.push((page_num, spills));
+                }
+            }
+        }
+        Ok(pages)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guard_falls_back_to_default_when_previous_is_dropped() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = KVStore::open(dir.path().join("ks.wkv"), None)?;
+        {
+            let mut a = store.keyspace("a")?;
+            {
+                let mut b = a.keyspace("b")?;
+                assert!(b.drop_keyspace("a")?);
+            }
+            assert_eq!(a.keyspace, DEFAULT_KEYSPACE_ID);
+            a.put(b"k", b"v")?;
+        }
+        assert_eq!(store.keyspace, DEFAULT_KEYSPACE_ID);
+        assert_eq!(store.get(b"k")?, Some(b"v".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn reads_other_keyspaces_without_switching() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = KVStore::open(dir.path().join("ks.wkv"), None)?;
+        store.put(b"k", b"default")?;
+        {
+            let mut users = store.keyspace("users")?;
+            users.put(b"k", br#"{"age": 42}"#)?;
+            users.put_with_ttl(b"t", b"v", Duration::from_secs(60))?;
+            users.create_index("by_age", "/age")?;
+        }
+
+        let store = &store;
+        assert_eq!(store.get_in("users", b"k")?, Some(br#"{"age": 42}"#.to_vec()));
+        assert_eq!(store.get_in(DEFAULT_KEYSPACE, b"k")?, Some(b"default".to_vec()));
+        assert!(store.get_with_version_in("users", b"k")?.is_some());
+        assert!(store.ttl_in("users", b"t")?.is_some());
+        assert_eq!(store.indexes_in("users")?.len(), 1);
+        assert!(store.indexes_in(DEFAULT_KEYSPACE)?.is_empty());
+        let found = store.find_by_index_range_in("users", "by_age", Value::from(40)..)?;
+        assert_eq!(found.len(), 1);
+        assert!(store.get_in("missing", b"k").is_err());
+        assert_eq!(store.current_keyspace(), DEFAULT_KEYSPACE);
+        Ok(())
+    }
+}
@@ -0,0 +1,630 @@
+// src/kvstore/salvage.rs
+// 抢救模式：不信任文件头和索引，直接扫描原始页，把能解码的键值对重建到一个新库中。
+//
+// 第一遍收集每个键出现在哪些数据页，并从残存的索引叶子中读出键 -> 页的对应关系，
+// 用来在同一个键出现在多个页时挑出正确的副本；第二遍逐页取值写入新库。
+// 键按页头记录的键空间区分，写入新库中同名的键空间。
+// 最后回放原库旁遗留的 WAL。
+// 文件头不可信时，页大小和条目格式都从页内容推断。
+use super::btree::Node;
+use super::keyspace::{KeyspaceEntry, DEFAULT_KEYSPACE_ID};
+use super::{
+    check_page_size, current_time_millis, decompress, overflow_threshold, parse_entries_as, verify_page,
+    Compression, DBHeader, EntrySpan, KVStore, PageHeader, StoredValue, WALManager, CHECKSUM_VERSION, ENTRY_FORMAT_VERSION,
+    FORMAT_VERSION, HEADER_SIZE, KEYSPACE_VERSION, MAX_PAGE_SIZE, MIN_PAGE_SIZE, PAGE_FLAG_FREE, PAGE_FLAG_INDEX_BRANCH,
+    PAGE_FLAG_INDEX_LEAF, PAGE_FLAG_OVERFLOW, PAGE_SIZE_VERSION, TTL_VERSION, VALUE_OVERFLOW_VERSION, WAL_FILE_EXT,
+};
+use crate::options::{KVStoreOptions, DEFAULT_PAGE_SIZE};
+use anyhow::{bail, Result};
+use log::{error, warn};
+use memmap2::MmapOptions;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+
+const KNOWN_FLAGS: u8 =
+    PAGE_FLAG_OVERFLOW | PAGE_FLAG_FREE | PAGE_FLAG_INDEX_LEAF | PAGE_FLAG_INDEX_BRANCH;
+
+
+// (键空间, 键) -> 出现该键的数据页
+type Locations = HashMap<(u16, Vec<u8>), Vec<u32>>;
+
+
+// 文件头不可信时猜测页大小：取开头若干页中校验和通过最多的候选值
+fn guess_page_size(data: &[u8]) -> usize {
+    const SAMPLE_PAGES: usize = 64;
+
+    let mut best = (0, DEFAULT_PAGE_SIZE);
+    let mut page_size = MIN_PAGE_SIZE;
+    while page_size <= MAX_PAGE_SIZE {
+        let valid = data
+            .get(HEADER_SIZE..)
+            .unwrap_or_default()
+            .chunks_exact(page_size)
+            .take(SAMPLE_PAGES)
+            .filter(|page| verify_page(page))
+            .count();
+        if valid > best.0 {
+            best = (valid, page_size);
+        }
+        page_size *= 2;
+    }
+    best.1
+}
+
+
+/// [`KVStore::salvage`] 的结果
+#[derive(Debug, Clone, Default)]
+pub struct SalvageReport {
+    /// 使用的页大小，文件头不可信时为猜测值
+    pub page_size: usize,
+    /// 扫描的物理页数（按文件长度计算，不依赖文件头）
+    pub pages_scanned: u32,
+    /// 写入新库的键数
+    pub recovered: u64,
+    /// 从 WAL 回放的操作数
+    pub wal_replayed: u64,
+    /// 无法恢复的内容，每条同时以 error 级别写入日志
+    pub lost: Vec<String>,
+}
+
+
+impl fmt::Display for SalvageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "scanned {} pages of {} bytes, recovered {} keys, replayed {} WAL operations",
+            self.pages_scanned, self.page_size, self.recovered, self.wal_replayed
+        )?;
+        if self.lost.is_empty() {
+            write!(f, "\nnothing was lost")
+        } else {
+            write!(f, "\n{} item(s) could not be recovered:", self.lost.len())?;
+            for item in &self.lost {
+                write!(f, "\n  - {}", item)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+
+struct Salvager<'a> {
+    data: &'a [u8],
+    page_size: usize,
+    page_count: u32,
+    // 文件头损坏时无法得知版本，按带校验和处理
+    checksums: bool,
+    // 按此格式版本解析条目与索引节点
+    version: u16,
+    // 已恢复条目中最大的版本号
+    max_version: u64,
+    // 键空间编号 -> 名称，来自可信文件头指向的目录
+    keyspace_names: HashMap<u16, String>,
+    // 目录中单独设置了压缩的键空间
+    keyspace_compression: HashMap<String, Compression>,
+    report: SalvageReport,
+}
+
+
+impl<'a> Salvager<'a> {
+    fn page(&self, page_num: u32) -> Option<&'a [u8]> {
+        if page_num == 0 || page_num > self.page_count {
+            return None;
+        }
+        let offset = HEADER_SIZE + (page_num - 1) as usize * self.page_size;
+        Some(&self.data[offset..offset + self.page_size])
+    }
+
+
+    fn lose(&mut self, message: String) {
+        error!("Salvage: {}", message);
+        self.report.lost.push(message);
+    }
+
+
+    // 页头看起来合理时返回页头和数据区，数据区长度截断到页容量
+    fn decode(&self, page_data: &'a [u8]) -> Option<(PageHeader, &'a [u8])> {
+        let header = PageHeader::unpack(page_data).ok()?;
+        if header.flags & !KNOWN_FLAGS != 0 {
+            return None;
+        }
+        let data_end = (PageHeader::SIZE + header.data_len as usize).min(page_data.len());
+        Some((header, &page_data[PageHeader::SIZE..data_end]))
+    }
+
+
+    fn entries(&self, data: &[u8], kv_count: u16) -> Vec<EntrySpan> {
+        parse_entries_as(data, kv_count, self.page_size, self.version)
+    }
+
+
+    // 文件头不可信时推断条目格式：取能完整解析最多数据页的格式，相同时取较新的格式
+    fn guess_version(&self) -> u16 {
+        // 版本 4 之前的条目格式都相同
+        let candidates = [
+            FORMAT_VERSION,
+            KEYSPACE_VERSION,
+            TTL_VERSION,
+            VALUE_OVERFLOW_VERSION,
+            ENTRY_FORMAT_VERSION,
+            PAGE_SIZE_VERSION,
+        ];
+        let mut votes = [0u32; 6];
+        for page_num in 1..=self.page_count {
+            let page_data = self.page(page_num).unwrap_or_default();
+            let (header, data) = match self.decode(page_data) {
+                Some(decoded) if decoded.0.is_data_page() && decoded.0.kv_count > 0 => decoded,
+                _ => continue,
+            };
+            for (version, votes) in candidates.iter().zip(votes.iter_mut()) {
+                let entries = parse_entries_as(data, header.kv_count, self.page_size, *version);
+                let clean = entries.len() == header.kv_count as usize
+                    && entries.last().map(EntrySpan::end) == Some(data.len());
+                *votes += clean as u32;
+            }
+        }
+
+        let mut best = 0;
+        for i in 1..candidates.len() {
+            if votes[i] > votes[best] {
+                best = i;
+            }
+        }
+        candidates[best]
+    }
+
+
+    // 补全溢出的键，溢出链不可用时返回 None
+    fn full_key(&self, data: &[u8], entry: &EntrySpan) -> Option<Vec<u8>> {
+        let mut key = data[entry.key.clone()].to_vec();
+        if entry.key_overflow != 0 {
+            key.extend(self.read_overflow(entry.key_overflow)?);
+        }
+        Some(key)
+    }
+
+
+    // 第一遍：键 -> 出现的数据页，以及残存索引叶子中记录的键 -> 页
+    fn collect_keys(&mut self) -> (Locations, HashMap<(u16, Vec<u8>), u32>) {
+        let mut locations = Locations::new();
+        let mut hints = HashMap::new();
+
+        for page_num in 1..=self.page_count {
+            let page_data = self.page(page_num).unwrap_or_default();
+            let (header, data) = match self.decode(page_data) {
+                Some(decoded) => decoded,
+                None => {
+                    self.lose(format!("page {}: unrecognizable page header", page_num));
+                    continue;
+                }
+            };
+
+            if header.flags & PAGE_FLAG_INDEX_LEAF != 0 {
+                if self.checksums && !verify_page(page_data) {
+                    continue;
+                }
+                let node = if self.version >= ENTRY_FORMAT_VERSION {
+                    Node::unpack(page_data)
+                } else {
+                    Node::unpack_legacy(page_data)
+                };
+                if let Ok(Node::Leaf { keys, pages, spills }) = node {
+                    for ((mut key, page), spill) in keys.into_iter().zip(pages).zip(spills) {
+                        if spill != 0 {
+                            match self.read_overflow(spill) {
+                                Some(rest) => key.extend(rest),
+                                None => continue,
+                            }
+                        }
+                        hints.insert((header.keyspace, key), page);
+                    }
+                }
+                continue;
+            }
+            if !header.is_data_page() {
+                continue;
+            }
+
+            if self.checksums && !verify_page(page_data) {
+                warn!("Salvage: page {} fails its checksum, decoding it anyway", page_num);
+            }
+            let entries = self.entries(data, header.kv_count);
+            if entries.len() < header.kv_count as usize {
+                let missing = header.kv_count as usize - entries.len();
+                self.lose(format!("page {}: {} malformed entries", page_num, missing));
+            }
+            for entry in entries {
+                // 空键为内部保留，出现即说明条目已损坏
+                if entry.key_len == 0 {
+                    continue;
+                }
+                match self.full_key(data, &entry) {
+                    Some(key) => locations
+                        .entry((header.keyspace, key))
+                        .or_default()
+                        .push(page_num),
+                    None => self.lose(format!(
+                        "page {}: key {:?}... lost its overflow chain",
+                        page_num,
+                        String::from_utf8_lossy(&data[entry.key])
+                    )),
+                }
+            }
+        }
+
+        (locations, hints)
+    }
+
+
+    // 同一个键出现在多个页时，优先索引指向的页，其次页号最大的页
+    fn choose(
+        locations: Locations,
+        hints: &HashMap<(u16, Vec<u8>), u32>,
+    ) -> HashMap<(u16, Vec<u8>), u32> {
+        locations
+            .into_iter()
+            .map(|(key, pages)| {
+                let page = hints
+                    .get(&key)
+                    .copied()
+                    .filter(|hint| pages.contains(hint))
+                    .unwrap_or_else(|| pages.iter().copied().max().unwrap_or_default());
+                (key, page)
+            })
+            .collect()
+    }
+
+
+    // 读取完整的溢出链，链断裂、成环或页校验失败时返回 None
+    fn read_overflow(&self, start: u32) -> Option<Vec<u8>> {
+        let mut value = Vec::new();
+        let mut visited = HashSet::new();
+        let mut current = start;
+
+        while current != 0 {
+            if !visited.insert(current) {
+                return None;
+            }
+            let page_data = self.page(current)?;
+            if self.checksums && !verify_page(page_data) {
+                return None;
+            }
+            let (header, data) = self.decode(page_data)?;
+            if header.flags & PAGE_FLAG_OVERFLOW == 0 || header.kv_count != 0 {
+                return None;
+            }
+            value.extend_from_slice(data);
+            current = header.next_page;
+        }
+        Some(value)
+    }
+
+
+    // WAL 中流式写入的值只有原文件中溢出链的位置
+    fn replay_blob(&mut self, store: &mut KVStore, key: &[u8], blob_ref: &[u8]) -> Result<()> {
+        let value = match WALManager::decode_blob_ref(blob_ref)? {
+            StoredValue::Overflow { len, start } => self
+                .read_overflow(start)
+                .filter(|value| value.len() == len),
+            StoredValue::Inline(_) => None,
+        };
+        match value {
+            Some(value) => store.put_internal(key, &value),
+            None => {
+                self.lose(format!(
+                    "WAL blob for key {:?}: overflow chain is broken",
+                    String::from_utf8_lossy(key)
+                ));
+                Ok(())
+            }
+        }
+    }
+
+
+    // 读取可信文件头指向的键空间目录
+    fn load_keyspaces(&mut self, catalog: u32) {
+        if catalog == 0 {
+            return;
+        }
+        match self
+            .read_overflow(catalog)
+            .and_then(|data| KeyspaceEntry::decode_all(&data, self.version))
+        {
+            Some(entries) => {
+                for entry in entries {
+                    if let Some(compression) = entry.compression {
+                        self.keyspace_compression.insert(entry.name.clone(), compression);
+                    }
+                    self.keyspace_names.insert(entry.id, entry.name);
+                }
+            }
+            None => warn!("Salvage: keyspace catalog at page {} is unreadable", catalog),
+        }
+    }
+
+
+    // 目录中找不到的键空间按编号命名
+    fn keyspace_name(&self, id: u16) -> String {
+        self.keyspace_names
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| format!("keyspace_{}", id))
+    }
+
+
+    // 第二遍：把每个键选中的副本写入新库
+    fn rebuild(&mut self, chosen: &HashMap<(u16, Vec<u8>), u32>, store: &mut KVStore) -> Result<()> {
+        // 目录中的键空间即使没有恢复出任何键也保留
+        let mut names: Vec<String> = self.keyspace_names.values().cloned().collect();
+        names.sort();
+        for name in names {
+            let id = store.create_keyspace(&name)?;
+            if let Some(compression) = self.keyspace_compression.get(&name) {
+                store.in_keyspace(id, |store| store.set_compression_internal(*compression))?;
+            }
+        }
+
+        for page_num in 1..=self.page_count {
+            let page_data = self.page(page_num).unwrap_or_default();
+            let (header, data) = match self.decode(page_data) {
+                Some(decoded) if decoded.0.is_data_page() => decoded,
+                _ => continue,
+            };
+            let keyspace = match header.keyspace {
+                DEFAULT_KEYSPACE_ID => DEFAULT_KEYSPACE_ID,
+                id => store.create_keyspace(&self.keyspace_name(id))?,
+            };
+            store.select_keyspace(keyspace);
+
+            for entry in self.entries(data, header.kv_count) {
+                let location = match self.full_key(data, &entry) {
+                    Some(key) => (header.keyspace, key),
+                    None => continue,
+                };
+                if chosen.get(&location) != Some(&page_num) {
+                    continue;
+                }
+                let key = location.1.as_slice();
+
+                let mut value = data[entry.value.clone()].to_vec();
+                // 旧格式的页级溢出链属于页中截断到阈值长度的值
+                let threshold = overflow_threshold(self.page_size);
+                let chain = if entry.value_overflow != 0 {
+                    entry.value_overflow
+                } else if header.flags & PAGE_FLAG_OVERFLOW != 0 && value.len() == threshold {
+                    header.next_page
+                } else {
+                    0
+                };
+                if chain != 0 {
+                    let rest = self.read_overflow(chain).filter(|rest| {
+                        entry.value_overflow == 0 || rest.len() == entry.value_len
+                    });
+                    match rest {
+                        Some(rest) => value.extend_from_slice(&rest),
+                        None => {
+                            self.lose(format!(
+                                "key {:?} in page {}: overflow chain is broken",
+                                String::from_utf8_lossy(key),
+                                page_num
+                            ));
+                            continue;
+                        }
+                    }
+                }
+
+                if entry.compressed {
+                    match decompress(&value) {
+                        Ok(raw) => value = raw,
+                        Err(e) => {
+                            self.lose(format!(
+                                "key {:?} in page {}: {}",
+                                String::from_utf8_lossy(key),
+                                page_num,
+                                e
+                            ));
+                            continue;
+                        }
+                    }
+                }
+
+                // 已过期的键不再恢复，未过期的保留原来的过期时间和版本号
+                if entry.expires_at.is_some_and(|expires_at| expires_at <= current_time_millis()) {
+                    continue;
+                }
+                store.put_value(key, &value, entry.meta())?;
+                self.max_version = self.max_version.max(entry.version);
+                self.report.recovered += 1;
+            }
+        }
+        store.select_keyspace(DEFAULT_KEYSPACE_ID);
+        Ok(())
+    }
+}
+
+
+impl KVStore {
+    /// 从可能已损坏的数据库文件中抢救数据，重建到 `output` 处的新库
+    ///
+    /// 不依赖文件头和索引：逐个物理页解码键值对，只在溢出链完整时取回大值，
+    /// 然后回放原库旁遗留的 WAL。原文件和它的 WAL 不会被修改。
+    /// `output` 必须不存在。无法恢复的内容记录在返回的报告中并写入日志。
+    pub fn salvage<P: AsRef<Path>, Q: AsRef<Path>>(damaged: P, output: Q) -> Result<SalvageReport> {
+        let damaged = damaged.as_ref();
+        let output = output.as_ref();
+        if output.exists() {
+            bail!("Salvage output {} already exists", output.display());
+        }
+
+        let file = File::open(damaged)?;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            bail!("Database file is empty");
+        }
+        let mmap = unsafe { MmapOptions::new().map(&file)? };
+        let data: &[u8] = &mmap;
+
+        // 文件头可读时沿用其标识符和版本
+        let header = data
+            .get(..HEADER_SIZE)
+            .and_then(|bytes| DBHeader::unpack_unchecked(bytes).ok());
+        if header.is_none() {
+            error!("Salvage: file header of {} is unreadable", damaged.display());
+        }
+
+        // 文件头校验通过时才相信其中的页大小和格式版本
+        let trusted = header.as_ref().filter(|header| {
+            header.format_version < CHECKSUM_VERSION || DBHeader::checksum_ok(&data[..HEADER_SIZE])
+        });
+        let applied_lsn = trusted.map_or(0, |header| header.applied_lsn);
+        let trusted_page_size = trusted
+            .map(|header| header.page_size as usize)
+            .filter(|page_size| check_page_size(*page_size).is_ok());
+        let page_size = match trusted_page_size {
+            Some(page_size) => page_size,
+            None => {
+                let page_size = guess_page_size(data);
+                warn!("Salvage: page size unknown, guessed {} bytes", page_size);
+                page_size
+            }
+        };
+
+        let mut options = KVStoreOptions::new(output).page_size(page_size);
+        if let Some(header) = &header {
+            options = options.identifier(&header.db_identifier);
+        }
+        if let Some(header) = trusted {
+            options = options.compression(header.compression);
+        }
+
+        let mut salvager = Salvager {
+            data,
+            page_size,
+            page_count: (len.saturating_sub(HEADER_SIZE) / page_size) as u32,
+            checksums: header
+                .as_ref()
+                .is_none_or(|header| header.format_version >= CHECKSUM_VERSION),
+            version: FORMAT_VERSION,
+            max_version: 0,
+            keyspace_names: HashMap::new(),
+            keyspace_compression: HashMap::new(),
+            report: SalvageReport::default(),
+        };
+        salvager.version = match trusted {
+            Some(header) => header.format_version,
+            None => salvager.guess_version(),
+        };
+        salvager.report.page_size = page_size;
+        salvager.report.pages_scanned = salvager.page_count;
+        if let Some(header) = trusted.filter(|header| header.format_version >= KEYSPACE_VERSION) {
+            salvager.load_keyspaces(header.keyspace_catalog);
+        }
+
+        let (locations, hints) = salvager.collect_keys();
+        let chosen = Salvager::choose(locations, &hints);
+
+        let mut store = KVStore::create_new_db(output, &options)?;
+        salvager.rebuild(&chosen, &mut store)?;
+        // 索引条目可能与恢复出的数据不一致，按数据重建，之后重放的 WAL 照常维护
+        store.rebuild_indexes()?;
+
+        let wal_path = damaged.with_extension(WAL_FILE_EXT);
+        if wal_path.exists() {
+            let (records, _) = WALManager::read_committed(&wal_path, applied_lsn)?;
+            for (lsn, (op_type, key, value)) in records {
+                store.wal_manager.last_lsn = lsn;
+                if op_type == WALManager::OP_PUT_BLOB {
+                    // 引用的是原文件中的溢出链
+                    salvager.replay_blob(&mut store, &key, &value)?;
+                    store.keyspace = DEFAULT_KEYSPACE_ID;
+                } else {
+                    WALManager::apply(&mut store, op_type, &key, &value);
+                }
+                salvager.report.wal_replayed += 1;
+            }
+        }
+
+        // 之后的写入从所有已恢复的版本号之后开始编号
+        store.wal_manager.last_lsn = store.wal_manager.last_lsn.max(applied_lsn).max(salvager.max_version);
+        store.close()?;
+        Ok(salvager.report)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::KvError;
+
+    const KEYS: usize = 200;
+
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:04}", i).into_bytes()
+    }
+
+
+    // 翻转第一个非空数据页中的一个字节，返回该页页号
+    fn corrupt_data_page(path: &Path) -> u32 {
+        let mut data = std::fs::read(path).unwrap();
+        let mut page_num = 1;
+        let mut offset = HEADER_SIZE;
+        while offset + DEFAULT_PAGE_SIZE <= data.len() {
+            let header = PageHeader::unpack(&data[offset..]).unwrap();
+            if header.is_data_page() && header.kv_count > 0 {
+                data[offset + PageHeader::SIZE + 1] ^= 0xff;
+                std::fs::write(path, data).unwrap();
+                return page_num;
+            }
+            page_num += 1;
+            offset += DEFAULT_PAGE_SIZE;
+        }
+        panic!("no data page found");
+    }
+
+
+    #[test]
+    fn detects_checksum_mismatch_and_salvages_other_pages() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("damaged.db");
+        let mut store = KVStore::open(&path, None)?;
+        for i in 0..KEYS {
+            store.put(&key(i), &[b'v'; 64])?;
+        }
+        store.close()?;
+        let page = corrupt_data_page(&path);
+
+        let store = KVStore::open(&path, None)?;
+        let mut intact = Vec::new();
+        for i in 0..KEYS {
+            match store.get(&key(i)) {
+                Ok(value) => {
+                    assert_eq!(value, Some(vec![b'v'; 64]));
+                    intact.push(i);
+                }
+                Err(e) => {
+                    assert!(matches!(e.downcast_ref::<KvError>(), Some(KvError::Corruption { page: p }) if *p == page));
+                }
+            }
+        }
+        assert!(intact.len() < KEYS);
+        assert!(!store.verify()?.is_ok());
+        drop(store);
+
+        let output = dir.path().join("salvaged.db");
+        // 校验失败的页仍会尽量解码，其余页的键必须原样恢复
+        let report = KVStore::salvage(&path, &output)?;
+        assert!(report.recovered as usize >= intact.len());
+
+        let salvaged = KVStore::open(&output, None)?;
+        assert!(salvaged.verify()?.is_ok());
+        for i in intact {
+            assert_eq!(salvaged.get(&key(i))?, Some(vec![b'v'; 64]));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,637 @@
+// src/kvstore/secondary.rs
+// 二级索引：按 JSON 指针从值中取出一个字段，按字段值查找键。
+//
+// 每个索引的条目存放在一个隐藏键空间中，条目的键为 编码后的字段值 | 主键，值为空。
+// 字段值的编码保持 null < false < true < 数字 < 字符串 的顺序，等值和区间查询都只是一次区间扫描。
+// 索引定义保存在隐藏键空间 INDEX_CATALOG 中，键为 所属键空间名 \0 索引名，值为 JSON 指针。
+// 条目不单独记录 WAL：写入和删除应用到数据页时同步修改索引，重放 WAL 时也一样，
+// 所以索引与数据随同一条记录（或同一个事务）一起生效。
+// 崩溃时页面可能只有一部分落盘，索引中可能残留过期的条目，查询时会按当前的值再核对一次。
+use super::cursor::KVCursor;
+use super::keyspace::HIDDEN_KEYSPACE_PREFIX;
+use super::{prefix_end, prefix_range, KVStore, PageHeader, WALManager, INDEX_NUMBER_VERSION};
+use anyhow::{anyhow, bail, Result};
+use log::warn;
+use serde_json::Value;
+use std::mem;
+use std::ops::{Bound, RangeBounds};
+
+
+const INDEX_CATALOG: &str = "\u{1}indexes";
+const MAX_INDEX_NAME: usize = 64; // 索引名称的最大字节数
+// 超过该长度的值（如大对象）不建立索引，避免每次写入都把它整体读入内存
+const MAX_INDEXED_VALUE: usize = 1 << 20;
+
+// 字段值的类型标记，决定不同类型之间的顺序
+const TAG_NULL: u8 = 1;
+const TAG_FALSE: u8 = 2;
+const TAG_TRUE: u8 = 3;
+const TAG_NUMBER: u8 = 4;
+const TAG_STRING: u8 = 5;
+
+
+// 已打开的索引
+#[derive(Debug, Clone)]
+pub(super) struct IndexDef {
+    keyspace: u16, // 被索引的键空间
+    name: String,
+    pointer: String,
+    entries: u16, // 保存索引条目的隐藏键空间
+}
+
+
+/// 二级索引的定义，由 [`KVStore::indexes`] 返回
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexInfo {
+    pub name: String,
+    /// 被索引字段的 JSON 指针（RFC 6901），如 `/status`
+    pub pointer: String,
+}
+
+
+fn check_index_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_INDEX_NAME || name.chars().any(char::is_control) {
+        bail!(
+            "Invalid index name {:?}, expected 1 to {} bytes without control characters",
+            name,
+            MAX_INDEX_NAME
+        );
+    }
+    Ok(())
+}
+
+
+fn check_pointer(pointer: &str) -> Result<()> {
+    if !pointer.starts_with('/') {
+        bail!("Invalid JSON pointer {:?}, expected a path such as /status", pointer);
+    }
+    Ok(())
+}
+
+
+fn entries_keyspace_name(owner: &str, name: &str) -> String {
+    format!("{0}index{0}{1}{0}{2}", HIDDEN_KEYSPACE_PREFIX, owner, name)
+}
+
+
+fn catalog_key(owner: &str, name: &str) -> Vec<u8> {
+    format!("{}\0{}", owner, name).into_bytes()
+}
+
+
+// 编码可被索引的字段值，对象和数组返回 None
+fn encode_value(value: &Value) -> Option<Vec<u8>> {
+    let mut buf = Vec::new();
+    match value {
+        Value::Null => buf.push(TAG_NULL),
+        Value::Bool(false) => buf.push(TAG_FALSE),
+        Value::Bool(true) => buf.push(TAG_TRUE),
+        Value::Number(number) => {
+            // 数字先按 f64 比较：非负数置符号位，负数翻转所有位，按字节比较即为数值顺序
+            let float = number.as_f64()? + 0.0; // -0 与 0 相同
+            let bits = float.to_bits();
+            let bits = if float < 0.0 { !bits } else { bits | 1 << 63 };
+            buf.push(TAG_NUMBER);
+            buf.extend_from_slice(&bits.to_be_bytes());
+            // 超过 2^53 的整数转成 f64 后可能相同，再附上精确的整数值区分；
+            // 值为整数的浮点数与相等的整数编码相同，其余浮点数附 0
+            let exact = match (number.as_i64(), number.as_u64()) {
+                (Some(int), _) => int as i128,
+                (None, Some(int)) => int as i128,
+                _ if float.fract() == 0.0 => float as i128,
+                _ => 0,
+            };
+            buf.extend_from_slice(&((exact as u128) ^ 1 << 127).to_be_bytes());
+        }
+        Value::String(text) => {
+            // 0x00 转义为 0x00 0x01，以 0x00 0x00 结尾，保证一个字符串是另一个的前缀时顺序不变
+            buf.push(TAG_STRING);
+            for &byte in text.as_bytes() {
+                buf.push(byte);
+                if byte == 0 {
+                    buf.push(1);
+                }
+            }
+            buf.extend_from_slice(&[0, 0]);
+        }
+        Value::Array(_) | Value::Object(_) => return None,
+    }
+    Some(buf)
+}
+
+
+fn encode_query(value: &Value) -> Result<Vec<u8>> {
+    encode_value(value).ok_or_else(|| anyhow!("Only null, booleans, numbers and strings can be looked up in an index"))
+}
+
+
+// 索引条目中字段值编码的长度
+fn encoded_len(entry: &[u8]) -> Option<usize> {
+    match *entry.first()? {
+        TAG_NULL | TAG_FALSE | TAG_TRUE => Some(1),
+        TAG_NUMBER => Some(25),
+        TAG_STRING => {
+            let mut pos = 1;
+            loop {
+                match entry.get(pos..pos + 2)? {
+                    [0, 0] => return Some(pos + 2),
+                    [0, _] => pos += 2,
+                    _ => pos += 1,
+                }
+            }
+        }
+        _ => None,
+    }
+}
+
+
+fn entry_key(field: &[u8], key: &[u8]) -> Vec<u8> {
+    [field, key].concat()
+}
+
+
+// 按 JSON 解析值，不是 JSON 的值不被索引
+fn parse_document(value: Option<&[u8]>) -> Option<Value> {
+    serde_json::from_slice(value?).ok()
+}
+
+
+fn indexed_field(document: Option<&Value>, pointer: &str) -> Option<Vec<u8>> {
+    encode_value(document?.pointer(pointer)?)
+}
+
+
+impl KVStore {
+    /// 在当前键空间上创建二级索引，按 JSON 指针 `pointer` 处的字段查找键
+    ///
+    /// 创建时为已有的键建立索引，之后的写入和删除会在同一次写入中维护它。
+    /// 值不是 JSON、字段不存在或字段是对象和数组的键不会出现在索引中。
+    /// 同名索引已存在时返回 false，指针不同则报错。
+    ///
+    /// ```no_run
+    /// # let mut store = wind_kvstore::KVStore::open("app.wkv", None)?;
+    /// store.create_index("by_status", "/status")?;
+    /// store.put(b"ticket:1", br#"{"status": "open"}"#)?;
+    /// store.put(b"ticket:2", br#"{"status": "closed"}"#)?;
+    /// let open = store.find_by_index("by_status", &"open".into())?;
+    /// assert_eq!(open[0].0, b"ticket:1");
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn create_index(&mut self, name: &str, pointer: &str) -> Result<bool> {
+        self.check_writable()?;
+        check_index_name(name)?;
+        check_pointer(pointer)?;
+        if let Some(index) = self.find_index(name) {
+            if index.pointer == pointer {
+                return Ok(false);
+            }
+            bail!("Index {} already exists on {}", name, index.pointer);
+        }
+        self.wal_manager
+            .log_operation(WALManager::OP_CREATE_INDEX, name.as_bytes(), Some(pointer.as_bytes()))?;
+        self.create_index_internal(name, pointer)?;
+        self.finish_write()?;
+        Ok(true)
+    }
+
+
+    /// 删除当前键空间上的二级索引，不存在时返回 false
+    pub fn drop_index(&mut self, name: &str) -> Result<bool> {
+        self.check_writable()?;
+        if self.find_index(name).is_none() {
+            return Ok(false);
+        }
+        self.wal_manager
+            .log_operation(WALManager::OP_DROP_INDEX, name.as_bytes(), None)?;
+        self.drop_index_internal(name)?;
+        self.finish_write()?;
+        Ok(true)
+    }
+
+
+    /// 当前键空间上的二级索引，按名称排序
+    pub fn indexes(&self) -> Vec<IndexInfo> {
+        self.indexes_at(self.keyspace)
+    }
+
+
+    pub(super) fn indexes_at(&self, id: u16) -> Vec<IndexInfo> {
+        let mut indexes: Vec<IndexInfo> = self
+            .indexes
+            .iter()
+            .filter(|index| index.keyspace == id)
+            .map(|index| IndexInfo {
+                name: index.name.clone(),
+                pointer: index.pointer.clone(),
+            })
+            .collect();
+        indexes.sort_by(|a, b| a.name.cmp(&b.name));
+        indexes
+    }
+
+
+    /// 按索引字段等于 `value` 查找键值对，按键排序
+    pub fn find_by_index(&self, name: &str, value: &Value) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let field = encode_query(value)?;
+        let end = prefix_end(&field);
+        self.scan_index(self.keyspace, name, prefix_range(&field, &end))
+    }
+
+
+    /// 按索引字段落在 `range` 内查找键值对，按字段值排序，字段值相同时按键排序
+    ///
+    /// 不同类型的字段值按 null < false < true < 数字 < 字符串 排序，数字按数值、字符串按字节比较。
+    ///
+    /// ```no_run
+    /// # let mut store = wind_kvstore::KVStore::open("app.wkv", None)?;
+    /// use serde_json::json;
+    /// store.create_index("by_age", "/age")?;
+    /// let adults = store.find_by_index_range("by_age", json!(18)..)?;
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn find_by_index_range<R: RangeBounds<Value>>(
+        &self,
+        name: &str,
+        range: R,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.find_by_index_range_at(self.keyspace, name, range)
+    }
+
+
+    pub(super) fn find_by_index_range_at<R: RangeBounds<Value>>(
+        &self,
+        id: u16,
+        name: &str,
+        range: R,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        // 条目的键以字段值编码开头，排除某个字段值要越过以它开头的所有条目
+        let lower = match range.start_bound() {
+            Bound::Included(value) => Bound::Included(encode_query(value)?),
+            Bound::Excluded(value) => match prefix_end(&encode_query(value)?) {
+                Some(end) => Bound::Included(end),
+                None => return Ok(Vec::new()),
+            },
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let upper = match range.end_bound() {
+            Bound::Included(value) => match prefix_end(&encode_query(value)?) {
+                Some(end) => Bound::Excluded(end),
+                None => Bound::Unbounded,
+            },
+            Bound::Excluded(value) => Bound::Excluded(encode_query(value)?),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        self.scan_index(id, name, (lower.as_ref().map(Vec::as_slice), upper.as_ref().map(Vec::as_slice)))
+    }
+
+
+    // 扫描键空间 id 上的索引条目，用键当前的值核对后返回
+    fn scan_index(
+        &self,
+        id: u16,
+        name: &str,
+        range: (Bound<&[u8]>, Bound<&[u8]>),
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let index = self
+            .find_index_at(id, name)
+            .ok_or_else(|| anyhow!("Index {} not found in keyspace {}", name, self.keyspace_name(id).unwrap_or_default()))?;
+
+        let mut found = Vec::new();
+        for entry in KVCursor::with_root(self, self.tree_root(index.entries), range, false)? {
+            let (entry, _) = entry?;
+            let len = encoded_len(&entry).ok_or_else(|| anyhow!("Index {} has a malformed entry", name))?;
+            let (field, key) = entry.split_at(len);
+            if key.is_empty() {
+                continue;
+            }
+            // 跳过崩溃后残留的条目：键已删除、已过期或字段已改变
+            let value = match self.get_at(id, key)? {
+                Some(value) => value,
+                None => continue,
+            };
+            if indexed_field(parse_document(Some(&value)).as_ref(), &index.pointer).as_deref() == Some(field) {
+                found.push((key.to_vec(), value));
+            }
+        }
+        Ok(found)
+    }
+
+
+    fn find_index(&self, name: &str) -> Option<&IndexDef> {
+        self.find_index_at(self.keyspace, name)
+    }
+
+
+    fn find_index_at(&self, id: u16, name: &str) -> Option<&IndexDef> {
+        self.indexes
+            .iter()
+            .find(|index| index.keyspace == id && index.name == name)
+    }
+
+
+    // 在当前键空间上创建索引并为已有的键建立条目，已存在时返回 false
+    pub(super) fn create_index_internal(&mut self, name: &str, pointer: &str) -> Result<bool> {
+        if self.find_index(name).is_some() {
+            return Ok(false);
+        }
+        let owner = self.current_keyspace().to_string();
+        let catalog = self.create_keyspace(INDEX_CATALOG)?;
+        let entries = self.create_keyspace(&entries_keyspace_name(&owner, name))?;
+        self.in_keyspace(catalog, |store| {
+            store.put_internal(&catalog_key(&owner, name), pointer.as_bytes())
+        })?;
+
+        let index = IndexDef {
+            keyspace: self.keyspace,
+            name: name.to_string(),
+            pointer: pointer.to_string(),
+            entries,
+        };
+        self.backfill(&index)?;
+        self.indexes.push(index);
+        Ok(true)
+    }
+
+
+    // 为键空间中已有的键建立索引条目
+    fn backfill(&mut self, index: &IndexDef) -> Result<()> {
+        // 先收集条目再写入，游标遍历时不能修改 store
+        let entries = self.in_keyspace(index.keyspace, |store| {
+            let mut entries = Vec::new();
+            for entry in store.cursor()? {
+                let (key, value) = entry?;
+                if value.len() > MAX_INDEXED_VALUE {
+                    continue;
+                }
+                if let Some(field) = indexed_field(parse_document(Some(&value)).as_ref(), &index.pointer) {
+                    entries.push(entry_key(&field, &key));
+                }
+            }
+            Ok(entries)
+        })?;
+
+        self.in_keyspace(index.entries, |store| {
+            for entry in &entries {
+                store.put_internal(entry, &[])?;
+            }
+            Ok(())
+        })
+    }
+
+
+    // 按恢复出的数据重新建立所有索引的条目，抢救时使用
+    pub(super) fn rebuild_indexes(&mut self) -> Result<()> {
+        self.load_indexes()?;
+        for index in self.indexes.clone() {
+            if let Some(entries) = self.keyspace_name(index.entries).map(str::to_string) {
+                self.clear_keyspace_internal(&entries)?;
+            }
+            self.backfill(&index)?;
+        }
+        Ok(())
+    }
+
+
+    // 旧文件中的整数按 f64 编码，升级时按新编码重建所有索引
+    pub(super) fn upgrade_index_numbers(&mut self) -> Result<()> {
+        self.rebuild_indexes()?;
+        self.header.format_version = INDEX_NUMBER_VERSION;
+        self.sync()
+    }
+
+
+    pub(super) fn drop_index_internal(&mut self, name: &str) -> Result<bool> {
+        let keyspace = self.keyspace;
+        let position = match self
+            .indexes
+            .iter()
+            .position(|index| index.keyspace == keyspace && index.name == name)
+        {
+            Some(position) => position,
+            None => return Ok(false),
+        };
+        let index = self.indexes.remove(position);
+        self.remove_index(&index)?;
+        Ok(true)
+    }
+
+
+    // 删除键空间 id 上的所有索引，键空间被删除前调用
+    pub(super) fn drop_indexes_of(&mut self, id: u16) -> Result<()> {
+        let (dropped, kept) = mem::take(&mut self.indexes)
+            .into_iter()
+            .partition::<Vec<_>, _>(|index| index.keyspace == id);
+        self.indexes = kept;
+        for index in &dropped {
+            self.remove_index(index)?;
+        }
+        Ok(())
+    }
+
+
+    // 清空键空间 id 上所有索引的条目，索引定义保留
+    pub(super) fn clear_indexes_of(&mut self, id: u16) -> Result<()> {
+        let entries: Vec<String> = self
+            .indexes
+            .iter()
+            .filter(|index| index.keyspace == id)
+            .filter_map(|index| self.keyspace_name(index.entries).map(str::to_string))
+            .collect();
+        for name in entries {
+            self.clear_keyspace_internal(&name)?;
+        }
+        Ok(())
+    }
+
+
+    // 删除索引的目录项和条目
+    fn remove_index(&mut self, index: &IndexDef) -> Result<()> {
+        let owner = self
+            .keyspace_name(index.keyspace)
+            .ok_or_else(|| anyhow!("Keyspace {} no longer exists", index.keyspace))?
+            .to_string();
+        if let Some(catalog) = self.keyspace_id(INDEX_CATALOG) {
+            self.in_keyspace(catalog, |store| store.delete_internal(&catalog_key(&owner, &index.name)))?;
+        }
+        self.drop_keyspace_internal(&entries_keyspace_name(&owner, &index.name))?;
+        Ok(())
+    }
+
+
+    // 当前键空间有索引时，写入或删除键前后更新索引条目。old 和 new 为键原来和现在的值
+    pub(super) fn update_indexes(&mut self, key: &[u8], old: Option<&[u8]>, new: Option<&[u8]>) -> Result<()> {
+        let old = parse_document(old);
+        let new = parse_document(new);
+
+        let mut changes = Vec::new();
+        for index in self.indexes.iter().filter(|index| index.keyspace == self.keyspace) {
+            let before = indexed_field(old.as_ref(), &index.pointer);
+            let after = indexed_field(new.as_ref(), &index.pointer);
+            if before != after {
+                changes.push((index.entries, before, after));
+            }
+        }
+
+        for (entries, before, after) in changes {
+            self.in_keyspace(entries, |store| {
+                if let Some(field) = before {
+                    store.delete_internal(&entry_key(&field, key))?;
+                }
+                if let Some(field) = after {
+                    store.put_internal(&entry_key(&field, key), &[])?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+
+    pub(super) fn has_indexes(&self) -> bool {
+        self.indexes.iter().any(|index| index.keyspace == self.keyspace)
+    }
+
+
+    // 键当前保存的值，包括已过期的值；过长而不被索引的值返回 None
+    pub(super) fn indexed_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let page_num = match self.index_lookup(key)? {
+            Some(num) => num,
+            None => return Ok(None),
+        };
+        let page_data = self.read_page(page_num)?;
+        let header = PageHeader::unpack(&page_data)?;
+        let data = &page_data[PageHeader::SIZE..PageHeader::SIZE + header.data_len as usize];
+        let entry = match self.find_entry(data, header.kv_count, key)? {
+            Some(entry) if entry.value_len <= MAX_INDEXED_VALUE => entry,
+            _ => return Ok(None),
+        };
+        // 压缩的值解压后才知道原始长度
+        let value = self.entry_value(data, &entry)?;
+        Ok((value.len() <= MAX_INDEXED_VALUE).then_some(value))
+    }
+
+
+    // 打开时读取索引目录
+    pub(super) fn load_indexes(&mut self) -> Result<()> {
+        let catalog = match self.keyspace_id(INDEX_CATALOG) {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+
+        let mut indexes = Vec::new();
+        for entry in KVCursor::with_root(self, self.tree_root(catalog), .., false)? {
+            let (key, pointer) = entry?;
+            let definition = std::str::from_utf8(&key)
+                .ok()
+                .and_then(|key| key.split_once('\0'))
+                .zip(String::from_utf8(pointer).ok());
+            let ((owner, name), pointer) = match definition {
+                Some(definition) => definition,
+                None => {
+                    warn!("Ignoring malformed index definition {:?}", String::from_utf8_lossy(&key));
+                    continue;
+                }
+            };
+            match (self.keyspace_id(owner), self.keyspace_id(&entries_keyspace_name(owner, name))) {
+                (Some(keyspace), Some(entries)) => indexes.push(IndexDef {
+                    keyspace,
+                    name: name.to_string(),
+                    pointer,
+                    entries,
+                }),
+                _ => warn!("Ignoring index {} of missing keyspace {}", name, owner),
+            }
+        }
+        self.indexes = indexes;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+
+    // 索引的原始条目数，不经过按当前值核对的过滤
+    fn entry_count(store: &KVStore, name: &str) -> usize {
+        let index = store.find_index(name).unwrap();
+        KVCursor::with_root(store, store.tree_root(index.entries), (Bound::<&[u8]>::Unbounded, Bound::Unbounded), false)
+            .unwrap()
+            .map(|entry| entry.unwrap().0)
+            .filter(|entry| encoded_len(entry).is_some_and(|len| len < entry.len()))
+            .count()
+    }
+
+
+    fn keys(found: Vec<(Vec<u8>, Vec<u8>)>) -> Vec<Vec<u8>> {
+        found.into_iter().map(|(key, _)| key).collect()
+    }
+
+
+    #[test]
+    fn overwrite_and_delete_maintain_entries() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("index.db");
+        let mut store = KVStore::open(&path, None)?;
+        store.create_index("by_age", "/age")?;
+        store.put(b"a", br#"{"age": 30}"#)?;
+        store.put(b"b", br#"{"age": 40}"#)?;
+        store.put(b"c", br#"{"age": 30}"#)?;
+        assert_eq!(entry_count(&store, "by_age"), 3);
+        assert_eq!(keys(store.find_by_index("by_age", &json!(30))?), vec![b"a".to_vec(), b"c".to_vec()]);
+
+        // 字段改变时旧条目被替换
+        store.put(b"a", br#"{"age": 50}"#)?;
+        assert_eq!(entry_count(&store, "by_age"), 3);
+        assert_eq!(keys(store.find_by_index("by_age", &json!(30))?), vec![b"c".to_vec()]);
+        assert_eq!(keys(store.find_by_index_range("by_age", json!(45)..)?), vec![b"a".to_vec()]);
+
+        // 字段消失或键被删除时条目被移除
+        store.put(b"b", b"not json")?;
+        store.delete(b"c")?;
+        assert_eq!(entry_count(&store, "by_age"), 1);
+        assert!(store.find_by_index("by_age", &json!(40))?.is_empty());
+        assert!(store.find_by_index("by_age", &json!(30))?.is_empty());
+        store.close()?;
+
+        let store = KVStore::open(&path, None)?;
+        assert_eq!(entry_count(&store, "by_age"), 1);
+        assert_eq!(keys(store.find_by_index_range("by_age", ..)?), vec![b"a".to_vec()]);
+        assert!(store.verify()?.is_ok());
+        Ok(())
+    }
+
+
+    #[test]
+    fn large_integers_are_indexed_exactly() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = KVStore::open(dir.path().join("index.db"), None)?;
+        store.create_index("by_id", "/id")?;
+        // 2^53 与 2^53 + 1 转成 f64 后相同
+        store.put(b"a", br#"{"id": 9007199254740992}"#)?;
+        store.put(b"b", br#"{"id": 9007199254740993}"#)?;
+        store.put(b"c", br#"{"id": 18446744073709551615}"#)?;
+        store.put(b"d", br#"{"id": -9223372036854775808}"#)?;
+        store.put(b"e", br#"{"id": 1.5}"#)?;
+        store.put(b"f", br#"{"id": 2}"#)?;
+
+        assert_eq!(keys(store.find_by_index("by_id", &json!(9007199254740992u64))?), vec![b"a".to_vec()]);
+        assert_eq!(keys(store.find_by_index("by_id", &json!(9007199254740993u64))?), vec![b"b".to_vec()]);
+        assert_eq!(
+            keys(store.find_by_index_range("by_id", json!(9007199254740993u64)..)?),
+            vec![b"b".to_vec(), b"c".to_vec()]
+        );
+        assert_eq!(
+            keys(store.find_by_index_range("by_id", ..json!(9007199254740993u64))?),
+            vec![b"d".to_vec(), b"e".to_vec(), b"f".to_vec(), b"a".to_vec()]
+        );
+
+        // 整数与浮点数混合时仍按数值排序，值为整数的浮点数等于相同的整数
+        assert_eq!(keys(store.find_by_index_range("by_id", json!(1)..json!(2.5))?), vec![b"e".to_vec(), b"f".to_vec()]);
+        assert_eq!(keys(store.find_by_index("by_id", &json!(2.0))?), vec![b"f".to_vec()]);
+        assert!(store.verify()?.is_ok());
+        Ok(())
+    }
+}
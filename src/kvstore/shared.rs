@@ -0,0 +1,384 @@
+// src/kvstore/shared.rs
+// 可在线程间共享的句柄：读操作只需要 &KVStore，持读锁并行执行；写操作持写锁独占
+use super::{Compression, IndexInfo, KVStore, KeyspaceStats, Snapshot, Transaction};
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::ops::RangeBounds;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+
+/// 可克隆、`Send + Sync` 的 [`KVStore`] 句柄，由 [`KVStore::into_shared`] 创建
+///
+/// 克隆只增加引用计数，所有克隆指向同一个 store。`get`、`scan` 等读操作持读锁，
+/// 多个线程可以同时读取；写操作持写锁，同一时刻只有一个写者，并与读者互斥。
+/// 游标、流式读取等需要借用 store 的操作通过 [`SharedKVStore::read`] /
+/// [`SharedKVStore::write`] 取得锁后调用。
+/// 当前键空间也是所有克隆共用的，多个线程使用不同键空间时应使用
+/// [`SharedKVStore::with_keyspace`] 或 `*_in` 方法，不要调用 [`SharedKVStore::use_keyspace`]。
+/// 与 [`KVStore`] 一样，最后一个句柄释放时只释放文件锁而不提交，未提交的修改在下次打开时从 WAL 恢复；
+/// 需要提交时用 [`SharedKVStore::close`]。
+///
+/// ```no_run
+/// # let store = wind_kvstore::KVStore::open("app.wkv", None)?;
+/// let shared = store.into_shared();
+/// let reader = shared.clone();
+/// std::thread::spawn(move || reader.get(b"username"));
+/// shared.put(b"username", b"alice")?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct SharedKVStore {
+    inner: Arc<RwLock<KVStore>>,
+}
+
+
+impl SharedKVStore {
+    /// 取得读锁，锁存活期间可以调用 `KVStore` 的所有只读方法
+    pub fn read(&self) -> Result<RwLockReadGuard<'_, KVStore>> {
+        // 写操作中途 panic 时内存中的文件头和页面可能不一致，不再继续使用
+        self.inner.read().map_err(|_| anyhow!("KVStore lock poisoned"))
+    }
+
+
+    /// 取得写锁，锁存活期间其他线程的读写都会等待
+    pub fn write(&self) -> Result<RwLockWriteGuard<'_, KVStore>> {
+        self.inner.write().map_err(|_| anyhow!("KVStore lock poisoned"))
+    }
+
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.read()?.get(key)
+    }
+
+
+    /// 见 [`KVStore::get_in`]，只持读锁
+    pub fn get_in(&self, keyspace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.read()?.get_in(keyspace, key)
+    }
+
+
+    /// 见 [`KVStore::scan`]
+    pub fn scan<'a, R: RangeBounds<&'a [u8]>>(
+        &self,
+        range: R,
+        limit: Option<usize>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read()?.scan(range, limit)
+    }
+
+
+    /// 见 [`KVStore::scan_prefix`]
+    pub fn scan_prefix(
+        &self,
+        prefix: &[u8],
+        limit: Option<usize>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read()?.scan_prefix(prefix, limit)
+    }
+
+
+    /// 见 [`KVStore::snapshot`]，只在创建时短暂持有读锁，之后的写入不受影响
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        self.read()?.snapshot()
+    }
+
+
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write()?.put(key, value)
+    }
+
+
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.write()?.delete(key)
+    }
+
+
+    /// 见 [`KVStore::put_if_absent`]，检查和写入在同一把写锁内完成
+    pub fn put_if_absent(&self, key: &[u8], value: &[u8]) -> Result<bool> {
+        self.write()?.put_if_absent(key, value)
+    }
+
+
+    /// 见 [`KVStore::replace_if_present`]
+    pub fn replace_if_present(&self, key: &[u8], value: &[u8]) -> Result<bool> {
+        self.write()?.replace_if_present(key, value)
+    }
+
+
+    /// 见 [`KVStore::compare_and_swap`]
+    pub fn compare_and_swap(&self, key: &[u8], expected: Option<&[u8]>, new: &[u8]) -> Result<bool> {
+        self.write()?.compare_and_swap(key, expected, new)
+    }
+
+
+    /// 见 [`KVStore::version`]
+    pub fn version(&self, key: &[u8]) -> Result<Option<u64>> {
+        self.read()?.version(key)
+    }
+
+
+    /// 见 [`KVStore::get_with_version`]
+    pub fn get_with_version(&self, key: &[u8]) -> Result<Option<(Vec<u8>, u64)>> {
+        self.read()?.get_with_version(key)
+    }
+
+
+    /// 见 [`KVStore::put_if_version`]
+    pub fn put_if_version(&self, key: &[u8], version: u64, value: &[u8]) -> Result<bool> {
+        self.write()?.put_if_version(key, version, value)
+    }
+
+
+    /// 见 [`KVStore::incr_by`]
+    pub fn incr_by(&self, key: &[u8], delta: i64) -> Result<i64> {
+        self.write()?.incr_by(key, delta)
+    }
+
+
+    /// 见 [`KVStore::decr_by`]
+    pub fn decr_by(&self, key: &[u8], delta: i64) -> Result<i64> {
+        self.write()?.decr_by(key, delta)
+    }
+
+
+    /// 见 [`KVStore::incr_by_float`]
+    pub fn incr_by_float(&self, key: &[u8], delta: f64) -> Result<f64> {
+        self.write()?.incr_by_float(key, delta)
+    }
+
+
+    /// 见 [`KVStore::decr_by_float`]
+    pub fn decr_by_float(&self, key: &[u8], delta: f64) -> Result<f64> {
+        self.write()?.decr_by_float(key, delta)
+    }
+
+
+    /// 见 [`KVStore::put_with_ttl`]
+    pub fn put_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        self.write()?.put_with_ttl(key, value, ttl)
+    }
+
+
+    /// 见 [`KVStore::expire`]
+    pub fn expire(&self, key: &[u8], ttl: Duration) -> Result<bool> {
+        self.write()?.expire(key, ttl)
+    }
+
+
+    /// 见 [`KVStore::persist`]
+    pub fn persist(&self, key: &[u8]) -> Result<bool> {
+        self.write()?.persist(key)
+    }
+
+
+    /// 见 [`KVStore::ttl`]
+    pub fn ttl(&self, key: &[u8]) -> Result<Option<Duration>> {
+        self.read()?.ttl(key)
+    }
+
+
+    /// 见 [`KVStore::purge_expired`]
+    pub fn purge_expired(&self) -> Result<usize> {
+        self.write()?.purge_expired()
+    }
+
+
+    /// 在写锁内执行一个事务，`f` 返回 `Ok` 时提交，返回错误时回滚
+    pub fn transaction<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Transaction<'_>) -> Result<T>,
+    {
+        let mut store = self.write()?;
+        let mut txn = store.transaction();
+        let result = f(&mut txn)?;
+        txn.commit()?;
+        Ok(result)
+    }
+
+
+    /// 在写锁内切换到键空间 `name`（不存在时创建）执行 `f`，结束后切换回原来的键空间
+    ///
+    /// 切换键空间会修改 store 的状态，所以即使 `f` 只读也要持写锁；
+    /// 只读取时可以在 [`SharedKVStore::read`] 的读锁下调用 [`KVStore::get_in`] 等 `*_in` 方法。
+    pub fn with_keyspace<T, F>(&self, name: &str, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut KVStore) -> Result<T>,
+    {
+        let mut store = self.write()?;
+        let mut keyspace = store.keyspace(name)?;
+        f(&mut keyspace)
+    }
+
+
+    /// 见 [`KVStore::use_keyspace`]
+    ///
+    /// 切换的是所有克隆共用的当前键空间：之后其他线程通过任何克隆进行的、
+    /// 没有指定键空间的读写都会落到 `name` 中。只适合整个 store 由一个使用方
+    /// （如服务端的一个会话）切换的场景，线程各自使用不同键空间时用
+    /// [`SharedKVStore::with_keyspace`] 或 [`SharedKVStore::get_in`] 等方法。
+    pub fn use_keyspace(&self, name: &str) -> Result<()> {
+        self.write()?.use_keyspace(name)
+    }
+
+
+    /// 见 [`KVStore::keyspaces`]
+    pub fn keyspaces(&self) -> Result<Vec<String>> {
+        Ok(self.read()?.keyspaces())
+    }
+
+
+    /// 见 [`KVStore::keyspace_stats`]
+    pub fn keyspace_stats(&self, name: &str) -> Result<Option<KeyspaceStats>> {
+        self.read()?.keyspace_stats(name)
+    }
+
+
+    /// 见 [`KVStore::clear_keyspace`]
+    pub fn clear_keyspace(&self, name: &str) -> Result<bool> {
+        self.write()?.clear_keyspace(name)
+    }
+
+
+    /// 见 [`KVStore::drop_keyspace`]
+    pub fn drop_keyspace(&self, name: &str) -> Result<bool> {
+        self.write()?.drop_keyspace(name)
+    }
+
+
+    /// 见 [`KVStore::create_index`]
+    pub fn create_index(&self, name: &str, pointer: &str) -> Result<bool> {
+        self.write()?.create_index(name, pointer)
+    }
+
+
+    /// 见 [`KVStore::drop_index`]
+    pub fn drop_index(&self, name: &str) -> Result<bool> {
+        self.write()?.drop_index(name)
+    }
+
+
+    /// 见 [`KVStore::indexes`]
+    pub fn indexes(&self) -> Result<Vec<IndexInfo>> {
+        Ok(self.read()?.indexes())
+    }
+
+
+    /// 见 [`KVStore::find_by_index`]
+    pub fn find_by_index(&self, name: &str, value: &Value) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read()?.find_by_index(name, value)
+    }
+
+
+    /// 见 [`KVStore::find_by_index_range`]
+    pub fn find_by_index_range<R: RangeBounds<Value>>(
+        &self,
+        name: &str,
+        range: R,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read()?.find_by_index_range(name, range)
+    }
+
+
+    /// 见 [`KVStore::set_compression`]
+    pub fn set_compression(&self, compression: Compression) -> Result<()> {
+        self.write()?.set_compression(compression)
+    }
+
+
+    /// 见 [`KVStore::compression`]
+    pub fn compression(&self) -> Result<Compression> {
+        Ok(self.read()?.compression())
+    }
+
+
+    /// 见 [`KVStore::checkpoint`]
+    pub fn checkpoint(&self) -> Result<()> {
+        self.write()?.checkpoint()
+    }
+
+
+    /// 关闭 store：提交所有修改并释放文件锁，见 [`KVStore::close`]
+    ///
+    /// 只有最后一个句柄能关闭 store，还有其他克隆存活时返回错误，这个句柄照常释放。
+    pub fn close(self) -> Result<()> {
+        let others = Arc::strong_count(&self.inner) - 1;
+        let store = Arc::into_inner(self.inner).ok_or_else(|| {
+            anyhow!("KVStore is still shared by {} other handle(s), close the last one instead", others)
+        })?;
+        store.into_inner().map_err(|_| anyhow!("KVStore lock poisoned"))?.close()
+    }
+}
+
+
+impl KVStore {
+    /// 转换为可在线程间共享的 [`SharedKVStore`]
+    ///
+    /// [`KVStore`] 的读方法只需要 `&self`，写方法需要 `&mut self`，跨线程共享时用它包装。
+    pub fn into_shared(self) -> SharedKVStore {
+        SharedKVStore {
+            inner: Arc::new(RwLock::new(self)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::WAL_FILE_EXT;
+    use super::*;
+    use crate::KVStoreOptions;
+
+    #[test]
+    fn last_handle_unlocks_like_a_dropped_store() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("shared.wkv");
+        let shared = KVStore::open(&path, None)?.into_shared();
+        let other = shared.clone();
+        shared.put(b"k", b"v")?;
+
+        // 还有其他句柄时不能关闭
+        assert!(shared.close().is_err());
+        assert!(KVStore::open(&path, None).is_err());
+
+        // 最后一个句柄释放时和丢弃 KVStore 一样不提交，修改留在 WAL 中
+        let wal_path = path.with_extension(WAL_FILE_EXT);
+        drop(other);
+        assert!(wal_path.exists());
+        let store = KVStore::open(&path, None)?;
+        assert_eq!(store.get(b"k")?, Some(b"v".to_vec()));
+        assert!(!wal_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn close_commits_when_not_shared() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("shared.wkv");
+        let shared = KVStore::open(&path, None)?.into_shared();
+        shared.put(b"k", b"v")?;
+        shared.close()?;
+        assert!(!path.with_extension(WAL_FILE_EXT).exists());
+        let store = KVStoreOptions::new(&path).read_only(true).open()?;
+        assert_eq!(store.get(b"k")?, Some(b"v".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn with_keyspace_does_not_affect_other_clones() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let shared = KVStore::open(dir.path().join("shared.wkv"), None)?.into_shared();
+        let other = shared.clone();
+        std::thread::spawn(move || other.with_keyspace("users", |store| store.put(b"k", b"v")))
+            .join()
+            .unwrap()?;
+        assert_eq!(shared.get(b"k")?, None);
+        assert_eq!(shared.get_in("users", b"k")?, Some(b"v".to_vec()));
+
+        // use_keyspace 切换的是所有克隆共用的当前键空间
+        let other = shared.clone();
+        other.use_keyspace("users")?;
+        assert_eq!(shared.get(b"k")?, Some(b"v".to_vec()));
+        Ok(())
+    }
+}
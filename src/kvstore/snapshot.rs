@@ -0,0 +1,266 @@
+// src/kvstore/snapshot.rs
+// 时间点快照：快照是一个只读映射的 KVStore 视图，页面按快照创建时的状态读取。
+// store 覆盖页面前，先把旧内容复制给每个存活的快照（写时复制），
+// 快照读取时优先使用这些副本，其余页面直接从文件映射中读取。
+use super::{current_time_millis, BlobReader, FileMap, KVCursor, KVStore, LRUCache, WALManager};
+use crate::options::Durability;
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Range, RangeBounds};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+
+
+// 快照创建后被 store 覆盖的页在覆盖前的内容
+pub(super) struct PinnedPages {
+    // 快照创建时的总页数，之后新增的页快照不会引用
+    total_pages: u32,
+    // 快照创建的时间（毫秒时间戳），按这一时刻判断键是否过期
+    pub(super) created_at: u64,
+    pages: Mutex<HashMap<u32, Vec<u8>>>,
+}
+
+
+impl PinnedPages {
+    fn lock(&self) -> MutexGuard<'_, HashMap<u32, Vec<u8>>> {
+        self.pages.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+
+    // 读取快照中的页。持锁读取文件映射，store 必须先拿到锁保存旧内容才能覆盖该页，
+    // 因此不会读到写了一半的页
+    pub(super) fn read(&self, page_num: u32, mmap: &[u8], range: Range<usize>) -> Vec<u8> {
+        let pages = self.lock();
+        match pages.get(&page_num) {
+            Some(page) => page.clone(),
+            None => mmap[range].to_vec(),
+        }
+    }
+}
+
+
+/// 由 [`KVStore::snapshot`] 创建的只读视图，固定在创建时的版本
+///
+/// 快照存活期间 store 照常写入，覆盖的页会先为快照保留一份旧内容，
+/// 所以快照上的 `get`、`scan` 和游标总是读到同一个版本，可以用于备份、导出和一致的多键读取。
+/// 保留的页只在快照存活期间占用内存，长时间持有快照时内存随写入量增长。
+/// 快照读取的是创建时 store 所在的键空间，按创建时的时间判断键是否过期。
+/// 快照持有数据库文件的句柄，存活期间文件锁不会释放。
+pub struct Snapshot {
+    view: KVStore,
+    lsn: u64,
+}
+
+
+impl Snapshot {
+    /// 快照对应的最后一条 WAL 记录的 LSN
+    pub fn lsn(&self) -> u64 {
+        self.lsn
+    }
+
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.view.get(key)
+    }
+
+
+    pub fn get_all(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.view.get_all()
+    }
+
+
+    /// 见 [`KVStore::get_reader`]
+    pub fn get_reader(&self, key: &[u8]) -> Result<Option<BlobReader<'_>>> {
+        self.view.get_reader(key)
+    }
+
+
+    pub fn cursor(&self) -> Result<KVCursor<'_>> {
+        self.view.cursor()
+    }
+
+
+    pub fn cursor_range<'a, R: RangeBounds<&'a [u8]>>(
+        &self,
+        range: R,
+        reverse: bool,
+    ) -> Result<KVCursor<'_>> {
+        self.view.cursor_range(range, reverse)
+    }
+
+
+    pub fn scan<'a, R: RangeBounds<&'a [u8]>>(
+        &self,
+        range: R,
+        limit: Option<usize>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.view.scan(range, limit)
+    }
+
+
+    pub fn scan_rev<'a, R: RangeBounds<&'a [u8]>>(
+        &self,
+        range: R,
+        limit: Option<usize>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.view.scan_rev(range, limit)
+    }
+
+
+    pub fn scan_prefix(
+        &self,
+        prefix: &[u8],
+        limit: Option<usize>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.view.scan_prefix(prefix, limit)
+    }
+
+
+    pub fn scan_prefix_rev(
+        &self,
+        prefix: &[u8],
+        limit: Option<usize>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.view.scan_prefix_rev(prefix, limit)
+    }
+}
+
+
+impl KVStore {
+    /// 创建固定在当前版本的只读快照，之后的写入对快照不可见
+    ///
+    /// ```no_run
+    /// # let mut store = wind_kvstore::KVStore::open("app.wkv", None)?;
+    /// store.put(b"counter", b"1")?;
+    /// let snapshot = store.snapshot()?;
+    /// store.put(b"counter", b"2")?;
+    /// assert_eq!(snapshot.get(b"counter")?, Some(b"1".to_vec()));
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        // 共用同一个打开的文件描述，快照存活期间文件锁一直有效，其他进程无法写入
+        let file = self.file.try_clone()?;
+        let mmap = FileMap::new(&file, true)?;
+
+        let pinned = Arc::new(PinnedPages {
+            total_pages: self.header.total_pages,
+            created_at: current_time_millis(),
+            pages: Mutex::new(HashMap::new()),
+        });
+        let mut snapshots = self.snapshots.lock().unwrap_or_else(PoisonError::into_inner);
+        snapshots.retain(|snapshot| snapshot.strong_count() > 0);
+        snapshots.push(Arc::downgrade(&pinned));
+
+        let view = KVStore {
+            path: self.path.clone(),
+            file,
+            mmap,
+            header: self.header.clone(),
+            page_cache: Mutex::new(LRUCache::new(self.options.cache_size)),
+            dirty_pages: HashSet::new(),
+            wal_manager: WALManager::new(&self.path, Durability::Off),
+            last_used_page: 0,
+            options: self.options.clone(),
+            keyspace: self.keyspace,
+            keyspaces: self.keyspaces.clone(),
+            indexes: self.indexes.clone(),
+            snapshots: Mutex::new(Vec::new()),
+            pinned: Some(pinned),
+        };
+        Ok(Snapshot {
+            view,
+            lsn: self.wal_manager.last_lsn,
+        })
+    }
+
+
+    // 覆盖页面前为存活的快照保留旧内容
+    pub(super) fn pin_for_snapshots(&mut self, page_num: u32, range: Range<usize>) {
+        let snapshots = self.snapshots.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if snapshots.is_empty() {
+            return;
+        }
+        snapshots.retain(|snapshot| snapshot.strong_count() > 0);
+
+        for pinned in snapshots.iter().filter_map(Weak::upgrade) {
+            if page_num > pinned.total_pages {
+                continue;
+            }
+            pinned
+                .lock()
+                .entry(page_num)
+                .or_insert_with(|| self.mmap[range.clone()].to_vec());
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:04}", i).into_bytes()
+    }
+
+
+    fn pinned_count(snapshot: &Snapshot) -> usize {
+        snapshot.view.pinned.as_ref().unwrap().lock().len()
+    }
+
+
+    #[test]
+    fn snapshot_keeps_old_view_across_splits() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = KVStore::open(dir.path().join("snapshot.wkv"), None)?;
+        for i in 0..50 {
+            store.put(&key(i), b"old")?;
+        }
+        store.commit()?;
+        let before = store.scan_prefix(b"key", None)?;
+
+        let snapshot = store.snapshot()?;
+        assert_eq!(pinned_count(&snapshot), 0);
+        for i in 0..10 {
+            store.put(&key(i), b"new")?;
+        }
+        for i in 10..20 {
+            store.delete(&key(i))?;
+        }
+        // 插入足够多的键，数据页和 B+ 树节点都会分裂
+        for i in 50..400 {
+            store.put(&key(i), &[b'v'; 64])?;
+        }
+        store.commit()?;
+        assert!(pinned_count(&snapshot) > 0);
+
+        assert_eq!(snapshot.get(&key(0))?, Some(b"old".to_vec()));
+        assert_eq!(snapshot.get(&key(10))?, Some(b"old".to_vec()));
+        assert_eq!(snapshot.get(&key(100))?, None);
+        assert_eq!(snapshot.scan_prefix(b"key", None)?, before);
+
+        assert_eq!(store.get(&key(0))?, Some(b"new".to_vec()));
+        assert_eq!(store.get(&key(10))?, None);
+        assert_eq!(store.scan_prefix(b"key", None)?.len(), 390);
+        Ok(())
+    }
+
+
+    #[test]
+    fn dropping_snapshot_releases_pinned_pages() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = KVStore::open(dir.path().join("release.wkv"), None)?;
+        store.put(b"a", b"1")?;
+        let snapshot = store.snapshot()?;
+        store.put(b"a", b"2")?;
+        let pinned = Arc::downgrade(snapshot.view.pinned.as_ref().unwrap());
+        assert!(pinned.upgrade().is_some_and(|pinned| !pinned.lock().is_empty()));
+
+        drop(snapshot);
+        assert!(pinned.upgrade().is_none());
+        // 下一次写入时清理已失效的快照，不再为它保留页面
+        store.put(b"a", b"3")?;
+        assert!(store.snapshots.lock().unwrap().is_empty());
+        Ok(())
+    }
+}
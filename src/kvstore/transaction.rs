@@ -0,0 +1,157 @@
+// src/kvstore/transaction.rs
+// 多操作事务：写入先缓存在内存中，提交时整体写入 WAL 再应用到页面
+use super::KVStore;
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+
+
+// 事务中的键和直接写入时一样检查：空键保留给引擎内部使用
+fn check_key(store: &KVStore, key: &[u8]) -> Result<()> {
+    if key.is_empty() {
+        return Err(anyhow!("Empty key is reserved for internal use"));
+    }
+    store.check_key_size(key)
+}
+
+
+/// 由 [`KVStore::transaction`] 创建的事务
+///
+/// 事务内的 put/delete 在 [`Transaction::commit`] 之前对其他读取不可见，
+/// `get` 能读到本事务自己的写入。未提交就被丢弃的事务等同于回滚。
+/// 提交时整个事务作为一条 BEGIN/COMMIT 包围的记录写入 WAL，
+/// 崩溃恢复时没有 COMMIT 标记的事务会被整体丢弃。
+pub struct Transaction<'a> {
+    store: &'a mut KVStore,
+    // None 表示删除
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+
+impl<'a> Transaction<'a> {
+    pub(super) fn new(store: &'a mut KVStore) -> Self {
+        Self {
+            store,
+            writes: BTreeMap::new(),
+        }
+    }
+
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        check_key(self.store, key)?;
+        self.writes.insert(key.to_vec(), Some(value.to_vec()));
+        Ok(())
+    }
+
+
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        check_key(self.store, key)?;
+        self.writes.insert(key.to_vec(), None);
+        Ok(())
+    }
+
+
+    /// 先查本事务的写入，再查数据库
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.writes.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => self.store.get(key),
+        }
+    }
+
+
+    /// 原子地应用事务中的全部写入
+    pub fn commit(self) -> Result<()> {
+        if self.writes.is_empty() {
+            return Ok(());
+        }
+        self.store.check_writable()?;
+
+        self.store.wal_manager.log_transaction(&self.writes)?;
+
+        // WAL 中已有 COMMIT 标记，应用中途失败时下次打开会重放整个事务
+        for (key, value) in &self.writes {
+            match value {
+                Some(value) => self.store.put_internal(key, value)?,
+                None => self.store.delete_internal(key)?,
+            }
+        }
+        self.store.finish_write()
+    }
+
+
+    /// 丢弃事务中的全部写入
+    pub fn rollback(self) {}
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::WAL_FILE_EXT;
+    use super::*;
+    use crate::KVStoreOptions;
+
+    #[test]
+    fn rejects_invalid_keys() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = KVStoreOptions::new(dir.path().join("tx.wkv")).max_key_size(8).open()?;
+        let mut tx = store.transaction();
+        assert!(tx.put(b"", b"v").is_err());
+        assert!(tx.delete(b"").is_err());
+        assert!(tx.put(b"too long key", b"v").is_err());
+        assert!(tx.delete(b"too long key").is_err());
+        tx.put(b"k", b"v")?;
+        tx.commit()?;
+        assert_eq!(store.get(b"k")?, Some(b"v".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn rollback_discards_writes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = KVStore::open(dir.path().join("tx.wkv"), None)?;
+        store.put(b"a", b"1")?;
+
+        let mut tx = store.transaction();
+        tx.put(b"a", b"2")?;
+        tx.put(b"b", b"3")?;
+        tx.delete(b"a")?;
+        assert_eq!(tx.get(b"a")?, None);
+        assert_eq!(tx.get(b"b")?, Some(b"3".to_vec()));
+        tx.rollback();
+        assert_eq!(store.get(b"a")?, Some(b"1".to_vec()));
+        assert_eq!(store.get(b"b")?, None);
+
+        // 未提交就丢弃等同于回滚
+        let mut tx = store.transaction();
+        tx.put(b"b", b"3")?;
+        drop(tx);
+        assert_eq!(store.get(b"b")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn recovery_discards_transaction_without_commit() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("tx.wkv");
+        let mut store = KVStore::open(&path, None)?;
+        store.put(b"a", b"1")?;
+        store.checkpoint()?;
+        let snapshot = std::fs::read(&path)?;
+
+        let mut tx = store.transaction();
+        tx.put(b"a", b"2")?;
+        tx.put(b"b", b"3")?;
+        tx.commit()?;
+        drop(store);
+
+        // COMMIT 标记没有完整写入：整个事务都不重放
+        let wal_path = path.with_extension(WAL_FILE_EXT);
+        let wal = std::fs::read(&wal_path)?;
+        std::fs::write(&wal_path, &wal[..wal.len() - 1])?;
+        std::fs::write(&path, snapshot)?;
+        let store = KVStore::open(&path, None)?;
+        assert_eq!(store.get(b"a")?, Some(b"1".to_vec()));
+        assert_eq!(store.get(b"b")?, None);
+        Ok(())
+    }
+}
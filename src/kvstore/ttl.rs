@@ -0,0 +1,301 @@
+// src/kvstore/ttl.rs
+// 键的过期时间：过期时间作为值字段的一部分保存在条目中，
+// 读取时按当前时间判断，过期的键对 get 和扫描不可见，
+// 由 purge_expired 或 compact 真正删除。
+use super::{current_time_millis, parse_entries, EntryMeta, EntrySpan, KVStore, PageHeader, StoredValue, WALManager};
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+
+
+// ttl 之后的毫秒时间戳
+fn deadline(ttl: Duration) -> u64 {
+    current_time_millis().saturating_add(ttl.as_millis().try_into().unwrap_or(u64::MAX))
+}
+
+
+impl KVStore {
+    /// 写入一个在 `ttl` 之后过期的键值对
+    ///
+    /// 过期后 `get`、扫描和游标都看不到这个键，空间在 [`KVStore::purge_expired`]
+    /// 或 [`KVStore::compact`] 时回收。之后用 `put` 覆盖会清除过期时间。
+    ///
+    /// ```no_run
+    /// # let mut store = wind_kvstore::KVStore::open("app.wkv", None)?;
+    /// use std::time::Duration;
+    /// store.put_with_ttl(b"session:42", b"alice", Duration::from_secs(1800))?;
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn put_with_ttl(&mut self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        self.put_expiring_at(key, value, deadline(ttl))
+    }
+
+
+    // 写入一个在毫秒时间戳 expires_at 过期的键值对
+    pub(super) fn put_expiring_at(&mut self, key: &[u8], value: &[u8], expires_at: u64) -> Result<()> {
+        self.check_writable()?;
+        self.check_key_size(key)?;
+
+        let mut record = WALManager::encode_expiry(Some(expires_at)).to_vec();
+        record.extend_from_slice(value);
+        self.wal_manager
+            .log_operation(WALManager::OP_PUT_TTL, key, Some(&record))?;
+        self.put_expiring(key, value, Some(expires_at))?;
+        self.finish_write()
+    }
+
+
+    /// 设置键在 `ttl` 之后过期，键不存在（或已过期）时返回 false
+    pub fn expire(&mut self, key: &[u8], ttl: Duration) -> Result<bool> {
+        self.update_expiry(key, Some(deadline(ttl)))
+    }
+
+
+    /// 取消键的过期时间，键不存在或本来就没有过期时间时返回 false
+    pub fn persist(&mut self, key: &[u8]) -> Result<bool> {
+        if self.ttl(key)?.is_none() {
+            return Ok(false);
+        }
+        self.update_expiry(key, None)
+    }
+
+
+    /// 键剩余的存活时间，键不存在、已过期或没有过期时间时返回 `None`
+    pub fn ttl(&self, key: &[u8]) -> Result<Option<Duration>> {
+        self.ttl_at(self.keyspace, key)
+    }
+
+
+    pub(super) fn ttl_at(&self, id: u16, key: &[u8]) -> Result<Option<Duration>> {
+        let entry = match self.find_live_entry_at(id, key)? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        Ok(entry.expires_at.map(|expires_at| {
+            Duration::from_millis(expires_at.saturating_sub(current_time_millis()))
+        }))
+    }
+
+
+    /// 删除所有键空间中已过期的键，返回删除的数量
+    ///
+    /// 删除和普通的 `delete` 一样写入 WAL。
+    pub fn purge_expired(&mut self) -> Result<usize> {
+        self.check_writable()?;
+        let now = current_time_millis();
+
+        let mut expired = Vec::new();
+        for page_num in 1..=self.header.total_pages {
+            let page_data = self.read_page(page_num)?;
+            let header = PageHeader::unpack(&page_data)?;
+            if !header.is_data_page() || self.keyspace_name(header.keyspace).is_none() {
+                continue;
+            }
+            let data = &page_data[PageHeader::SIZE..PageHeader::SIZE + header.data_len as usize];
+            for entry in parse_entries(data, header.kv_count, self.page_size()) {
+                if entry.expires_at.is_some_and(|expires_at| expires_at <= now) {
+                    let mut key = data[entry.key.clone()].to_vec();
+                    if entry.key_overflow != 0 {
+                        key.extend_from_slice(&self.read_overflow(entry.key_overflow)?);
+                    }
+                    expired.push((header.keyspace, key));
+                }
+            }
+        }
+
+        // 按数据页所属的键空间逐个删除，结束后回到原来的键空间
+        let active = self.keyspace;
+        let mut purged = Ok(());
+        for (keyspace, key) in &expired {
+            self.select_keyspace(*keyspace);
+            purged = self
+                .wal_manager
+                .log_operation(WALManager::OP_DELETE, key, None)
+                .and_then(|()| self.delete_internal(key));
+            if purged.is_err() {
+                break;
+            }
+        }
+        self.select_keyspace(active);
+        purged?;
+        if !expired.is_empty() {
+            self.finish_write()?;
+        }
+        Ok(expired.len())
+    }
+
+
+    fn update_expiry(&mut self, key: &[u8], expires_at: Option<u64>) -> Result<bool> {
+        self.check_writable()?;
+        if self.find_live_entry(key)?.is_none() {
+            return Ok(false);
+        }
+
+        self.wal_manager.log_operation(
+            WALManager::OP_EXPIRE,
+            key,
+            Some(&WALManager::encode_expiry(expires_at)),
+        )?;
+        self.set_expiry(key, expires_at)?;
+        self.finish_write()?;
+        Ok(true)
+    }
+
+
+    // 修改条目的过期时间并更新版本号，值保持原样（溢出的值继续引用原来的溢出链，压缩的值不重新压缩）
+    pub(super) fn set_expiry(&mut self, key: &[u8], expires_at: Option<u64>) -> Result<bool> {
+        let page_num = match self.index_lookup(key)? {
+            Some(num) => num,
+            None => return Ok(false),
+        };
+        let page_data = self.read_page(page_num)?;
+        let header = PageHeader::unpack(&page_data)?;
+        let data = &page_data[PageHeader::SIZE..PageHeader::SIZE + header.data_len as usize];
+        let entry = match self.find_entry(data, header.kv_count, key)? {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+
+        let value = if entry.value_overflow != 0 {
+            StoredValue::Overflow {
+                len: entry.value_len,
+                start: entry.value_overflow,
+            }
+        } else {
+            StoredValue::Inline(&data[entry.value.clone()])
+        };
+        let meta = EntryMeta {
+            compressed: entry.compressed,
+            ..self.write_meta(expires_at)
+        };
+        self.put_stored(key, &value, meta)?;
+        Ok(true)
+    }
+
+
+    // 查找未过期的条目
+    pub(super) fn find_live_entry(&self, key: &[u8]) -> Result<Option<EntrySpan>> {
+        self.find_live_entry_at(self.keyspace, key)
+    }
+
+
+    fn find_live_entry_at(&self, id: u16, key: &[u8]) -> Result<Option<EntrySpan>> {
+        if key.is_empty() {
+            return Err(anyhow!("Empty key is reserved for internal use"));
+        }
+        Ok(self
+            .find_stored_entry_at(id, key)?
+            .filter(|entry| !self.is_expired(entry)))
+    }
+
+
+    // 查找键的条目，不判断是否过期
+    pub(super) fn find_stored_entry(&self, key: &[u8]) -> Result<Option<EntrySpan>> {
+        self.find_stored_entry_at(self.keyspace, key)
+    }
+
+
+    fn find_stored_entry_at(&self, id: u16, key: &[u8]) -> Result<Option<EntrySpan>> {
+        let page_num = match self.index_lookup_at(id, key)? {
+            Some(num) => num,
+            None => return Ok(None),
+        };
+        let page_data = self.read_page(page_num)?;
+        let header = PageHeader::unpack(&page_data)?;
+        let data = &page_data[PageHeader::SIZE..PageHeader::SIZE + header.data_len as usize];
+        self.find_entry(data, header.kv_count, key)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    const SHORT: Duration = Duration::from_millis(50);
+
+
+    fn open(dir: &tempfile::TempDir) -> Result<KVStore> {
+        KVStore::open(dir.path().join("ttl.wkv"), None)
+    }
+
+
+    #[test]
+    fn expired_keys_are_invisible() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = open(&dir)?;
+        store.put(b"k1", b"kept")?;
+        store.put_with_ttl(b"k2", b"short", SHORT)?;
+        store.put_with_ttl(b"k3", b"long", Duration::from_secs(3600))?;
+        assert_eq!(store.get(b"k2")?, Some(b"short".to_vec()));
+        assert!(store.ttl(b"k2")?.is_some_and(|ttl| ttl <= SHORT));
+
+        sleep(SHORT * 2);
+        assert_eq!(store.get(b"k2")?, None);
+        assert_eq!(store.ttl(b"k2")?, None);
+        let keys: Vec<Vec<u8>> = store.scan(.., None)?.into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![b"k1".to_vec(), b"k3".to_vec()]);
+        assert_eq!(store.scan_prefix(b"k", None)?.len(), 2);
+        assert_eq!(store.cursor()?.count(), 2);
+        // 过期的键不能再设置过期时间
+        assert!(!store.expire(b"k2", SHORT)?);
+        Ok(())
+    }
+
+
+    #[test]
+    fn ttl_persist_and_expire_on_plain_and_missing_keys() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = open(&dir)?;
+        store.put(b"plain", b"1")?;
+
+        assert_eq!(store.ttl(b"missing")?, None);
+        assert!(!store.persist(b"missing")?);
+        assert!(!store.expire(b"missing", SHORT)?);
+        assert_eq!(store.get(b"missing")?, None);
+
+        assert_eq!(store.ttl(b"plain")?, None);
+        assert!(!store.persist(b"plain")?);
+
+        let hour = Duration::from_secs(3600);
+        assert!(store.expire(b"plain", hour)?);
+        assert!(store.ttl(b"plain")?.is_some_and(|ttl| ttl <= hour));
+        assert!(store.persist(b"plain")?);
+        assert_eq!(store.ttl(b"plain")?, None);
+
+        // 覆盖会清除过期时间
+        store.put_with_ttl(b"plain", b"2", hour)?;
+        store.put(b"plain", b"3")?;
+        assert_eq!(store.ttl(b"plain")?, None);
+        Ok(())
+    }
+
+
+    #[test]
+    fn purge_removes_expired_entries() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = open(&dir)?;
+        for i in 0..10 {
+            let key = format!("key{}", i);
+            if i % 2 == 0 {
+                store.put_with_ttl(key.as_bytes(), b"v", SHORT)?;
+            } else {
+                store.put(key.as_bytes(), b"v")?;
+            }
+        }
+        sleep(SHORT * 2);
+        // 过期但尚未清除的条目仍在数据页中
+        assert_eq!(store.verify()?.entries, 10);
+        assert!(store.find_stored_entry(b"key0")?.is_some());
+
+        assert_eq!(store.purge_expired()?, 5);
+        assert!(store.find_stored_entry(b"key0")?.is_none());
+        assert_eq!(store.verify()?.entries, 5);
+        assert_eq!(store.purge_expired()?, 0);
+        store.close()?;
+
+        let store = open(&dir)?;
+        assert_eq!(store.scan(.., None)?.len(), 5);
+        Ok(())
+    }
+}
@@ -0,0 +1,812 @@
+// src/kvstore/verify.rs
+// 离线完整性检查（fsck）：只读映射数据库文件，逐页检查页头、空闲链表、溢出链和索引，
+// 发现的问题汇总到 VerifyReport 中，不会修改文件，也不会回放 WAL。
+use super::btree::Node;
+use super::keyspace::{KeyspaceEntry, DEFAULT_KEYSPACE_ID};
+use super::{
+    check_page_size, lock_file, parse_entries_as, verify_page, DBHeader, KVStore, KvError, PageHeader,
+    CHECKSUM_VERSION, ENTRY_FORMAT_VERSION, FORMAT_VERSION, HEADER_SIZE,
+    PAGE_FLAG_FREE, PAGE_FLAG_INDEX_BRANCH, PAGE_FLAG_INDEX_LEAF, PAGE_FLAG_OVERFLOW,
+    WAL_FILE_EXT,
+};
+use anyhow::{bail, Result};
+use memmap2::MmapOptions;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+
+/// 页的用途，由页头 flags 判断
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageKind {
+    Data,
+    Overflow,
+    Free,
+    Index,
+}
+
+
+impl fmt::Display for PageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PageKind::Data => "data",
+            PageKind::Overflow => "overflow",
+            PageKind::Free => "free",
+            PageKind::Index => "index",
+        };
+        f.write_str(name)
+    }
+}
+
+
+/// 检查中发现的一个问题，页号 0 表示文件头
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyIssue {
+    /// 文件头校验和不匹配
+    HeaderChecksum,
+    /// 文件长度不足以容纳 `total_pages` 个页，之后的页均无法检查
+    Truncated { total_pages: u32, available: u32 },
+    /// 页校验和不匹配
+    ChecksumMismatch { page: u32 },
+    /// 页头中的 data_len 超出页容量
+    DataLenOverflow { page: u32, data_len: u16 },
+    /// 页头中的 kv_count 与实际能解析出的条目数不符，多出的条目已无法读取
+    EntryCountMismatch { page: u32, kv_count: u16, parsed: usize },
+    /// `from` 页（或文件头）指向了不存在的页
+    DanglingPointer { from: u32, to: u32 },
+    /// 空闲链表成环
+    FreeListCycle { page: u32 },
+    /// `owner` 页（数据页或索引节点）引用的溢出链成环
+    OverflowChainCycle { owner: u32, page: u32 },
+    /// 被引用的页类型与引用方期望的不符
+    UnexpectedPageKind { page: u32, expected: PageKind, found: PageKind },
+    /// 同一页被多处以不同（或互斥的）身份引用，例如既在空闲链表中又被溢出链使用
+    MultiplyReferenced { page: u32, first: PageKind, second: PageKind },
+    /// 索引页无法解析
+    MalformedIndexNode { page: u32 },
+    /// 从 `page` 开始的键空间目录无法解析，命名键空间的索引均无法检查
+    MalformedKeyspaceCatalog { page: u32 },
+    /// 同一个键出现在多个数据页中
+    DuplicateKey { key: Vec<u8>, pages: Vec<u32> },
+    /// 索引记录的数据页中找不到该键
+    IndexMismatch { key: Vec<u8>, page: u32 },
+    /// 数据页中的键没有被索引，正常读取时不可见
+    MissingFromIndex { key: Vec<u8>, page: u32 },
+    /// 没有被任何结构引用的溢出页、索引页或空闲页（空间泄漏）
+    OrphanPage { page: u32, kind: PageKind },
+}
+
+
+impl fmt::Display for VerifyIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyIssue::HeaderChecksum => write!(f, "file header checksum mismatch"),
+            VerifyIssue::Truncated {
+                total_pages,
+                available,
+            } => write!(
+                f,
+                "file is truncated: header claims {} pages but only {} are present",
+                total_pages, available
+            ),
+            VerifyIssue::ChecksumMismatch { page } => {
+                write!(f, "page {}: checksum mismatch", page)
+            }
+            VerifyIssue::DataLenOverflow { page, data_len } => {
+                write!(f, "page {}: data_len {} exceeds page capacity", page, data_len)
+            }
+            VerifyIssue::EntryCountMismatch {
+                page,
+                kv_count,
+                parsed,
+            } => write!(
+                f,
+                "page {}: header says {} entries but only {} could be parsed",
+                page, kv_count, parsed
+            ),
+            VerifyIssue::DanglingPointer { from, to } => {
+                write!(f, "page {}: points to nonexistent page {}", from, to)
+            }
+            VerifyIssue::FreeListCycle { page } => {
+                write!(f, "free list loops back to page {}", page)
+            }
+            VerifyIssue::OverflowChainCycle { owner, page } => write!(
+                f,
+                "overflow chain of page {} loops back to page {}",
+                owner, page
+            ),
+            VerifyIssue::UnexpectedPageKind {
+                page,
+                expected,
+                found,
+            } => write!(
+                f,
+                "page {}: referenced as {} page but is a {} page",
+                page, expected, found
+            ),
+            VerifyIssue::MultiplyReferenced {
+                page,
+                first,
+                second,
+            } => write!(
+                f,
+                "page {}: referenced as {} page and again as {} page",
+                page, first, second
+            ),
+            VerifyIssue::MalformedIndexNode { page } => {
+                write!(f, "page {}: index node cannot be parsed", page)
+            }
+            VerifyIssue::MalformedKeyspaceCatalog { page } => {
+                write!(f, "page {}: keyspace catalog cannot be parsed", page)
+            }
+            VerifyIssue::DuplicateKey { key, pages } => write!(
+                f,
+                "key {:?} is stored in pages {:?}",
+                String::from_utf8_lossy(key),
+                pages
+            ),
+            VerifyIssue::IndexMismatch { key, page } => write!(
+                f,
+                "index maps key {:?} to page {} which does not contain it",
+                String::from_utf8_lossy(key),
+                page
+            ),
+            VerifyIssue::MissingFromIndex { key, page } => write!(
+                f,
+                "key {:?} in page {} is missing from the index",
+                String::from_utf8_lossy(key),
+                page
+            ),
+            VerifyIssue::OrphanPage { page, kind } => {
+                write!(f, "page {}: {} page is not referenced", page, kind)
+            }
+        }
+    }
+}
+
+
+/// [`KVStore::verify_file`] 的检查结果
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub format_version: u16,
+    pub page_size: usize,
+    pub total_pages: u32,
+    pub data_pages: u32,
+    pub overflow_pages: u32,
+    pub free_pages: u32,
+    pub index_pages: u32,
+    /// 数据页中解析出的条目数（含重复键）
+    pub entries: u64,
+    /// 数据库旁存在 WAL，下次打开时会先回放，报告反映的是回放前的页面
+    pub wal_present: bool,
+    pub issues: Vec<VerifyIssue>,
+}
+
+
+impl VerifyReport {
+    /// 没有发现任何问题
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "format version {}, {}-byte pages, {} pages ({} data, {} overflow, {} index, {} free), {} entries",
+            self.format_version,
+            self.page_size,
+            self.total_pages,
+            self.data_pages,
+            self.overflow_pages,
+            self.index_pages,
+            self.free_pages,
+            self.entries
+        )?;
+        if self.wal_present {
+            writeln!(f, "a WAL file is present and will be replayed on the next open")?;
+        }
+        if self.is_ok() {
+            write!(f, "no problems found")
+        } else {
+            write!(f, "{} problem(s) found:", self.issues.len())?;
+            for issue in &self.issues {
+                write!(f, "\n  - {}", issue)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+
+// 单页的解析结果
+struct PageInfo {
+    kind: PageKind,
+    header: PageHeader,
+}
+
+
+struct Verifier<'a> {
+    data: &'a [u8],
+    header: DBHeader,
+    // 下标为页号 - 1，无法读取的页为 None
+    pages: Vec<Option<PageInfo>>,
+    // 页被哪种结构引用，用于发现交叉引用
+    claims: HashMap<u32, PageKind>,
+    // (键空间, 键) -> 存放它的数据页
+    locations: HashMap<(u16, Vec<u8>), Vec<u32>>,
+    // 数据页中溢出的键：(数据页, 键空间, 页内前缀, 溢出链起始页)
+    key_chains: Vec<(u32, u16, Vec<u8>, u32)>,
+    // 条目引用的值溢出链：(数据页, 溢出链起始页)
+    value_chains: Vec<(u32, u32)>,
+    report: VerifyReport,
+}
+
+
+impl<'a> Verifier<'a> {
+    fn new(data: &'a [u8]) -> Result<Self> {
+        if data.len() < HEADER_SIZE {
+            bail!("Database file too small");
+        }
+        let header = DBHeader::unpack_unchecked(&data[..HEADER_SIZE])?;
+        if header.format_version > FORMAT_VERSION {
+            bail!(KvError::UnsupportedVersion(header.format_version));
+        }
+        // 页大小不可信时无法定位任何页
+        check_page_size(header.page_size as usize)?;
+
+        let report = VerifyReport {
+            format_version: header.format_version,
+            page_size: header.page_size as usize,
+            total_pages: header.total_pages,
+            ..VerifyReport::default()
+        };
+        let mut verifier = Self {
+            data,
+            header,
+            pages: Vec::new(),
+            claims: HashMap::new(),
+            locations: HashMap::new(),
+            key_chains: Vec::new(),
+            value_chains: Vec::new(),
+            report,
+        };
+        if verifier.header.format_version >= CHECKSUM_VERSION
+            && !DBHeader::checksum_ok(&data[..HEADER_SIZE])
+        {
+            verifier.report.issues.push(VerifyIssue::HeaderChecksum);
+        }
+        Ok(verifier)
+    }
+
+
+    fn run(mut self) -> VerifyReport {
+        self.scan_pages();
+        self.check_free_list();
+        self.check_overflow_chains();
+        if self.header.format_version >= 1 {
+            let mut indexed = HashSet::new();
+            for (keyspace, root) in self.index_roots() {
+                self.check_index(keyspace, root, &mut indexed);
+            }
+            self.check_unindexed(&indexed);
+        }
+        self.check_orphans();
+        self.report
+    }
+
+
+    fn issue(&mut self, issue: VerifyIssue) {
+        self.report.issues.push(issue);
+    }
+
+
+    fn page(&self, page_num: u32) -> Option<&PageInfo> {
+        if page_num == 0 {
+            return None;
+        }
+        self.pages.get(page_num as usize - 1)?.as_ref()
+    }
+
+
+    // 第一遍：逐页检查页头与条目，记录每页的类型
+    fn scan_pages(&mut self) {
+        let total = self.header.total_pages;
+        let page_size = self.header.page_size as usize;
+        let available = ((self.data.len() - HEADER_SIZE) / page_size) as u32;
+        if available < total {
+            self.issue(VerifyIssue::Truncated {
+                total_pages: total,
+                available,
+            });
+        }
+
+        for page_num in 1..=total {
+            let info = if page_num <= available {
+                let offset = HEADER_SIZE + (page_num - 1) as usize * page_size;
+                self.scan_page(page_num, &self.data[offset..offset + page_size])
+            } else {
+                None
+            };
+            self.pages.push(info);
+        }
+
+        // 所有页头读完后才能沿溢出链补全长键，链断裂时按前缀记录，问题由溢出链检查报告
+        for (page_num, keyspace, prefix, start) in self.key_chains.clone() {
+            let mut key = prefix;
+            key.extend(self.read_chain(start).unwrap_or_default());
+            self.locations.entry((keyspace, key)).or_default().push(page_num);
+        }
+
+        let mut duplicates: Vec<_> = self
+            .locations
+            .iter()
+            .filter(|(_, pages)| pages.len() > 1)
+            .collect();
+        duplicates.sort();
+        let duplicates: Vec<_> = duplicates
+            .into_iter()
+            .map(|((_, key), pages)| VerifyIssue::DuplicateKey {
+                key: key.clone(),
+                pages: pages.clone(),
+            })
+            .collect();
+        self.report.issues.extend(duplicates);
+    }
+
+
+    fn scan_page(&mut self, page_num: u32, page_data: &[u8]) -> Option<PageInfo> {
+        let page_size = page_data.len();
+        if self.header.format_version >= CHECKSUM_VERSION && !verify_page(page_data) {
+            self.issue(VerifyIssue::ChecksumMismatch { page: page_num });
+        }
+
+        let header = PageHeader::unpack(page_data).ok()?;
+        let kind = if header.flags & PAGE_FLAG_FREE != 0 {
+            PageKind::Free
+        } else if header.flags & (PAGE_FLAG_INDEX_LEAF | PAGE_FLAG_INDEX_BRANCH) != 0 {
+            PageKind::Index
+        } else if header.is_data_page() {
+            PageKind::Data
+        } else {
+            PageKind::Overflow
+        };
+        match kind {
+            PageKind::Data => self.report.data_pages += 1,
+            PageKind::Overflow => self.report.overflow_pages += 1,
+            PageKind::Free => self.report.free_pages += 1,
+            PageKind::Index => self.report.index_pages += 1,
+        }
+
+        let data_end = PageHeader::SIZE + header.data_len as usize;
+        if data_end > page_data.len() {
+            self.issue(VerifyIssue::DataLenOverflow {
+                page: page_num,
+                data_len: header.data_len,
+            });
+            return Some(PageInfo { kind, header });
+        }
+
+        if kind == PageKind::Data {
+            let data = &page_data[PageHeader::SIZE..data_end];
+            let entries =
+                parse_entries_as(data, header.kv_count, page_size, self.header.format_version);
+            if entries.len() != header.kv_count as usize {
+                self.issue(VerifyIssue::EntryCountMismatch {
+                    page: page_num,
+                    kv_count: header.kv_count,
+                    parsed: entries.len(),
+                });
+            }
+            self.report.entries += entries.len() as u64;
+            for entry in entries {
+                if entry.value_overflow != 0 {
+                    self.value_chains.push((page_num, entry.value_overflow));
+                }
+                let key = data[entry.key].to_vec();
+                if entry.key_overflow != 0 {
+                    self.key_chains
+                        .push((page_num, header.keyspace, key, entry.key_overflow));
+                } else {
+                    self.locations
+                        .entry((header.keyspace, key))
+                        .or_default()
+                        .push(page_num);
+                }
+            }
+        }
+
+        Some(PageInfo { kind, header })
+    }
+
+
+    // 记录一次引用，返回 false 表示该页已被占用，调用方不应继续沿它遍历
+    fn claim(&mut self, page_num: u32, usage: PageKind) -> bool {
+        if let Some(&first) = self.claims.get(&page_num) {
+            // 多个索引条目指向同一数据页是正常的
+            if !(first == PageKind::Data && usage == PageKind::Data) {
+                self.issue(VerifyIssue::MultiplyReferenced {
+                    page: page_num,
+                    first,
+                    second: usage,
+                });
+            }
+            return false;
+        }
+        self.claims.insert(page_num, usage);
+
+        let found = self.page(page_num).map(|info| info.kind);
+        if let Some(found) = found.filter(|kind| *kind != usage) {
+            self.issue(VerifyIssue::UnexpectedPageKind {
+                page: page_num,
+                expected: usage,
+                found,
+            });
+        }
+        true
+    }
+
+
+    // 检查页号是否在范围内且可读，否则记一个悬空指针
+    fn resolve(&mut self, from: u32, to: u32) -> bool {
+        if self.page(to).is_some() {
+            return true;
+        }
+        // 超出文件长度的页已经作为 Truncated 报告过
+        if to == 0 || to > self.header.total_pages {
+            self.issue(VerifyIssue::DanglingPointer { from, to });
+        }
+        false
+    }
+
+
+    // 沿 next_page 遍历一条链，cycle 用于在成环时生成问题
+    fn walk_chain(
+        &mut self,
+        from: u32,
+        start: u32,
+        usage: PageKind,
+        cycle: impl Fn(u32) -> VerifyIssue,
+    ) {
+        let mut visited = HashSet::new();
+        let mut prev = from;
+        let mut current = start;
+
+        while current != 0 {
+            if !visited.insert(current) {
+                self.issue(cycle(current));
+                return;
+            }
+            if !self.resolve(prev, current) || !self.claim(current, usage) {
+                return;
+            }
+            prev = current;
+            current = self.page(current).map_or(0, |info| info.header.next_page);
+        }
+    }
+
+
+    // 读取一条溢出链的内容，链断裂或成环时返回 None
+    fn read_chain(&self, start: u32) -> Option<Vec<u8>> {
+        let page_size = self.header.page_size as usize;
+        let mut value = Vec::new();
+        let mut visited = HashSet::new();
+        let mut current = start;
+
+        while current != 0 {
+            if !visited.insert(current) {
+                return None;
+            }
+            let info = self.page(current)?;
+            let offset = HEADER_SIZE + (current - 1) as usize * page_size + PageHeader::SIZE;
+            value.extend_from_slice(self.data.get(offset..offset + info.header.data_len as usize)?);
+            current = info.header.next_page;
+        }
+        Some(value)
+    }
+
+
+    fn check_free_list(&mut self) {
+        let head = self.header.free_page_head;
+        self.walk_chain(0, head, PageKind::Free, |page| VerifyIssue::FreeListCycle {
+            page,
+        });
+    }
+
+
+    fn check_overflow_chains(&mut self) {
+        // 格式版本 5 之前大值的溢出链挂在数据页上
+        let owners: Vec<(u32, u32)> = (1..=self.header.total_pages)
+            .filter_map(|page_num| {
+                let info = self.page(page_num)?;
+                let has_chain = info.kind == PageKind::Data
+                    && info.header.flags & PAGE_FLAG_OVERFLOW != 0;
+                has_chain.then_some((page_num, info.header.next_page))
+            })
+            .collect();
+
+        let key_chains: Vec<(u32, u32)> = self
+            .key_chains
+            .iter()
+            .map(|(owner, _, _, start)| (*owner, *start))
+            .collect();
+
+        let value_chains = self.value_chains.clone();
+        // 流式写入中断时留下的待引用溢出链，下次读写打开时释放
+        let pending = (self.header.pending_blob != 0).then_some((0, self.header.pending_blob));
+        for (owner, start) in owners.into_iter().chain(key_chains).chain(value_chains).chain(pending) {
+            self.walk_chain(owner, start, PageKind::Overflow, |page| {
+                VerifyIssue::OverflowChainCycle { owner, page }
+            });
+        }
+    }
+
+
+    // 默认键空间的索引根在文件头中，命名键空间的在键空间目录里
+    fn index_roots(&mut self) -> Vec<(u16, u32)> {
+        let mut roots = vec![(DEFAULT_KEYSPACE_ID, self.header.index_root)];
+        let catalog = self.header.keyspace_catalog;
+        if catalog == 0 {
+            return roots;
+        }
+
+        self.walk_chain(0, catalog, PageKind::Overflow, |page| {
+            VerifyIssue::OverflowChainCycle { owner: 0, page }
+        });
+        match self
+            .read_chain(catalog)
+            .and_then(|data| KeyspaceEntry::decode_all(&data, self.header.format_version))
+        {
+            Some(entries) => roots.extend(entries.into_iter().map(|entry| (entry.id, entry.root))),
+            None => self.issue(VerifyIssue::MalformedKeyspaceCatalog { page: catalog }),
+        }
+        roots
+    }
+
+
+    fn check_index(&mut self, keyspace: u16, root: u32, indexed: &mut HashSet<(u16, Vec<u8>)>) {
+        let mut stack = Vec::new();
+        if root != 0 {
+            stack.push((0, root));
+        }
+
+        while let Some((from, page_num)) = stack.pop() {
+            if !self.resolve(from, page_num) || !self.claim(page_num, PageKind::Index) {
+                continue;
+            }
+            let page_size = self.header.page_size as usize;
+            let offset = HEADER_SIZE + (page_num - 1) as usize * page_size;
+            let page_data = &self.data[offset..offset + page_size];
+            let node = if self.header.format_version >= ENTRY_FORMAT_VERSION {
+                Node::unpack(page_data)
+            } else {
+                Node::unpack_legacy(page_data)
+            };
+            let node = match node {
+                Ok(node) => node,
+                Err(_) => {
+                    self.issue(VerifyIssue::MalformedIndexNode { page: page_num });
+                    continue;
+                }
+            };
+
+            match node {
+                Node::Branch {
+                    children, spills, ..
+                } => {
+                    for spill in spills.into_iter().filter(|spill| *spill != 0) {
+                        self.walk_chain(page_num, spill, PageKind::Overflow, |page| {
+                            VerifyIssue::OverflowChainCycle { owner: page_num, page }
+                        });
+                    }
+                    stack.extend(children.into_iter().rev().map(|child| (page_num, child)));
+                }
+                Node::Leaf { keys, pages, spills } => {
+                    for ((mut key, data_page), spill) in keys.into_iter().zip(pages).zip(spills) {
+                        if spill != 0 {
+                            key.extend(self.read_chain(spill).unwrap_or_default());
+                            self.walk_chain(page_num, spill, PageKind::Overflow, |page| {
+                                VerifyIssue::OverflowChainCycle { owner: page_num, page }
+                            });
+                        }
+                        let location = (keyspace, key);
+                        if self.resolve(page_num, data_page) {
+                            self.claim(data_page, PageKind::Data);
+                            let stored = self
+                                .locations
+                                .get(&location)
+                                .is_some_and(|pages| pages.contains(&data_page));
+                            if !stored {
+                                self.issue(VerifyIssue::IndexMismatch {
+                                    key: location.1.clone(),
+                                    page: data_page,
+                                });
+                            }
+                        }
+                        indexed.insert(location);
+                    }
+                }
+            }
+        }
+    }
+
+
+    fn check_unindexed(&mut self, indexed: &HashSet<(u16, Vec<u8>)>) {
+        let mut missing: Vec<_> = self
+            .locations
+            .iter()
+            .filter(|(location, _)| !indexed.contains(*location))
+            .map(|((_, key), pages)| (pages[0], key.clone()))
+            .collect();
+        missing.sort();
+        self.report.issues.extend(
+            missing
+                .into_iter()
+                .map(|(page, key)| VerifyIssue::MissingFromIndex { key, page }),
+        );
+    }
+
+
+    fn check_orphans(&mut self) {
+        for page_num in 1..=self.header.total_pages {
+            let kind = match self.page(page_num) {
+                Some(info) => info.kind,
+                None => continue,
+            };
+            // 空数据页（如新库的第一页）不属于任何结构，不算泄漏
+            if kind != PageKind::Data && !self.claims.contains_key(&page_num) {
+                self.issue(VerifyIssue::OrphanPage {
+                    page: page_num,
+                    kind,
+                });
+            }
+        }
+    }
+}
+
+
+impl KVStore {
+    /// 以只读方式检查数据库文件的完整性，不回放 WAL、不升级格式、不修改文件
+    ///
+    /// 文件头无法识别（魔数错误、版本过新）时返回错误，其余问题都记录在报告中。
+    /// 检查期间所有键会被载入内存以查找重复键。
+    /// 与只读打开一样对文件加共享锁，文件被读写句柄打开时返回 [`KvError::Locked`]。
+    pub fn verify_file<P: AsRef<Path>>(path: P) -> Result<VerifyReport> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        // 读写句柄的 compact 会截断并替换文件，检查期间映射不能失效
+        lock_file(&file, path, true, None)?;
+        if file.metadata()?.len() < HEADER_SIZE as u64 {
+            bail!("Database file too small");
+        }
+        let mmap = unsafe { MmapOptions::new().map(&file)? };
+
+        let mut report = Verifier::new(&mmap)?.run();
+        report.wal_present = path.with_extension(WAL_FILE_EXT).exists();
+        Ok(report)
+    }
+
+
+    /// 检查本句柄映射的页面，见 [`KVStore::verify_file`]
+    ///
+    /// 检查的是包括尚未经检查点落盘的写入在内的当前状态，不提交，也不修改文件。
+    pub fn verify(&self) -> Result<VerifyReport> {
+        let mut report = Verifier::new(&self.mmap)?.run();
+        // 当前 WAL 中的操作都已应用到页面
+        report.wal_present = false;
+        Ok(report)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::seal_page;
+    use super::*;
+
+
+    // 写入一个占用多个溢出页的值后关闭，返回该值溢出链上的页号
+    fn store_with_chain(path: &Path, delete: bool) -> Result<Vec<u32>> {
+        let mut store = KVStore::open(path, None)?;
+        store.put(b"small", b"1")?;
+        store.put(b"large", &[b'x'; 4000])?;
+        let mut chain = Vec::new();
+        let mut current = store.find_stored_entry(b"large")?.unwrap().value_overflow;
+        while current != 0 {
+            chain.push(current);
+            current = PageHeader::unpack(&store.read_page(current)?)?.next_page;
+        }
+        if delete {
+            store.delete(b"large")?;
+        }
+        store.close()?;
+        assert!(KVStore::verify_file(path)?.is_ok());
+        Ok(chain)
+    }
+
+
+    // 修改文件中某页的 next_page 并重新计算校验和
+    fn relink(path: &Path, page_num: u32, next_page: u32) -> Result<()> {
+        let mut data = std::fs::read(path)?;
+        let page_size = DBHeader::unpack(&data[..HEADER_SIZE])?.page_size as usize;
+        let offset = HEADER_SIZE + (page_num - 1) as usize * page_size;
+        let page = &mut data[offset..offset + page_size];
+        let mut header = PageHeader::unpack(page)?;
+        header.next_page = next_page;
+        page[..PageHeader::SIZE].copy_from_slice(&header.pack());
+        seal_page(page);
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+
+
+    #[test]
+    fn reports_dangling_overflow_pointer() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("dangling.wkv");
+        let chain = store_with_chain(&path, false)?;
+        assert!(chain.len() >= 3);
+        relink(&path, chain[1], 9999)?;
+
+        let report = KVStore::verify_file(&path)?;
+        assert!(report.issues.contains(&VerifyIssue::DanglingPointer { from: chain[1], to: 9999 }), "{}", report);
+        // 链尾之后的页不再被引用
+        assert!(report.issues.contains(&VerifyIssue::OrphanPage {
+            page: chain[2],
+            kind: PageKind::Overflow,
+        }));
+        Ok(())
+    }
+
+
+    #[test]
+    fn reports_free_list_cycle() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("cycle.wkv");
+        store_with_chain(&path, true)?;
+        let head = DBHeader::unpack(&std::fs::read(&path)?[..HEADER_SIZE])?.free_page_head;
+        assert_ne!(head, 0);
+        let report = KVStore::verify_file(&path)?;
+        assert!(report.free_pages >= 3);
+
+        // 空闲链表的最后一页指回表头
+        let mut last = head;
+        let data = std::fs::read(&path)?;
+        let page_size = report.page_size;
+        loop {
+            let offset = HEADER_SIZE + (last - 1) as usize * page_size;
+            match PageHeader::unpack(&data[offset..])?.next_page {
+                0 => break,
+                next => last = next,
+            }
+        }
+        relink(&path, last, head)?;
+
+        let report = KVStore::verify_file(&path)?;
+        assert!(report.issues.contains(&VerifyIssue::FreeListCycle { page: head }), "{}", report);
+        Ok(())
+    }
+
+
+    #[test]
+    fn verify_file_refuses_a_locked_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("locked.wkv");
+        let mut store = KVStore::open(&path, None)?;
+        store.put(b"a", b"1")?;
+        let err = KVStore::verify_file(&path).unwrap_err();
+        assert!(matches!(err.downcast_ref::<KvError>(), Some(KvError::Locked(_))));
+
+        // 句柄自身的检查不提交，WAL 保持原样
+        let wal_path = path.with_extension(WAL_FILE_EXT);
+        let wal = std::fs::read(&wal_path)?;
+        assert!(store.verify()?.is_ok());
+        assert_eq!(std::fs::read(&wal_path)?, wal);
+        drop(store);
+        KVStore::verify_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+//! Wind-KVStore 存储引擎。
+//!
+//! 所有数据保存在一个文件中：128 字节的文件头之后是从 1 开始编号的定长页，
+//! 页大小（默认 1KB，最大 64KB）在创建文件时确定并记录在文件头中。
+//! 每次写入先追加到数据库旁边的 `.wal` 文件，再修改页面；检查点把页面落盘并清空 WAL，
+//! 崩溃后残留的记录在下次打开时重放。
+//!
+//! 错误以 [`anyhow::Error`] 返回，引擎自身的错误可以 downcast 为 [`KvError`]。
+//!
+//! ```no_run
+//! use wind_kvstore::KVStore;
+//!
+//! let mut store = KVStore::open("app.wkv", Some("MyAppDB"))?;
+//! store.put(b"username", b"alice")?;
+//! assert_eq!(store.get(b"username")?, Some(b"alice".to_vec()));
+//! store.close()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod kvstore;
+mod options;
+
+pub use kvstore::{
+    BlobReader, Codec, Compression, IndexInfo, KVCursor, KVStore, Keyspace, KeyspaceStats, KvError, PageKind,
+    SalvageReport, SharedKVStore, Snapshot, Transaction, VerifyIssue, VerifyReport, WalStats,
+    DEFAULT_KEYSPACE,
+};
+pub use options::{Durability, KVStoreOptions};
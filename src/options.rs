@@ -0,0 +1,244 @@
+use crate::kvstore::{Compression, KVStore};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub(crate) const DEFAULT_IDENTIFIER: &str = "KVStore";
+pub(crate) const DEFAULT_CACHE_SIZE: usize = 100 * 1024; // 100KB 缓存
+pub(crate) const DEFAULT_PAGE_SIZE: usize = 1024; // 1KB 页
+pub(crate) const DEFAULT_MAX_KEY_SIZE: usize = 4096; // 4KB 键
+pub(crate) const DEFAULT_WAL_CHECKPOINT_SIZE: u64 = 4 * 1024 * 1024; // WAL 超过 4MB 时检查点
+pub(crate) const DEFAULT_WAL_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+
+/// WAL 的落盘策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// 每次 put/delete 后对 WAL 执行 fsync（默认）
+    #[default]
+    Full,
+    /// 组提交：累积 `max_batch` 次写入或距上次 fsync 超过 `interval_ms` 毫秒时才 fsync，
+    /// 空闲时由后台线程按间隔补做。崩溃时最多丢失最近一批写入，文件本身仍可恢复
+    GroupCommit { interval_ms: u64, max_batch: usize },
+    /// 只写入操作系统缓冲区，不主动 fsync
+    OsBuffered,
+    /// 不写 WAL，适合批量导入，导入结束后应调用 [`KVStore::commit`]
+    ///
+    /// 上次检查点之后的写入在崩溃时全部丢失，文件可能需要用 [`KVStore::salvage`] 修复。
+    Off,
+}
+
+
+/// 打开数据库时使用的选项
+///
+/// ```no_run
+/// use wind_kvstore::{Durability, KVStoreOptions};
+///
+/// let store = KVStoreOptions::new("app.wkv")
+///     .identifier("MyAppDB")
+///     .cache_size(1024 * 1024)
+///     .durability(Durability::OsBuffered)
+///     .page_size(8 * 1024)
+///     .open()?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct KVStoreOptions {
+    pub(crate) path: PathBuf,
+    pub(crate) identifier: Option<String>,
+    pub(crate) cache_size: usize,
+    pub(crate) durability: Durability,
+    pub(crate) page_size: usize,
+    pub(crate) max_key_size: usize,
+    pub(crate) wal_checkpoint_size: u64,
+    pub(crate) wal_checkpoint_interval: Duration,
+    pub(crate) lock_timeout: Option<Duration>,
+    pub(crate) read_only: bool,
+    pub(crate) compression: Compression,
+}
+
+
+impl KVStoreOptions {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            identifier: None,
+            cache_size: DEFAULT_CACHE_SIZE,
+            durability: Durability::default(),
+            page_size: DEFAULT_PAGE_SIZE,
+            max_key_size: DEFAULT_MAX_KEY_SIZE,
+            wal_checkpoint_size: DEFAULT_WAL_CHECKPOINT_SIZE,
+            wal_checkpoint_interval: DEFAULT_WAL_CHECKPOINT_INTERVAL,
+            lock_timeout: None,
+            read_only: false,
+            compression: Compression::NONE,
+        }
+    }
+
+
+    /// 数据库标识，新建时写入文件头；打开已有文件时覆盖内存中的标识
+    pub fn identifier(mut self, identifier: &str) -> Self {
+        self.identifier = Some(identifier.to_string());
+        self
+    }
+
+
+    /// 页缓存的最大字节数
+    pub fn cache_size(mut self, bytes: usize) -> Self {
+        self.cache_size = bytes;
+        self
+    }
+
+
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+
+    /// 新建数据库的页大小，须为 1KB 到 64KB 之间的 2 的幂，默认 1KB
+    ///
+    /// 只在新建时生效，已有文件始终使用文件头中记录的页大小。
+    /// 页缓存按字节计，使用大页时应相应调大 [`cache_size`](Self::cache_size)。
+    pub fn page_size(mut self, bytes: usize) -> Self {
+        self.page_size = bytes;
+        self
+    }
+
+
+    /// 允许写入的最大键长，默认 4KB，不能超过 65535 字节
+    ///
+    /// 超过页大小 1/16 的键只在页内保存前缀，其余部分放入溢出页。
+    /// 超过上限的写入返回 [`KvError::KeyTooLarge`](crate::KvError::KeyTooLarge)。
+    pub fn max_key_size(mut self, bytes: usize) -> Self {
+        self.max_key_size = bytes;
+        self
+    }
+
+
+    /// WAL 达到该字节数后，下一次写入结束时自动执行检查点，默认 4MB，0 表示不按大小触发
+    pub fn wal_checkpoint_size(mut self, bytes: u64) -> Self {
+        self.wal_checkpoint_size = bytes;
+        self
+    }
+
+
+    /// 距上次检查点超过该时长后，下一次写入结束时自动执行检查点，默认 60 秒，
+    /// [`Duration::ZERO`] 表示不按时间触发
+    ///
+    /// 没有后台线程，空闲期间不会触发；需要时可直接调用 [`KVStore::checkpoint`]。
+    pub fn wal_checkpoint_interval(mut self, interval: Duration) -> Self {
+        self.wal_checkpoint_interval = interval;
+        self
+    }
+
+
+    /// 数据库文件被其他进程锁定时最多等待的时长，默认不等待
+    ///
+    /// 超时后打开失败并返回 [`KvError::Locked`](crate::KvError::Locked)。
+    pub fn lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = Some(timeout);
+        self
+    }
+
+
+    /// 只读打开已有的数据库，默认 false
+    ///
+    /// 文件以只读方式映射并加共享锁，多个只读句柄可以同时打开，但不能与读写句柄共存。
+    /// 写操作返回 [`KvError::ReadOnly`](crate::KvError::ReadOnly)；不升级旧格式，
+    /// 也不重放 WAL，看到的是最后一次写入文件的状态。
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+
+    /// 新建数据库的默认值压缩设置，默认不压缩
+    ///
+    /// 只在新建时生效并记录在文件头中，之后用 [`KVStore::set_compression`] 修改。
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+
+    /// 打开数据库，文件不存在时新建
+    pub fn open(&self) -> Result<KVStore> {
+        KVStore::open_with_options(self)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KvError;
+
+    // 文件头中页大小字段（u16）的位置：魔数 4 字节、标识 32 字节、两个时间戳各 8 字节之后
+    const PAGE_SIZE_OFFSET: usize = 52;
+
+
+    fn stored_page_size(path: &Path) -> u16 {
+        let data = std::fs::read(path).unwrap();
+        u16::from_le_bytes([data[PAGE_SIZE_OFFSET], data[PAGE_SIZE_OFFSET + 1]])
+    }
+
+
+    fn round_trip(page_size: usize, stored: u16) -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("pages.wkv");
+        let mut store = KVStoreOptions::new(&path).page_size(page_size).open()?;
+        assert_eq!(store.page_size(), page_size);
+        for i in 0..500 {
+            store.put(format!("key{:04}", i).as_bytes(), &[b'v'; 100])?;
+        }
+        store.put(b"large", &vec![b'l'; 3 * page_size])?;
+        store.close()?;
+        assert_eq!(stored_page_size(&path), stored);
+
+        // 已有文件使用文件头中的页大小，忽略选项
+        let store = KVStoreOptions::new(&path).page_size(4096).open()?;
+        assert_eq!(store.page_size(), page_size);
+        assert_eq!(store.get(b"key0499")?, Some(vec![b'v'; 100]));
+        assert_eq!(store.get(b"large")?, Some(vec![b'l'; 3 * page_size]));
+        assert_eq!(store.verify()?.page_size, page_size);
+        Ok(())
+    }
+
+
+    #[test]
+    fn smallest_page_size() -> Result<()> {
+        round_trip(1024, 1024)
+    }
+
+
+    #[test]
+    fn largest_page_size_is_stored_as_zero() -> Result<()> {
+        round_trip(64 * 1024, 0)
+    }
+
+
+    #[test]
+    fn rejects_invalid_page_sizes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for page_size in [0, 512, 3000, 128 * 1024] {
+            let path = dir.path().join(format!("invalid-{}.wkv", page_size));
+            let err = match KVStoreOptions::new(&path).page_size(page_size).open() {
+                Ok(_) => panic!("page size {} was accepted", page_size),
+                Err(e) => e,
+            };
+            assert!(
+                matches!(err.downcast_ref::<KvError>(), Some(KvError::InvalidPageSize(size)) if *size == page_size),
+                "{}",
+                err
+            );
+            assert!(!path.exists());
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use crate::config::load_config;
-use crate::kvstore::KVStore;
-use crate::utils::{format_header, format_session_id, get_client_ip, get_lan_ip, get_session_from_header, is_local_port_available, parse_compact, parse_delete_command, parse_get_command, parse_identifier_get, parse_identifier_set, parse_put_command, server_info, ParsedGetCommand};
+use wind_kvstore::{Durability, KVStore, KVStoreOptions, KvError, SharedKVStore};
+use crate::utils::{format_header, format_session_id, get_client_ip, get_lan_ip, get_session_from_header, is_local_port_available, parse_checkpoint, parse_compact, parse_compression_command, parse_counter_command, parse_delete_command, parse_expire_command, parse_get_command, parse_identifier_get, parse_identifier_set, parse_index_command, parse_keyspace_command, parse_persist_command, parse_purge, parse_put_command, parse_put_if_command, parse_put_ttl_command, parse_ttl_command, parse_verify, parse_version_command, server_info, where_range, CompressionCommand, CounterDelta, IndexCommand, KeyspaceCommand, ParsedGetCommand, PutCondition};
 use actix_web::{
     App, HttpRequest, HttpResponse, HttpServer, Responder, get, post,
     web::{self, Data, Json},
@@ -11,7 +11,9 @@ use dashmap::DashMap;
 use futures::executor::block_on;
 use log::error;
 use serde::{Deserialize, Serialize};
+use std::ops::Bound;
 use std::sync::Arc;
+use serde_json::Value;
 use std::sync::OnceLock;
 use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
@@ -19,6 +21,7 @@ use tokio::time;
 use uuid::Uuid;
 
 static PRINT_HEADER: OnceLock<bool> = OnceLock::new();
+static DURABILITY: OnceLock<Durability> = OnceLock::new();
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -29,7 +32,7 @@ struct Args {
 
 // 会话结构体
 struct Session {
-    store: Mutex<Option<KVStore>>,
+    store: Mutex<Option<SharedKVStore>>,
     last_active: Mutex<Instant>,
     current_path: Mutex<Option<String>>,
 }
@@ -44,6 +47,44 @@ impl Session {
     }
 }
 
+// 取出会话当前的数据库句柄。读写在会话锁之外进行，同一会话的读请求可以并行
+async fn session_store(session: &Session) -> Option<SharedKVStore> {
+    session.store.lock().await.clone()
+}
+
+// 数据库操作会读写文件并等待 SharedKVStore 的锁，放到阻塞线程池中执行，不占用 actix 的工作线程
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    web::block(f).await?
+}
+
+// 在请求指定的键空间（不指定时为会话当前的键空间）中读取，不存在的键空间不会被创建。
+// f 收到键空间名称，通过 *_in 方法读取，不切换键空间，所有读请求都持读锁并行执行
+async fn read_in<T: Send + 'static>(
+    store: SharedKVStore,
+    keyspace: Option<String>,
+    f: impl FnOnce(&KVStore, &str) -> Result<T> + Send + 'static,
+) -> Result<T> {
+    blocking(move || {
+        let store = store.read()?;
+        let keyspace = keyspace.unwrap_or_else(|| store.current_keyspace().to_string());
+        f(&store, &keyspace)
+    })
+    .await
+}
+
+// 在请求指定的键空间中写入，键空间不存在时创建
+async fn write_in<T: Send + 'static>(
+    store: SharedKVStore,
+    keyspace: Option<String>,
+    f: impl FnOnce(&mut KVStore) -> Result<T> + Send + 'static,
+) -> Result<T> {
+    blocking(move || match keyspace {
+        Some(name) => store.with_keyspace(&name, f),
+        None => f(&mut *store.write()?),
+    })
+    .await
+}
+
 // 会话管理器
 type SessionManager = Arc<DashMap<String, Arc<Session>>>;
 
@@ -52,6 +93,47 @@ type SessionManager = Arc<DashMap<String, Arc<Session>>>;
 struct KeyValueRequest {
     key: String,
     value: Option<String>,
+    keyspace: Option<String>, // 不设置时使用会话当前的键空间
+    ttl: Option<u64>, // 存活秒数，不设置时不过期
+    // 写入条件，最多指定一种
+    #[serde(default)]
+    if_absent: bool,
+    #[serde(default)]
+    if_present: bool,
+    expected: Option<String>, // 当前值等于它时才写入
+    version: Option<u64>,     // 版本号等于它时才写入
+}
+
+#[derive(Deserialize)]
+struct ExpireRequest {
+    key: String,
+    ttl: u64,
+    keyspace: Option<String>,
+}
+
+// 计数器的增量和结果，JSON 中的整数按整数计算，带小数的按浮点数计算
+#[derive(Deserialize, Serialize, Clone, Copy)]
+#[serde(untagged)]
+enum CounterNumber {
+    Int(i64),
+    Float(f64),
+}
+
+#[derive(Deserialize)]
+struct CounterRequest {
+    key: String,
+    by: Option<CounterNumber>, // 不设置时为 1，负数表示递减
+    keyspace: Option<String>,
+}
+
+// 按二级索引查询：指定 value 时查等于它的键，否则查 from 到 to 之间（都包含）的键
+#[derive(Deserialize)]
+struct FindRequest {
+    index: String,
+    value: Option<Value>,
+    from: Option<Value>,
+    to: Option<Value>,
+    keyspace: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -68,6 +150,25 @@ struct IdentifierRequest {
 struct KeyValueResponse {
     key: String,
     value: Option<String>,
+    version: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct FindResponse {
+    index: String,
+    results: Vec<KeyValueResponse>,
+}
+
+#[derive(Serialize)]
+struct TtlResponse {
+    key: String,
+    ttl: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct CounterResponse {
+    key: String,
+    value: CounterNumber,
 }
 
 #[derive(Serialize)]
@@ -84,6 +185,7 @@ struct StatusResponse {
 fn init_session_manager() -> SessionManager {
     let manager: SessionManager = Arc::new(DashMap::new());
     start_session_cleanup(manager.clone());
+    start_expiry_sweep(manager.clone());
     manager
 }
 
@@ -104,16 +206,36 @@ fn start_session_cleanup(manager: SessionManager) {
     });
 }
 
+// 过期键清理任务：读取时已看不到过期的键，这里定期把它们从文件中删除
+fn start_expiry_sweep(manager: SessionManager) {
+    tokio::spawn(async move {
+        let mut interval = time::interval(Duration::from_secs(60)); // 每分钟清理一次
+        loop {
+            interval.tick().await;
+            // 先取出所有会话，遍历时不持有 DashMap 的分片锁
+            let sessions: Vec<Arc<Session>> =
+                manager.iter().map(|entry| entry.value().clone()).collect();
+            for session in sessions {
+                if let Some(store) = session_store(&session).await
+                    && let Err(e) = blocking(move || store.purge_expired()).await
+                {
+                    error!("Failed to purge expired keys: {}", e);
+                }
+            }
+        }
+    });
+}
+
 // 获取或创建会话
 async fn get_or_create_session(
     sessions: Data<SessionManager>,
     session_id: Option<String>,
 ) -> (String, Arc<Session>) {
-    if let Some(id) = session_id {
-        if let Some(session) = sessions.get(&id) {
-            *session.last_active.lock().await = Instant::now();
-            return (id, session.clone());
-        }
+    if let Some((id, session)) = session_id
+        .and_then(|id| sessions.get(&id).map(|session| (id, session.clone())))
+    {
+        *session.last_active.lock().await = Instant::now();
+        return (id, session);
     }
 
     let new_id = Uuid::new_v4().to_string();
@@ -152,9 +274,18 @@ async fn open_db(
 
     let mut store = session.store.lock().await;
 
-    match KVStore::open(&req.path, None) {
+    // 先关闭会话中已打开的数据库，重新打开同一个文件时它还持有文件锁
+    if let Some(kv_store) = store.take()
+        && let Err(e) = blocking(move || kv_store.close()).await
+    {
+        error!("Failed to close database: {}", e);
+    }
+
+    let durability = DURABILITY.get().copied().unwrap_or_default();
+    let path = req.path.clone();
+    match blocking(move || KVStoreOptions::new(&path).durability(durability).open()).await {
         Ok(kv_store) => {
-            *store = Some(kv_store);
+            *store = Some(kv_store.into_shared());
             *session.current_path.lock().await = Some(req.path.clone());
             *session.last_active.lock().await = Instant::now();
 
@@ -196,13 +327,13 @@ async fn close_db(sessions: Data<SessionManager>, http_req: HttpRequest) -> impl
         }
     };
 
-    if let Some(kv_store) = store.take() {
-        if let Err(e) = kv_store.close() {
-            error!("Failed to close database: {}", e);
-            return HttpResponse::InternalServerError().json(StatusResponse {
-                status: format!("Error: {}", e),
-            });
-        }
+    if let Some(kv_store) = store.take()
+        && let Err(e) = blocking(move || kv_store.close()).await
+    {
+        error!("Failed to close database: {}", e);
+        return HttpResponse::InternalServerError().json(StatusResponse {
+            status: format!("Error: {}", e),
+        });
     }
 
     *session.current_path.lock().await = None;
@@ -232,9 +363,7 @@ async fn put_value(
     format_header(&http_req, PRINT_HEADER.clone());
 
     let (session_id, session) = get_or_create_session(sessions, Option::from(session_id)).await;
-    let mut store = session.store.lock().await;
-
-    let kv_store = match store.as_mut() {
+    let kv_store = match session_store(&session).await {
         Some(store) => store,
         None => {
             return HttpResponse::BadRequest().json(StatusResponse {
@@ -244,35 +373,81 @@ async fn put_value(
     };
 
     let mut success = 0;
+    let mut skipped = Vec::new();
     let mut errors = Vec::new();
 
-    for kv in req.iter() {
-        if let Some(value) = &kv.value {
-            if let Err(e) = kv_store.put(kv.key.as_bytes(), value.as_bytes()) {
-                errors.push(format!("{}: {}", kv.key, e));
-            } else {
-                success += 1;
+    for kv in req.into_inner() {
+        if let Some(value) = kv.value.clone() {
+            let (key, keyspace) = (kv.key.clone(), kv.keyspace.clone());
+            let result = write_in(kv_store.clone(), keyspace, move |store| {
+                put_with_options(store, &kv, value.as_bytes())
+            })
+            .await;
+            match result {
+                Ok(true) => success += 1,
+                Ok(false) => skipped.push(key),
+                Err(e) => errors.push(format!("{}: {}", key, e)),
             }
         }
     }
 
     *session.last_active.lock().await = Instant::now();
 
-    if errors.is_empty() {
-        HttpResponse::Ok()
+    if !errors.is_empty() {
+        HttpResponse::PartialContent()
             .insert_header(("X-Session-ID", session_id))
             .json(StatusResponse {
-                status: format!("Inserted {} key-value pairs", success),
+                status: format!("Inserted {}, errors: {:?}", success, errors),
+            })
+    } else if !skipped.is_empty() {
+        HttpResponse::Conflict()
+            .insert_header(("X-Session-ID", session_id))
+            .json(StatusResponse {
+                status: format!("Inserted {} key-value pairs, condition not met for {:?}", success, skipped),
             })
     } else {
-        HttpResponse::PartialContent()
+        HttpResponse::Ok()
             .insert_header(("X-Session-ID", session_id))
             .json(StatusResponse {
-                status: format!("Inserted {}, errors: {:?}", success, errors),
+                status: format!("Inserted {} key-value pairs", success),
             })
     }
 }
 
+// 按请求中的选项写入一个键值对，条件不满足时返回 false
+fn put_with_options(store: &mut KVStore, kv: &KeyValueRequest, value: &[u8]) -> Result<bool> {
+    let key = kv.key.as_bytes();
+    let conditions = [kv.if_absent, kv.if_present, kv.expected.is_some(), kv.version.is_some()]
+        .into_iter()
+        .filter(|given| *given)
+        .count();
+    if conditions > 1 {
+        return Err(anyhow!("Only one of if_absent, if_present, expected and version may be given"));
+    }
+    if conditions == 1 && kv.ttl.is_some() {
+        return Err(anyhow!("ttl cannot be combined with a write condition"));
+    }
+
+    if kv.if_absent {
+        return store.put_if_absent(key, value);
+    }
+    if kv.if_present {
+        return store.replace_if_present(key, value);
+    }
+    if let Some(expected) = &kv.expected {
+        return store.compare_and_swap(key, Some(expected.as_bytes()), value);
+    }
+    if let Some(version) = kv.version {
+        return store.put_if_version(key, version, value);
+    }
+
+    match kv.ttl {
+        Some(ttl) => store.put_with_ttl(key, value, Duration::from_secs(ttl))?,
+        None => store.put(key, value)?,
+    }
+    Ok(true)
+}
+
 
 #[get("/api/get")]
 async fn get_value(
@@ -290,9 +465,7 @@ async fn get_value(
     format_header(&http_req, PRINT_HEADER.clone());
 
     let (session_id, session) = get_or_create_session(sessions, Option::from(session_id)).await;
-    let mut store = session.store.lock().await;
-
-    let kv_store = match store.as_mut() {
+    let kv_store = match session_store(&session).await {
         Some(store) => store,
         None => {
             return HttpResponse::BadRequest().json(StatusResponse {
@@ -301,8 +474,13 @@ async fn get_value(
         }
     };
 
-    match kv_store.get(query.key.as_bytes()) {
-        Ok(Some(value)) => {
+    let key = query.key.clone();
+    let result = read_in(kv_store, query.keyspace.clone(), move |store, keyspace| {
+        store.get_with_version_in(keyspace, key.as_bytes())
+    })
+    .await;
+    match result {
+        Ok(Some((value, version))) => {
             let value_str = String::from_utf8(value).unwrap_or_else(|_| "<BINARY>".to_string());
             *session.last_active.lock().await = Instant::now();
 
@@ -311,6 +489,7 @@ async fn get_value(
                 .json(KeyValueResponse {
                     key: query.key.clone(),
                     value: Some(value_str),
+                    version: Some(version),
                 })
         }
         Ok(None) => {
@@ -320,6 +499,7 @@ async fn get_value(
                 .json(KeyValueResponse {
                     key: query.key.clone(),
                     value: None,
+                    version: None,
                 })
         }
         Err(e) => {
@@ -350,9 +530,7 @@ async fn delete_value(
 
     let (session_id, session) = get_or_create_session(sessions, Option::from(session_id)).await;
 
-    let mut store = session.store.lock().await;
-
-    let kv_store = match store.as_mut() {
+    let kv_store = match session_store(&session).await {
         Some(store) => store,
         None => {
             return HttpResponse::BadRequest().json(StatusResponse {
@@ -361,7 +539,8 @@ async fn delete_value(
         }
     };
 
-    match kv_store.delete(req.key.as_bytes()) {
+    let key = req.key.clone();
+    match write_in(kv_store, req.keyspace.clone(), move |store| store.delete(key.as_bytes())).await {
         Ok(_) => {
             *session.last_active.lock().await = Instant::now();
             HttpResponse::Ok()
@@ -380,6 +559,297 @@ async fn delete_value(
 }
 
 
+#[post("/api/expire")]
+async fn expire_value(
+    sessions: Data<SessionManager>,
+    req: Json<ExpireRequest>,
+    http_req: HttpRequest,
+) -> impl Responder {
+    server_info(
+        get_client_ip(&http_req).as_str(),
+        http_req.method().as_str(),
+        http_req.path(),
+    );
+
+    let session_id = get_session_from_header(&http_req);
+    format_session_id(&session_id);
+    format_header(&http_req, PRINT_HEADER.clone());
+
+    let (session_id, session) = get_or_create_session(sessions, Option::from(session_id)).await;
+
+    let kv_store = match session_store(&session).await {
+        Some(store) => store,
+        None => {
+            return HttpResponse::BadRequest().json(StatusResponse {
+                status: "No database open".to_string(),
+            });
+        }
+    };
+
+    let (key, ttl) = (req.key.clone(), req.ttl);
+    let result = write_in(kv_store, req.keyspace.clone(), move |store| {
+        store.expire(key.as_bytes(), Duration::from_secs(ttl))
+    })
+    .await;
+    match result {
+        Ok(found) => {
+            *session.last_active.lock().await = Instant::now();
+            let status = if found {
+                format!("Key expires in {} seconds", req.ttl)
+            } else {
+                "Key not found".to_string()
+            };
+            HttpResponse::Ok()
+                .insert_header(("X-Session-ID", session_id))
+                .json(StatusResponse { status })
+        }
+        Err(e) => {
+            error!("Expire error: {}", e);
+            HttpResponse::InternalServerError().json(StatusResponse {
+                status: format!("Error: {}", e),
+            })
+        }
+    }
+}
+
+
+#[get("/api/ttl")]
+async fn get_ttl(
+    sessions: Data<SessionManager>,
+    query: web::Query<KeyValueRequest>,
+    http_req: HttpRequest,
+) -> impl Responder {
+    server_info(
+        get_client_ip(&http_req).as_str(),
+        http_req.method().as_str(),
+        http_req.path(),
+    );
+    let session_id = get_session_from_header(&http_req);
+    format_session_id(&session_id);
+    format_header(&http_req, PRINT_HEADER.clone());
+
+    let (session_id, session) = get_or_create_session(sessions, Option::from(session_id)).await;
+    let kv_store = match session_store(&session).await {
+        Some(store) => store,
+        None => {
+            return HttpResponse::BadRequest().json(StatusResponse {
+                status: "No database open".to_string(),
+            });
+        }
+    };
+
+    let key = query.key.clone();
+    match read_in(kv_store, query.keyspace.clone(), move |store, keyspace| store.ttl_in(keyspace, key.as_bytes())).await {
+        Ok(ttl) => {
+            *session.last_active.lock().await = Instant::now();
+            HttpResponse::Ok()
+                .insert_header(("X-Session-ID", session_id))
+                .json(TtlResponse {
+                    key: query.key.clone(),
+                    ttl: ttl.map(|ttl| ttl.as_secs()),
+                })
+        }
+        Err(e) => {
+            error!("TTL error: {}", e);
+            HttpResponse::InternalServerError().json(StatusResponse {
+                status: format!("Error: {}", e),
+            })
+        }
+    }
+}
+
+
+#[post("/api/persist")]
+async fn persist_value(
+    sessions: Data<SessionManager>,
+    req: Json<KeyValueRequest>,
+    http_req: HttpRequest,
+) -> impl Responder {
+    server_info(
+        get_client_ip(&http_req).as_str(),
+        http_req.method().as_str(),
+        http_req.path(),
+    );
+
+    let session_id = get_session_from_header(&http_req);
+    format_session_id(&session_id);
+    format_header(&http_req, PRINT_HEADER.clone());
+
+    let (session_id, session) = get_or_create_session(sessions, Option::from(session_id)).await;
+
+    let kv_store = match session_store(&session).await {
+        Some(store) => store,
+        None => {
+            return HttpResponse::BadRequest().json(StatusResponse {
+                status: "No database open".to_string(),
+            });
+        }
+    };
+
+    let key = req.key.clone();
+    match write_in(kv_store, req.keyspace.clone(), move |store| store.persist(key.as_bytes())).await {
+        Ok(removed) => {
+            *session.last_active.lock().await = Instant::now();
+            let status = if removed {
+                "Expiration removed"
+            } else {
+                "Key not found or does not expire"
+            };
+            HttpResponse::Ok()
+                .insert_header(("X-Session-ID", session_id))
+                .json(StatusResponse {
+                    status: status.to_string(),
+                })
+        }
+        Err(e) => {
+            error!("Persist error: {}", e);
+            HttpResponse::InternalServerError().json(StatusResponse {
+                status: format!("Error: {}", e),
+            })
+        }
+    }
+}
+
+
+#[post("/api/incr")]
+async fn incr_value(
+    sessions: Data<SessionManager>,
+    req: Json<CounterRequest>,
+    http_req: HttpRequest,
+) -> impl Responder {
+    server_info(
+        get_client_ip(&http_req).as_str(),
+        http_req.method().as_str(),
+        http_req.path(),
+    );
+
+    let session_id = get_session_from_header(&http_req);
+    format_session_id(&session_id);
+    format_header(&http_req, PRINT_HEADER.clone());
+
+    let (session_id, session) = get_or_create_session(sessions, Option::from(session_id)).await;
+
+    let kv_store = match session_store(&session).await {
+        Some(store) => store,
+        None => {
+            return HttpResponse::BadRequest().json(StatusResponse {
+                status: "No database open".to_string(),
+            });
+        }
+    };
+
+    let (key, by) = (req.key.clone(), req.by.unwrap_or(CounterNumber::Int(1)));
+    let result = write_in(kv_store, req.keyspace.clone(), move |store| {
+        let key = key.as_bytes();
+        match by {
+            CounterNumber::Int(by) => store.incr_by(key, by).map(CounterNumber::Int),
+            CounterNumber::Float(by) => store.incr_by_float(key, by).map(CounterNumber::Float),
+        }
+    })
+    .await;
+
+    match result {
+        Ok(value) => {
+            *session.last_active.lock().await = Instant::now();
+            HttpResponse::Ok()
+                .insert_header(("X-Session-ID", session_id))
+                .json(CounterResponse {
+                    key: req.key.clone(),
+                    value,
+                })
+        }
+        // 值不是数字或结果溢出是请求的问题，值保持不变
+        Err(e) if matches!(
+            e.downcast_ref::<KvError>(),
+            Some(KvError::NotNumeric(_) | KvError::CounterOverflow(_) | KvError::NonFiniteDelta(_))
+        ) => HttpResponse::BadRequest().json(StatusResponse {
+            status: format!("Error: {}", e),
+        }),
+        Err(e) => {
+            error!("Incr error: {}", e);
+            HttpResponse::InternalServerError().json(StatusResponse {
+                status: format!("Error: {}", e),
+            })
+        }
+    }
+}
+
+
+#[post("/api/find")]
+async fn find_values(
+    sessions: Data<SessionManager>,
+    req: Json<FindRequest>,
+    http_req: HttpRequest,
+) -> impl Responder {
+    server_info(
+        get_client_ip(&http_req).as_str(),
+        http_req.method().as_str(),
+        http_req.path(),
+    );
+
+    let session_id = get_session_from_header(&http_req);
+    format_session_id(&session_id);
+    format_header(&http_req, PRINT_HEADER.clone());
+
+    let (session_id, session) = get_or_create_session(sessions, Option::from(session_id)).await;
+
+    let kv_store = match session_store(&session).await {
+        Some(store) => store,
+        None => {
+            return HttpResponse::BadRequest().json(StatusResponse {
+                status: "No database open".to_string(),
+            });
+        }
+    };
+
+    let find = req.into_inner();
+    let index_name = find.index.clone();
+    let result = read_in(kv_store, find.keyspace.clone(), move |store, keyspace| {
+        if !store.indexes_in(keyspace)?.iter().any(|info| info.name == find.index) {
+            return Ok(None);
+        }
+        let range = match find.value {
+            Some(value) => (Bound::Included(value.clone()), Bound::Included(value)),
+            None => (
+                find.from.map_or(Bound::Unbounded, Bound::Included),
+                find.to.map_or(Bound::Unbounded, Bound::Included),
+            ),
+        };
+        store.find_by_index_range_in(keyspace, &find.index, range).map(Some)
+    })
+    .await;
+
+    match result {
+        Ok(Some(pairs)) => {
+            *session.last_active.lock().await = Instant::now();
+            let results = pairs
+                .into_iter()
+                .map(|(key, value)| KeyValueResponse {
+                    key: String::from_utf8_lossy(&key).into_owned(),
+                    value: Some(String::from_utf8(value).unwrap_or_else(|_| "<BINARY>".to_string())),
+                    version: None,
+                })
+                .collect();
+            HttpResponse::Ok()
+                .insert_header(("X-Session-ID", session_id))
+                .json(FindResponse {
+                    index: index_name,
+                    results,
+                })
+        }
+        Ok(None) => HttpResponse::NotFound().json(StatusResponse {
+            status: format!("Index {} not found", index_name),
+        }),
+        Err(e) => {
+            error!("Find error: {}", e);
+            HttpResponse::InternalServerError().json(StatusResponse {
+                status: format!("Error: {}", e),
+            })
+        }
+    }
+}
+
+
 #[get("/api/id/get")]
 async fn get_identifier(sessions: Data<SessionManager>, http_req: HttpRequest) -> impl Responder {
     server_info(
@@ -393,9 +863,7 @@ async fn get_identifier(sessions: Data<SessionManager>, http_req: HttpRequest) -
 
     let (session_id, session) = get_or_create_session(sessions, Option::from(session_id)).await;
 
-    let store = session.store.lock().await;
-
-    let kv_store = match store.as_ref() {
+    let kv_store = match session_store(&session).await {
         Some(store) => store,
         None => {
             return HttpResponse::BadRequest().json(StatusResponse {
@@ -406,11 +874,19 @@ async fn get_identifier(sessions: Data<SessionManager>, http_req: HttpRequest) -
 
     *session.last_active.lock().await = Instant::now();
 
+    let identifier = match blocking(move || Ok(kv_store.read()?.get_identifier().to_string())).await {
+        Ok(identifier) => identifier,
+        Err(e) => {
+            error!("Get identifier error: {}", e);
+            return HttpResponse::InternalServerError().json(StatusResponse {
+                status: format!("Error: {}", e),
+            });
+        }
+    };
+
     HttpResponse::Ok()
         .insert_header(("X-Session-ID", session_id))
-        .json(IdentifierResponse {
-            identifier: kv_store.get_identifier().to_string(),
-        })
+        .json(IdentifierResponse { identifier })
 }
 
 
@@ -431,9 +907,7 @@ async fn set_identifier(
 
     let (session_id, session) = get_or_create_session(sessions, Option::from(session_id)).await;
 
-    let mut store = session.store.lock().await;
-
-    let kv_store = match store.as_mut() {
+    let kv_store = match session_store(&session).await {
         Some(store) => store,
         None => {
             return HttpResponse::BadRequest().json(StatusResponse {
@@ -442,7 +916,8 @@ async fn set_identifier(
         }
     };
 
-    match kv_store.set_identifier(&req.identifier) {
+    let identifier = req.identifier.clone();
+    match blocking(move || kv_store.write()?.set_identifier(&identifier)).await {
         Ok(_) => {
             *session.last_active.lock().await = Instant::now();
             HttpResponse::Ok()
@@ -503,9 +978,7 @@ async fn compact_db(sessions: Data<SessionManager>, http_req: HttpRequest) -> im
 
     let (session_id, session) = get_or_create_session(sessions, Option::from(session_id)).await;
 
-    let mut store = session.store.lock().await;
-
-    let kv_store = match store.as_mut() {
+    let kv_store = match session_store(&session).await {
         Some(store) => store,
         None => {
             return HttpResponse::BadRequest().json(StatusResponse {
@@ -514,7 +987,7 @@ async fn compact_db(sessions: Data<SessionManager>, http_req: HttpRequest) -> im
         }
     };
 
-    match kv_store.compact() {
+    match blocking(move || kv_store.write()?.compact()).await {
         Ok(_) => {
             *session.last_active.lock().await = Instant::now();
             HttpResponse::Ok()
@@ -551,9 +1024,7 @@ async fn execute_command(
 
     let (session_id, session) = get_or_create_session(sessions, Option::from(session_id)).await;
 
-    let mut store = session.store.lock().await;
-
-    let kv_store = match store.as_mut() {
+    let kv_store = match session_store(&session).await {
         Some(store) => store,
         None => {
             return HttpResponse::BadRequest().json(StatusResponse {
@@ -563,24 +1034,26 @@ async fn execute_command(
     };
 
     // 复用shell中的命令解析逻辑
-    let mut arr: Vec<String> = Vec::new();
-    for cmd in command.split(";") {
-        if cmd == "" || cmd == " " {
-            continue;
-        }
-        // println!("cmd: {}", cmd);
-        arr.push(format!(
-            "\"{}: {}{}\"",
-            cmd,
-            parse_and_execute(cmd, kv_store)
-                .await
-                .unwrap_or_else(|e| format!("Error: {}", e)),
-            ";"
-        ));
-    }
+    let result = blocking(move || {
+        let mut arr: Vec<String> = Vec::new();
+        for cmd in command.split(";") {
+            if cmd.is_empty() || cmd == " " {
+                continue;
+            }
+            // println!("cmd: {}", cmd);
+            arr.push(format!(
+                "\"{}: {}{}\"",
+                cmd,
+                parse_and_execute(cmd, &kv_store).unwrap_or_else(|e| format!("Error: {}", e)),
+                ";"
+            ));
+        }
+        Ok(arr.join(" "))
+    })
+    .await
+    .unwrap_or_else(|e| format!("Error: {}", e));
     *session.last_active.lock().await = Instant::now();
 
-    let result = arr.join(" ");
     // println!("RESULT: {}", result);
 
     HttpResponse::Ok()
@@ -588,10 +1061,44 @@ async fn execute_command(
         .json(StatusResponse { status: result })
 }
 
-// 命令解析和执行
-async fn parse_and_execute(command: &str, store: &mut KVStore) -> Result<String> {
+// 命令解析和执行，在阻塞线程池中调用
+fn parse_and_execute(command: &str, store: &SharedKVStore) -> Result<String> {
     let command = command.trim();
 
+    // 解析带条件的PUT命令
+    if let Ok((kvs, condition)) = parse_put_if_command(command) {
+        let mut success = 0;
+        let mut skipped = Vec::new();
+        for (key, value) in kvs {
+            let (k, v) = (key.as_bytes(), value.as_bytes());
+            let applied = match &condition {
+                PutCondition::Absent => store.put_if_absent(k, v)?,
+                PutCondition::Present => store.replace_if_present(k, v)?,
+                PutCondition::Value(expected) => store.compare_and_swap(k, Some(expected.as_bytes()), v)?,
+                PutCondition::Version(version) => store.put_if_version(k, *version, v)?,
+            };
+            if applied {
+                success += 1;
+            } else {
+                skipped.push(key);
+            }
+        }
+        if skipped.is_empty() {
+            return Ok(format!("Inserted {} key-value pairs", success));
+        }
+        return Ok(format!("Inserted {} key-value pairs, condition not met for {:?}", success, skipped));
+    }
+
+    // 解析带过期时间的PUT命令
+    if let Ok((kvs, ttl)) = parse_put_ttl_command(command) {
+        let mut success = 0;
+        for (key, value) in kvs {
+            store.put_with_ttl(key.as_bytes(), value.as_bytes(), Duration::from_secs(ttl))?;
+            success += 1;
+        }
+        return Ok(format!("Inserted {} key-value pairs, expiring in {} seconds", success, ttl));
+    }
+
     // 解析PUT命令
     if let Ok(kvs) = parse_put_command(command) {
         let mut success = 0;
@@ -610,6 +1117,25 @@ async fn parse_and_execute(command: &str, store: &mut KVStore) -> Result<String>
         return Ok("Key not found".to_string());
     }
 
+    // 解析GET WHERE VALUE命令，使用当前键空间中建在该字段上的索引
+    if let Ok(ParsedGetCommand::Where(pointer, op, value)) = parse_get_command(command) {
+        let store = store.read()?;
+        let info = store
+            .indexes()
+            .into_iter()
+            .find(|info| info.pointer == pointer)
+            .ok_or_else(|| anyhow!("No index on {} in keyspace '{}'", pointer, store.current_keyspace()))?;
+        let pairs: Vec<String> = store
+            .find_by_index_range(&info.name, where_range(op, value))?
+            .iter()
+            .map(|(k, v)| format!("\"{}\": \"{}\"", String::from_utf8_lossy(k), String::from_utf8_lossy(v)))
+            .collect();
+        if pairs.is_empty() {
+            return Ok("No data found".to_string());
+        }
+        return Ok(pairs.join(", "));
+    }
+
 
     // 解析DELETE命令
     if let Ok(key) = parse_delete_command(command) {
@@ -617,20 +1143,146 @@ async fn parse_and_execute(command: &str, store: &mut KVStore) -> Result<String>
         return Ok("Key deleted".to_string());
     }
 
+    // 解析EXPIRE命令
+    if let Ok((key, ttl)) = parse_expire_command(command) {
+        if store.expire(key.as_bytes(), Duration::from_secs(ttl))? {
+            return Ok(format!("Key expires in {} seconds", ttl));
+        }
+        return Ok("Key not found".to_string());
+    }
+
+    // 解析TTL命令
+    if let Ok(key) = parse_ttl_command(command) {
+        return match store.ttl(key.as_bytes())? {
+            Some(ttl) => Ok(format!("{} seconds", ttl.as_secs())),
+            None if store.get(key.as_bytes())?.is_some() => Ok("Key does not expire".to_string()),
+            None => Ok("Key not found".to_string()),
+        };
+    }
+
+    // 解析PERSIST命令
+    if let Ok(key) = parse_persist_command(command) {
+        if store.persist(key.as_bytes())? {
+            return Ok("Expiration removed".to_string());
+        }
+        return Ok("Key not found or does not expire".to_string());
+    }
+
+    // 解析VERSION命令
+    if let Ok(key) = parse_version_command(command) {
+        return match store.version(key.as_bytes())? {
+            Some(version) => Ok(version.to_string()),
+            None => Ok("Key not found".to_string()),
+        };
+    }
+
+    // 解析INCR/DECR命令
+    if let Ok((key, delta)) = parse_counter_command(command) {
+        return match delta {
+            CounterDelta::Int(delta) => Ok(store.incr_by(key.as_bytes(), delta)?.to_string()),
+            CounterDelta::Float(delta) => Ok(store.incr_by_float(key.as_bytes(), delta)?.to_string()),
+        };
+    }
+
+    // 解析USE/SHOW KEYSPACES/CLEAR KEYSPACE/DROP KEYSPACE命令
+    if let Ok(cmd) = parse_keyspace_command(command) {
+        return match cmd {
+            KeyspaceCommand::Use(name) => {
+                store.use_keyspace(&name)?;
+                Ok(format!("Using keyspace '{}'", name))
+            }
+            KeyspaceCommand::Show => {
+                let store = store.read()?;
+                let mut names = Vec::new();
+                for name in store.keyspaces() {
+                    let stats = store.keyspace_stats(&name)?.unwrap_or_default();
+                    let marker = if name == store.current_keyspace() { "*" } else { "" };
+                    names.push(format!("{}{} ({})", marker, name, stats));
+                }
+                Ok(names.join(", "))
+            }
+            KeyspaceCommand::Clear(name) => match store.clear_keyspace(&name)? {
+                true => Ok(format!("Keyspace '{}' cleared", name)),
+                false => Ok("Keyspace not found".to_string()),
+            },
+            KeyspaceCommand::Drop(name) => match store.drop_keyspace(&name)? {
+                true => Ok(format!("Keyspace '{}' dropped", name)),
+                false => Ok("Keyspace not found".to_string()),
+            },
+        };
+    }
+
+    // 解析CREATE INDEX/DROP INDEX/SHOW INDEXES命令
+    if let Ok(cmd) = parse_index_command(command) {
+        return match cmd {
+            IndexCommand::Create(name, pointer) => match store.create_index(&name, &pointer)? {
+                true => Ok(format!("Index '{}' created on {}", name, pointer)),
+                false => Ok("Index already exists".to_string()),
+            },
+            IndexCommand::Drop(name) => match store.drop_index(&name)? {
+                true => Ok(format!("Index '{}' dropped", name)),
+                false => Ok("Index not found".to_string()),
+            },
+            IndexCommand::Show => {
+                let indexes: Vec<String> = store
+                    .indexes()?
+                    .iter()
+                    .map(|info| format!("{} ON {}", info.name, info.pointer))
+                    .collect();
+                Ok(indexes.join(", "))
+            }
+        };
+    }
+
+    // 解析SET COMPRESSION/SHOW COMPRESSION命令
+    if let Ok(cmd) = parse_compression_command(command) {
+        let mut store = store.write()?;
+        return match cmd {
+            CompressionCommand::Set(compression) => {
+                store.set_compression(compression)?;
+                Ok(format!("Compression of keyspace '{}' set to {}", store.current_keyspace(), compression))
+            }
+            CompressionCommand::Show => Ok(format!(
+                "Keyspace '{}' uses compression {}",
+                store.current_keyspace(),
+                store.compression()
+            )),
+        };
+    }
+
+    // 解析PURGE命令
+    if parse_purge(command).is_ok() {
+        let count = store.purge_expired()?;
+        return Ok(format!("Purged {} expired keys", count));
+    }
+
     // 解析COMPACT命令
     if parse_compact(command).is_ok() {
-        store.compact()?;
+        store.write()?.compact()?;
         return Ok("Database compacted".to_string());
     }
 
+    // 解析CHECKPOINT命令
+    if parse_checkpoint(command).is_ok() {
+        let mut store = store.write()?;
+        let size = store.wal_stats().size;
+        store.checkpoint()?;
+        return Ok(format!("Checkpoint complete, {} bytes of WAL truncated", size));
+    }
+
+    // 解析VERIFY命令
+    if parse_verify(command).is_ok() {
+        return Ok(store.read()?.verify()?.to_string());
+    }
+
     // 解析IDENTIFIER GET命令
     if parse_identifier_get(command).is_ok() {
-        return Ok(store.get_identifier().to_string());
+        return Ok(store.read()?.get_identifier().to_string());
     }
 
     // 解析IDENTIFIER SET命令
     if let Ok(id) = parse_identifier_set(command) {
-        store.set_identifier(&id)?;
+        store.write()?.set_identifier(&id)?;
         return Ok(format!("Identifier set to '{}'", id));
     }
 
@@ -646,10 +1298,13 @@ pub async fn run_server() -> Result<()> {
     // println!("{:?}", PRINT_HEADER);
 
     let config = load_config()?;
+    DURABILITY
+        .set(config.durability)
+        .expect("Global durability init succeed.");
 
     let sessions = init_session_manager();
 
-    if !is_local_port_available(config.host.clone(), config.port.clone()) {
+    if !is_local_port_available(config.host.clone(), config.port) {
         return Err(anyhow::anyhow!(
             " * Port `{}` on Host `{}` is already in use.",
             config.port,
@@ -659,10 +1314,10 @@ pub async fn run_server() -> Result<()> {
 
     println!(" * Starting Wind-KVStore Server...");
     if config.host == "0.0.0.0" {
-        println!(" * Server start on: http://{}:{}", "127.0.0.1", config.port);
+        println!(" * Server start on: http://127.0.0.1:{}", config.port);
         println!(
             " * Server start on: http://{}:{}",
-            get_lan_ip().unwrap().to_string(),
+            get_lan_ip().unwrap(),
             config.port
         );
     } else {
@@ -677,6 +1332,11 @@ pub async fn run_server() -> Result<()> {
             .service(put_value)
             .service(get_value)
             .service(delete_value)
+            .service(expire_value)
+            .service(get_ttl)
+            .service(persist_value)
+            .service(incr_value)
+            .service(find_values)
             .service(get_identifier)
             .service(set_identifier)
             .service(get_current)
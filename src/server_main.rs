@@ -1,5 +1,4 @@
 mod config;
-mod kvstore;
 mod utils;
 mod server;
 
@@ -1,18 +1,40 @@
-use crate::kvstore::KVStore;
+use wind_kvstore::{KVStore, DEFAULT_KEYSPACE};
 use crate::utils::{
     parse_put_command,
+    parse_put_ttl_command,
+    parse_put_if_command,
     parse_get_command,
     parse_delete_command,
+    parse_expire_command,
+    parse_ttl_command,
+    parse_persist_command,
+    parse_version_command,
+    parse_counter_command,
+    parse_keyspace_command,
+    parse_index_command,
+    parse_compression_command,
     parse_identifier_get,
     parse_identifier_set,
     parse_compact,
+    parse_checkpoint,
+    parse_verify,
+    parse_purge,
     output_tile,
-    ParsedGetCommand
+    where_range,
+    ParsedGetCommand,
+    PutCondition,
+    CounterDelta,
+    CompareOp,
+    KeyspaceCommand,
+    IndexCommand,
+    CompressionCommand
 };
 use anyhow::{anyhow, Result};
 use linefeed::{Interface, ReadResult};
+use serde_json::Value;
 use std::path::Path;
 use std::process::Command;
+use std::time::Duration;
 use std::env::consts::OS;
 
 
@@ -25,6 +47,8 @@ const HELP_MSG: &str = concat!(
                                 "    .help                    Show this message.\n",
                                 "    .quit                    Exit KVStore shell.\n",
                                 "    .open <path>             Open kvstore at the specified path.\n",
+                                "    .verify <path>           Check a kvstore file for corruption without opening it.\n",
+                                "    .salvage <path> <new>    Rebuild a damaged kvstore file into a new file.\n",
                                 "    .close                   Close current kvstore.\n",
                                 "    .clear                   Execute screen clear command.\n",
                                 "    .title                   Show KVStore's startup information.\n",
@@ -34,6 +58,47 @@ const HELP_MSG: &str = concat!(
                                 "    GET WHERE KEY=\"MyKey\"    Retrieve the value associated with key \"MyKey\".\n",
                                 "    DEL WHERE KEY=\"MyKey\"    Remove the key-value pair \"MyKey\".\n",
                                 "    COMPACT                  Compress the currently activity KV database.\n",
+                                "    CHECKPOINT               Flush pending pages and empty the WAL.\n",
+                                "    VERIFY                   Check the current KV database for corruption.\n",
+                                "\n",
+                                "EXPIRATION:\n",
+                                "    PUT \"KEY\":\"VALUE\" TTL 60             Insert pairs that expire after 60 seconds.\n",
+                                "    EXPIRE WHERE KEY=\"MyKey\" TTL 60      Let \"MyKey\" expire after 60 seconds.\n",
+                                "    TTL WHERE KEY=\"MyKey\"                Show the remaining lifetime of \"MyKey\".\n",
+                                "    PERSIST WHERE KEY=\"MyKey\"            Remove the expiration of \"MyKey\".\n",
+                                "    PURGE                                Delete all expired keys now.\n",
+                                "\n",
+                                "CONDITIONAL WRITE:\n",
+                                "    PUT \"KEY\":\"VALUE\" IF ABSENT          Insert only if the key does not exist.\n",
+                                "    PUT \"KEY\":\"VALUE\" IF PRESENT         Overwrite only if the key exists.\n",
+                                "    PUT \"KEY\":\"VALUE\" IF VALUE=\"OLD\"     Overwrite only if the value is \"OLD\".\n",
+                                "    PUT \"KEY\":\"VALUE\" IF VERSION=7       Overwrite only if the key is at version 7.\n",
+                                "    VERSION WHERE KEY=\"MyKey\"            Show the current version of \"MyKey\".\n",
+                                "\n",
+                                "COUNTER:\n",
+                                "    INCR WHERE KEY=\"MyKey\"               Add 1 to the number stored at \"MyKey\".\n",
+                                "    INCR WHERE KEY=\"MyKey\" BY 5          Add 5 (use 0.5 for a floating-point step).\n",
+                                "    DECR WHERE KEY=\"MyKey\" [BY 5]        Subtract 1 (or 5) from \"MyKey\".\n",
+                                "\n",
+                                "KEYSPACE:\n",
+                                "    USE users                            Switch to keyspace \"users\", creating it if needed.\n",
+                                "    SHOW KEYSPACES                       List keyspaces with key counts and compression.\n",
+                                "    CLEAR KEYSPACE users                 Delete every key in keyspace \"users\".\n",
+                                "    DROP KEYSPACE users                  Delete keyspace \"users\" and its keys.\n",
+                                "\n",
+                                "SECONDARY INDEX:\n",
+                                "    CREATE INDEX by_status ON VALUE.status  Index the JSON field \"status\" of every value.\n",
+                                "    GET WHERE VALUE.status=\"open\"         Find keys by an indexed field (also <, <=, >, >=).\n",
+                                "    SHOW INDEXES                            List the indexes of the current keyspace.\n",
+                                "    DROP INDEX by_status                    Delete index \"by_status\".\n",
+                                "    (Write JSON values with escaped quotes: PUT \"t1\":\"{\\\"status\\\": \\\"open\\\"}\")\n",
+                                "\n",
+                                "COMPRESSION:\n",
+                                "    SET COMPRESSION lz4                  Compress new values of the current keyspace with LZ4.\n",
+                                "    SET COMPRESSION zstd THRESHOLD 512   Use zstd for values of at least 512 bytes.\n",
+                                "    SET COMPRESSION none                 Store new values uncompressed.\n",
+                                "    SHOW COMPRESSION                     Show the compression of the current keyspace.\n",
+                                "    (Set in \"default\" it is the database default; SHOW KEYSPACES shows the ratio.)\n",
                                 "\n",
                                 "METADATA OPERATOR:\n",
                                 "    IDENTIFIER:\n",
@@ -49,7 +114,7 @@ const HELP_MSG: &str = concat!(
 fn clear_scene() {
     if OS == "windows" {
         Command::new("cmd")
-            .args(&["/C", "cls"])
+            .args(["/C", "cls"])
             .status()
             .expect("Failed to execute command");
     } else {
@@ -60,6 +125,7 @@ fn clear_scene() {
 }
 
 
+#[derive(Default)]
 pub struct Shell {
     store: Option<KVStore>,
     current_path: Option<String>,
@@ -130,7 +196,12 @@ impl Shell {
                     .and_then(|os_str| os_str.to_str())
                     .unwrap_or(path);
                 
-                format!("\n{} > ", file_name)
+                match self.store.as_ref().map(KVStore::current_keyspace) {
+                    Some(keyspace) if keyspace != DEFAULT_KEYSPACE => {
+                        format!("\n{}/{} > ", file_name, keyspace)
+                    }
+                    _ => format!("\n{} > ", file_name),
+                }
             
             }
             None => "\nKVStore > ".to_string(),
@@ -149,7 +220,15 @@ impl Shell {
             return Err(anyhow!("No database open. Use .open first"));
         }
         
-                if let Ok(cmd) = parse_put_command(command) {
+                if let Ok((cmd, condition)) = parse_put_if_command(command) {
+            return self.handle_put_if_command(cmd, condition);
+        }
+
+        if let Ok((cmd, ttl)) = parse_put_ttl_command(command) {
+            return self.handle_put_ttl_command(cmd, ttl);
+        }
+
+        if let Ok(cmd) = parse_put_command(command) {
             return self.handle_put_command(cmd);
         }
 
@@ -160,6 +239,9 @@ impl Shell {
             Ok(ParsedGetCommand::Key(key)) => {
                 return self.handle_get_command(key);
             }
+            Ok(ParsedGetCommand::Where(pointer, op, value)) => {
+                return self.handle_get_where_command(pointer, op, value);
+            }
             Err(_msg) => {
                             }         }
 
@@ -167,6 +249,42 @@ impl Shell {
             return self.handle_delete_command(key);
         }
         
+        if let Ok((key, ttl)) = parse_expire_command(command) {
+            return self.handle_expire_command(key, ttl);
+        }
+
+        if let Ok(key) = parse_ttl_command(command) {
+            return self.handle_ttl_command(key);
+        }
+
+        if let Ok(key) = parse_persist_command(command) {
+            return self.handle_persist_command(key);
+        }
+
+        if let Ok(key) = parse_version_command(command) {
+            return self.handle_version_command(key);
+        }
+
+        if let Ok((key, delta)) = parse_counter_command(command) {
+            return self.handle_counter_command(key, delta);
+        }
+
+        if let Ok(cmd) = parse_keyspace_command(command) {
+            return self.handle_keyspace_command(cmd);
+        }
+
+        if let Ok(cmd) = parse_index_command(command) {
+            return self.handle_index_command(cmd);
+        }
+
+        if let Ok(cmd) = parse_compression_command(command) {
+            return self.handle_compression_command(cmd);
+        }
+
+        if let Ok(()) = parse_purge(command) {
+            return self.handle_purge();
+        }
+
         if let Ok(()) = parse_identifier_get(command) {
             return self.handle_identifier_get();
         }
@@ -178,6 +296,14 @@ impl Shell {
         if let Ok(()) = parse_compact(command) {
             return self.handle_compact();
         }
+
+        if let Ok(()) = parse_checkpoint(command) {
+            return self.handle_checkpoint();
+        }
+
+        if let Ok(()) = parse_verify(command) {
+            return self.handle_verify();
+        }
         
         Err(anyhow!("Unknown command: {}", command))
     }
@@ -219,6 +345,24 @@ impl Shell {
                 }
                 self.open_database(path)
             }
+
+            cmd if cmd.starts_with(".verify") => {
+                let path = cmd.trim_start_matches(".verify").trim();
+                if path.is_empty() {
+                    return Err(anyhow!("Usage: .verify <path>"));
+                }
+                let report = KVStore::verify_file(path)?;
+                Ok(report.to_string())
+            }
+
+            cmd if cmd.starts_with(".salvage") => {
+                let args: Vec<&str> = cmd.trim_start_matches(".salvage").split_whitespace().collect();
+                if args.len() != 2 {
+                    return Err(anyhow!("Usage: .salvage <damaged path> <output path>"));
+                }
+                let report = KVStore::salvage(args[0], args[1])?;
+                Ok(report.to_string())
+            }
             _ => Err(anyhow!("Unknown meta command: {}", command)),
         }
     }
@@ -241,6 +385,12 @@ impl Shell {
             }
         }
 
+        // 先关闭当前数据库，重新打开同一个文件时它还持有文件锁
+        if let Some(store) = self.store.take() {
+            store.close()?;
+            self.current_path = None;
+        }
+
         let store = KVStore::open(path, None)?;
         
         self.store = Some(store);
@@ -286,30 +436,29 @@ impl Shell {
     fn handle_get_all_command(&mut self) -> Result<String> {
         let store = self.store.as_mut().ok_or(anyhow!("No database open"))?;
 
-        let all_data = store.get_all()?;
-                if all_data.is_empty() {
-            return Ok("No data found".to_string());
-        }
-
-        let mut output = String::new();
-        output.push_str(&format!("Found {} key-value pairs:\n", all_data.len()));
-
-        for (k, v) in all_data {
+        // 边遍历边输出，避免把整个数据库拼成一个字符串
+        let mut count = 0;
+        for entry in store.cursor()? {
+            let (k, v) = entry?;
             let key_str = String::from_utf8_lossy(&k);
-            let value_str = match String::from_utf8(v.clone()) {
+            let value_str = match String::from_utf8(v) {
                 Ok(s) => s,
-                Err(_) => {
-                    let hex_str = v.iter()
+                Err(e) => {
+                    let hex_str = e.as_bytes().iter()
                         .map(|b| format!("{:02X}", b))
                         .collect::<Vec<_>>()
                         .join(" ");
                     format!("<BINARY DATA: {}>", hex_str)
                 }
             };
-            output.push_str(&format!("\"{}\": \"{}\"\n", key_str, value_str));
+            println!("\"{}\": \"{}\"", key_str, value_str);
+            count += 1;
         }
 
-        Ok(output)
+        if count == 0 {
+            return Ok("No data found".to_string());
+        }
+        Ok(format!("Found {} key-value pairs", count))
     }
 
 
@@ -325,6 +474,201 @@ impl Shell {
     }
 
 
+    fn handle_put_ttl_command(&mut self, kvs: Vec<(String, String)>, ttl: u64) -> Result<String> {
+        let store = self.store.as_mut().ok_or(anyhow!("No database open"))?;
+        let length = kvs.len();
+
+        for (key, value) in kvs {
+            store.put_with_ttl(key.as_bytes(), value.as_bytes(), Duration::from_secs(ttl))?;
+        }
+        Ok(format!("Inserted {} key-value pairs, expiring in {} seconds", length, ttl))
+    }
+
+
+    fn handle_put_if_command(&mut self, kvs: Vec<(String, String)>, condition: PutCondition) -> Result<String> {
+        let store = self.store.as_mut().ok_or(anyhow!("No database open"))?;
+        let mut inserted = 0;
+        let mut skipped = Vec::new();
+
+        for (key, value) in kvs {
+            let (k, v) = (key.as_bytes(), value.as_bytes());
+            let applied = match &condition {
+                PutCondition::Absent => store.put_if_absent(k, v)?,
+                PutCondition::Present => store.replace_if_present(k, v)?,
+                PutCondition::Value(expected) => store.compare_and_swap(k, Some(expected.as_bytes()), v)?,
+                PutCondition::Version(version) => store.put_if_version(k, *version, v)?,
+            };
+            if applied {
+                inserted += 1;
+            } else {
+                skipped.push(key);
+            }
+        }
+
+        if skipped.is_empty() {
+            Ok(format!("Inserted {} key-value pairs", inserted))
+        } else {
+            Ok(format!("Inserted {} key-value pairs, condition not met for {:?}", inserted, skipped))
+        }
+    }
+
+
+    fn handle_version_command(&mut self, key: String) -> Result<String> {
+        let store = self.store.as_mut().ok_or(anyhow!("No database open"))?;
+
+        match store.version(key.as_bytes())? {
+            Some(version) => Ok(version.to_string()),
+            None => Ok("Key not found".to_string()),
+        }
+    }
+
+
+    fn handle_counter_command(&mut self, key: String, delta: CounterDelta) -> Result<String> {
+        let store = self.store.as_mut().ok_or(anyhow!("No database open"))?;
+
+        match delta {
+            CounterDelta::Int(delta) => Ok(store.incr_by(key.as_bytes(), delta)?.to_string()),
+            CounterDelta::Float(delta) => Ok(store.incr_by_float(key.as_bytes(), delta)?.to_string()),
+        }
+    }
+
+
+    fn handle_keyspace_command(&mut self, cmd: KeyspaceCommand) -> Result<String> {
+        let store = self.store.as_mut().ok_or(anyhow!("No database open"))?;
+
+        match cmd {
+            KeyspaceCommand::Use(name) => {
+                store.use_keyspace(&name)?;
+                Ok(format!("Using keyspace '{}'", name))
+            }
+            KeyspaceCommand::Show => {
+                let mut lines = Vec::new();
+                for name in store.keyspaces() {
+                    let marker = if name == store.current_keyspace() { "*" } else { " " };
+                    let stats = store.keyspace_stats(&name)?.unwrap_or_default();
+                    lines.push(format!("{} {} ({})", marker, name, stats));
+                }
+                Ok(lines.join("\n"))
+            }
+            KeyspaceCommand::Clear(name) => match store.clear_keyspace(&name)? {
+                true => Ok(format!("Keyspace '{}' cleared", name)),
+                false => Ok("Keyspace not found".to_string()),
+            },
+            KeyspaceCommand::Drop(name) => match store.drop_keyspace(&name)? {
+                true => Ok(format!("Keyspace '{}' dropped", name)),
+                false => Ok("Keyspace not found".to_string()),
+            },
+        }
+    }
+
+
+    fn handle_index_command(&mut self, cmd: IndexCommand) -> Result<String> {
+        let store = self.store.as_mut().ok_or(anyhow!("No database open"))?;
+
+        match cmd {
+            IndexCommand::Create(name, pointer) => match store.create_index(&name, &pointer)? {
+                true => Ok(format!("Index '{}' created on {}", name, pointer)),
+                false => Ok("Index already exists".to_string()),
+            },
+            IndexCommand::Drop(name) => match store.drop_index(&name)? {
+                true => Ok(format!("Index '{}' dropped", name)),
+                false => Ok("Index not found".to_string()),
+            },
+            IndexCommand::Show => {
+                let indexes = store.indexes();
+                if indexes.is_empty() {
+                    return Ok("No indexes found".to_string());
+                }
+                let lines: Vec<String> = indexes
+                    .iter()
+                    .map(|index| format!("{} ON {}", index.name, index.pointer))
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+        }
+    }
+
+
+    fn handle_compression_command(&mut self, cmd: CompressionCommand) -> Result<String> {
+        let store = self.store.as_mut().ok_or(anyhow!("No database open"))?;
+
+        match cmd {
+            CompressionCommand::Set(compression) => {
+                store.set_compression(compression)?;
+                Ok(format!("Compression of keyspace '{}' set to {}", store.current_keyspace(), compression))
+            }
+            CompressionCommand::Show => Ok(format!(
+                "Keyspace '{}' uses compression {}",
+                store.current_keyspace(),
+                store.compression()
+            )),
+        }
+    }
+
+
+    // 用当前键空间中建在该字段上的索引查询
+    fn handle_get_where_command(&mut self, pointer: String, op: CompareOp, value: Value) -> Result<String> {
+        let store = self.store.as_mut().ok_or(anyhow!("No database open"))?;
+
+        let index = store
+            .indexes()
+            .into_iter()
+            .find(|index| index.pointer == pointer)
+            .ok_or_else(|| {
+                anyhow!("No index on {} in keyspace '{}', create one with CREATE INDEX", pointer, store.current_keyspace())
+            })?;
+        let pairs = store.find_by_index_range(&index.name, where_range(op, value))?;
+
+        if pairs.is_empty() {
+            return Ok("No data found".to_string());
+        }
+        for (k, v) in &pairs {
+            println!("\"{}\": \"{}\"", String::from_utf8_lossy(k), String::from_utf8_lossy(v));
+        }
+        Ok(format!("Found {} key-value pairs", pairs.len()))
+    }
+
+
+    fn handle_expire_command(&mut self, key: String, ttl: u64) -> Result<String> {
+        let store = self.store.as_mut().ok_or(anyhow!("No database open"))?;
+
+        if store.expire(key.as_bytes(), Duration::from_secs(ttl))? {
+            Ok(format!("Key expires in {} seconds", ttl))
+        } else {
+            Ok("Key not found".to_string())
+        }
+    }
+
+
+    fn handle_ttl_command(&mut self, key: String) -> Result<String> {
+        let store = self.store.as_mut().ok_or(anyhow!("No database open"))?;
+
+        match store.ttl(key.as_bytes())? {
+            Some(ttl) => Ok(format!("{} seconds", ttl.as_secs())),
+            None if store.get(key.as_bytes())?.is_some() => Ok("Key does not expire".to_string()),
+            None => Ok("Key not found".to_string()),
+        }
+    }
+
+
+    fn handle_persist_command(&mut self, key: String) -> Result<String> {
+        let store = self.store.as_mut().ok_or(anyhow!("No database open"))?;
+
+        if store.persist(key.as_bytes())? {
+            Ok("Expiration removed".to_string())
+        } else {
+            Ok("Key not found or does not expire".to_string())
+        }
+    }
+
+
+    fn handle_purge(&mut self) -> Result<String> {
+        let store = self.store.as_mut().ok_or(anyhow!("No database open"))?;
+        let count = store.purge_expired()?;
+        Ok(format!("Purged {} expired keys", count))
+    }
+
+
     fn handle_identifier_get(&self) -> Result<String> {
         let store = self.store.as_ref().ok_or(anyhow!("No database open"))?;
         Ok(store.get_identifier().to_string())
@@ -343,4 +687,19 @@ impl Shell {
         store.compact()?;
         Ok("Database compacted".to_string())
     }
+
+
+    fn handle_checkpoint(&mut self) -> Result<String> {
+        let store = self.store.as_mut().ok_or(anyhow!("No database open"))?;
+        let size = store.wal_stats().size;
+        store.checkpoint()?;
+        Ok(format!("Checkpoint complete, {} bytes of WAL truncated", size))
+    }
+
+
+    fn handle_verify(&self) -> Result<String> {
+        let store = self.store.as_ref().ok_or(anyhow!("No database open"))?;
+        let report = store.verify()?;
+        Ok(report.to_string())
+    }
 }
@@ -1,5 +1,4 @@
 // src/shell-main
-pub mod kvstore;
 mod utils;
 pub mod shell;
 use anyhow::Result;
@@ -2,16 +2,70 @@ use regex::Regex;
 use anyhow::anyhow;
 use std::env;
 use std::net::{SocketAddr, TcpListener, IpAddr};
+use std::ops::Bound;
 use std::sync::OnceLock;
 use actix_web::HttpRequest;
 use chrono::Local;
 use if_addrs::get_if_addrs;
+use serde_json::Value;
+use wind_kvstore::{Codec, Compression};
 
 
 #[derive(Debug)]
 pub enum ParsedGetCommand {
     All,        // GET WHERE KEY=*; (获取所有键值对)
     Key(String), // GET WHERE KEY="specific_key"; (获取特定键)
+    Where(String, CompareOp, Value), // GET WHERE VALUE.status="open"; (按 JSON 字段查询，字段需建有索引)
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq, // =
+    Lt, // <
+    Le, // <=
+    Gt, // >
+    Ge, // >=
+}
+
+
+#[derive(Debug)]
+pub enum PutCondition {
+    Absent,        // PUT ... IF ABSENT; (键不存在时写入)
+    Present,       // PUT ... IF PRESENT; (键存在时覆盖)
+    Value(String), // PUT ... IF VALUE="old"; (当前值相同时写入)
+    Version(u64),  // PUT ... IF VERSION=3; (版本号相同时写入)
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub enum CounterDelta {
+    Int(i64),   // INCR WHERE KEY="k" BY 5; (整数增量)
+    Float(f64), // INCR WHERE KEY="k" BY 0.5; (带小数点或指数的增量按浮点数计算)
+}
+
+
+#[derive(Debug)]
+pub enum KeyspaceCommand {
+    Use(String),   // USE users; (切换当前键空间，不存在时创建)
+    Show,          // SHOW KEYSPACES; (列出所有键空间)
+    Clear(String), // CLEAR KEYSPACE users; (删除键空间中的所有键)
+    Drop(String),  // DROP KEYSPACE users; (删除键空间)
+}
+
+
+#[derive(Debug)]
+pub enum IndexCommand {
+    Create(String, String), // CREATE INDEX by_status ON VALUE.status; (按 JSON 字段建立索引)
+    Drop(String),           // DROP INDEX by_status; (删除索引)
+    Show,                   // SHOW INDEXES; (列出当前键空间的索引)
+}
+
+
+#[derive(Debug)]
+pub enum CompressionCommand {
+    Set(Compression), // SET COMPRESSION zstd THRESHOLD 512; (设置当前键空间的值压缩)
+    Show,             // SHOW COMPRESSION; (显示当前键空间生效的压缩设置)
 }
 
 
@@ -25,7 +79,7 @@ pub fn output_tile(is_server: Option<bool>) {
             "Welcome to Wind-KVStore!\n"                               +
             "\n"                                                       +
             "\t       "                                                +
-            &*"██╗    ██╗    ██╗    ███╗   ██╗    ██████╗ \n"            +
+            "██╗    ██╗    ██╗    ███╗   ██╗    ██████╗ \n"              +
             "\t       "                                                +
             "██║    ██║    ██║    ████╗  ██║    ██╔══██╗\n"            +
             "\t       "                                                +
@@ -47,21 +101,22 @@ pub fn output_tile(is_server: Option<bool>) {
 
 
 pub fn parse_put_command(command: &str) -> anyhow::Result<Vec<(String, String)>> {
-    let re = Regex::new(r#"(?i)PUT\s+"([^"]+)"\s*:\s*"([^"]+)"(?:\s*,\s*"([^"]+)"\s*:\s*"([^"]+)")*\s*$"#)?;
+    // 值中的双引号写作 \"，如 PUT "t1":"{\"status\": \"open\"}"
+    let re = Regex::new(r#"(?i)PUT\s+"([^"]+)"\s*:\s*"((?:[^"\\]|\\.)+)"(?:\s*,\s*"([^"]+)"\s*:\s*"((?:[^"\\]|\\.)+)")*\s*$"#)?;
 
     if let Some(caps) = re.captures(command) {
         let mut kvs = Vec::new();
 
         // 第一个键值对
         if let (Some(k), Some(v)) = (caps.get(1), caps.get(2)) {
-            kvs.push((k.as_str().to_string(), v.as_str().to_string()));
+            kvs.push((k.as_str().to_string(), v.as_str().replace("\\\"", "\"")));
         }
 
         // 后续键值对
         let mut i = 3;
         while i < caps.len() {
             if let (Some(k), Some(v)) = (caps.get(i), caps.get(i + 1)) {
-                kvs.push((k.as_str().to_string(), v.as_str().to_string()));
+                kvs.push((k.as_str().to_string(), v.as_str().replace("\\\"", "\"")));
                 i += 2;
             } else {
                 break;
@@ -79,6 +134,44 @@ pub fn parse_put_command(command: &str) -> anyhow::Result<Vec<(String, String)>>
 }
 
 
+// PUT "KEY":"VALUE" TTL <seconds>，返回键值对和存活秒数
+pub fn parse_put_ttl_command(command: &str) -> anyhow::Result<(Vec<(String, String)>, u64)> {
+    let re = Regex::new(r#"(?i)^(PUT\s+.*")\s+TTL\s+(\d+)\s*$"#)?;
+
+    if let Some(caps) = re.captures(command.trim()) {
+        let kvs = parse_put_command(&caps[1])?;
+        let ttl = caps[2].parse::<u64>()?;
+        return Ok((kvs, ttl));
+    }
+
+    Err(anyhow!("Invalid PUT ... TTL command format"))
+}
+
+
+// PUT "KEY":"VALUE" IF ABSENT|PRESENT|VALUE="old"|VERSION=<n>，返回键值对和写入条件
+pub fn parse_put_if_command(command: &str) -> anyhow::Result<(Vec<(String, String)>, PutCondition)> {
+    let re = Regex::new(
+        r#"(?i)^(PUT\s+.*")\s+IF\s+(?:(ABSENT)|(PRESENT)|VALUE\s*=\s*"([^"]+)"|VERSION\s*=\s*(\d+))\s*$"#,
+    )?;
+
+    if let Some(caps) = re.captures(command.trim()) {
+        let kvs = parse_put_command(&caps[1])?;
+        let condition = if caps.get(2).is_some() {
+            PutCondition::Absent
+        } else if caps.get(3).is_some() {
+            PutCondition::Present
+        } else if let Some(expected) = caps.get(4) {
+            PutCondition::Value(expected.as_str().to_string())
+        } else {
+            PutCondition::Version(caps[5].parse::<u64>()?)
+        };
+        return Ok((kvs, condition));
+    }
+
+    Err(anyhow!("Invalid PUT ... IF command format"))
+}
+
+
 pub fn parse_get_command(command: &str) -> anyhow::Result<ParsedGetCommand> {
     let command = command.trim();
 
@@ -90,26 +183,216 @@ pub fn parse_get_command(command: &str) -> anyhow::Result<ParsedGetCommand> {
 
     // 匹配 GET WHERE KEY="*"; （有引号的星号）
     let quoted_pattern = Regex::new(r#"(?i)^GET\s+WHERE\s+KEY\s*=\s*"([^"]+)"\s*$"#)?;
-    if let Some(caps) = quoted_pattern.captures(command) {
-        if let Some(key_match) = caps.get(1) {
-            return Ok(ParsedGetCommand::Key(key_match.as_str().to_string()));
-        }
+    if let Some(key_match) = quoted_pattern.captures(command).and_then(|caps| caps.get(1)) {
+        return Ok(ParsedGetCommand::Key(key_match.as_str().to_string()));
+    }
+
+    // 匹配 GET WHERE VALUE.a.b <op> <JSON 字面量>;
+    let value_pattern = Regex::new(r#"(?i)^GET\s+WHERE\s+VALUE((?:\.[\w-]+)+)\s*(<=|>=|=|<|>)\s*(.+?)\s*$"#)?;
+    if let Some(caps) = value_pattern.captures(command) {
+        let op = match &caps[2] {
+            "=" => CompareOp::Eq,
+            "<" => CompareOp::Lt,
+            "<=" => CompareOp::Le,
+            ">" => CompareOp::Gt,
+            _ => CompareOp::Ge,
+        };
+        let value = serde_json::from_str(&caps[3]).map_err(|_| {
+            anyhow!("Invalid value {}, expected a quoted string, a number, true, false or null", &caps[3])
+        })?;
+        return Ok(ParsedGetCommand::Where(json_pointer(&caps[1]), op, value));
     }
 
     Err(anyhow!("Invalid GET command: {}", command))
 }
 
 
+// 比较条件对应的索引区间，只包含与 value 同类型的字段值
+pub fn where_range(op: CompareOp, value: Value) -> (Bound<Value>, Bound<Value>) {
+    // 索引中的顺序为 null < false < true < 数字 < 字符串
+    let (low, high) = match &value {
+        Value::Number(_) => (Bound::Excluded(Value::Bool(true)), Bound::Excluded(Value::from(""))),
+        Value::String(_) => (Bound::Included(Value::from("")), Bound::Unbounded),
+        Value::Bool(_) => (Bound::Included(Value::Bool(false)), Bound::Included(Value::Bool(true))),
+        _ => (Bound::Included(Value::Null), Bound::Included(Value::Null)),
+    };
+    match op {
+        CompareOp::Eq => (Bound::Included(value.clone()), Bound::Included(value)),
+        CompareOp::Lt => (low, Bound::Excluded(value)),
+        CompareOp::Le => (low, Bound::Included(value)),
+        CompareOp::Gt => (Bound::Excluded(value), high),
+        CompareOp::Ge => (Bound::Included(value), high),
+    }
+}
+
+
+// VALUE 之后的字段路径 .a.b 转为 JSON 指针 /a/b
+fn json_pointer(path: &str) -> String {
+    path.split('.')
+        .skip(1)
+        .map(|field| format!("/{}", field.replace('~', "~0")))
+        .collect()
+}
+
+
 pub fn parse_delete_command(command: &str) -> anyhow::Result<String> {
     let re = Regex::new(r#"(?i)DEL\s+WHERE\s+KEY\s*=\s*"([^"]+)"\s*$"#)?;
 
+    if let Some(key) = re.captures(command).and_then(|caps| caps.get(1)) {
+        return Ok(key.as_str().to_string());
+    }
+
+    Err(anyhow!("Invalid DELETE command format"))
+}
+
+
+// EXPIRE WHERE KEY="KEY" TTL <seconds>
+pub fn parse_expire_command(command: &str) -> anyhow::Result<(String, u64)> {
+    let re = Regex::new(r#"(?i)^EXPIRE\s+WHERE\s+KEY\s*=\s*"([^"]+)"\s+TTL\s+(\d+)\s*$"#)?;
+
+    if let Some(caps) = re.captures(command.trim()) {
+        let ttl = caps[2].parse::<u64>()?;
+        return Ok((caps[1].to_string(), ttl));
+    }
+
+    Err(anyhow!("Invalid EXPIRE command format"))
+}
+
+
+pub fn parse_ttl_command(command: &str) -> anyhow::Result<String> {
+    let re = Regex::new(r#"(?i)^TTL\s+WHERE\s+KEY\s*=\s*"([^"]+)"\s*$"#)?;
+
+    if let Some(key) = re.captures(command.trim()).and_then(|caps| caps.get(1)) {
+        return Ok(key.as_str().to_string());
+    }
+
+    Err(anyhow!("Invalid TTL command format"))
+}
+
+
+pub fn parse_persist_command(command: &str) -> anyhow::Result<String> {
+    let re = Regex::new(r#"(?i)^PERSIST\s+WHERE\s+KEY\s*=\s*"([^"]+)"\s*$"#)?;
+
+    if let Some(key) = re.captures(command.trim()).and_then(|caps| caps.get(1)) {
+        return Ok(key.as_str().to_string());
+    }
+
+    Err(anyhow!("Invalid PERSIST command format"))
+}
+
+
+// INCR WHERE KEY="KEY" [BY <n>] / DECR WHERE KEY="KEY" [BY <n>]
+// 省略 BY 时增量为 1，DECR 的增量在这里取反
+pub fn parse_counter_command(command: &str) -> anyhow::Result<(String, CounterDelta)> {
+    let re = Regex::new(r#"(?i)^(INCR|DECR)\s+WHERE\s+KEY\s*=\s*"([^"]+)"(?:\s+BY\s+(\S+))?\s*$"#)?;
+
+    if let Some(caps) = re.captures(command.trim()) {
+        let decrement = caps[1].eq_ignore_ascii_case("DECR");
+        let delta = match caps.get(3).map(|by| by.as_str()) {
+            None => CounterDelta::Int(1),
+            Some(by) if by.contains(['.', 'e', 'E']) => {
+                let delta = by.parse::<f64>()?;
+                if !delta.is_finite() {
+                    return Err(anyhow!("Invalid counter delta: {}", by));
+                }
+                CounterDelta::Float(delta)
+            }
+            Some(by) => CounterDelta::Int(by.parse::<i64>()?),
+        };
+        let delta = match delta {
+            CounterDelta::Int(n) if decrement => CounterDelta::Int(
+                n.checked_neg().ok_or_else(|| anyhow!("Counter delta out of range"))?,
+            ),
+            CounterDelta::Float(n) if decrement => CounterDelta::Float(-n),
+            delta => delta,
+        };
+        return Ok((caps[2].to_string(), delta));
+    }
+
+    Err(anyhow!("Invalid INCR/DECR command format"))
+}
+
+
+// USE <name> / SHOW KEYSPACES / CLEAR KEYSPACE <name> / DROP KEYSPACE <name>
+// 名称可以加双引号，含空格时必须加
+pub fn parse_keyspace_command(command: &str) -> anyhow::Result<KeyspaceCommand> {
+    let command = command.trim();
+    if Regex::new(r"(?i)^SHOW\s+KEYSPACES$")?.is_match(command) {
+        return Ok(KeyspaceCommand::Show);
+    }
+
+    let re = Regex::new(r#"(?i)^(USE|CLEAR\s+KEYSPACE|DROP\s+KEYSPACE)\s+(?:"([^"]+)"|(\S+))$"#)?;
+    if let Some(caps) = re.captures(command) {
+        let name = caps.get(2).or(caps.get(3)).map_or("", |name| name.as_str()).to_string();
+        let verb = caps[1].to_ascii_uppercase();
+        return Ok(if verb == "USE" {
+            KeyspaceCommand::Use(name)
+        } else if verb.starts_with("CLEAR") {
+            KeyspaceCommand::Clear(name)
+        } else {
+            KeyspaceCommand::Drop(name)
+        });
+    }
+
+    Err(anyhow!("Invalid keyspace command format"))
+}
+
+
+// CREATE INDEX <name> ON VALUE.a.b / CREATE INDEX <name> ON "/a/b" / DROP INDEX <name> / SHOW INDEXES
+pub fn parse_index_command(command: &str) -> anyhow::Result<IndexCommand> {
+    let command = command.trim();
+    if Regex::new(r"(?i)^SHOW\s+INDEXES$")?.is_match(command) {
+        return Ok(IndexCommand::Show);
+    }
+
+    let create = Regex::new(r#"(?i)^CREATE\s+INDEX\s+(\S+)\s+ON\s+(?:VALUE((?:\.[\w-]+)+)|"([^"]+)")$"#)?;
+    if let Some(caps) = create.captures(command) {
+        let pointer = match caps.get(2) {
+            Some(path) => json_pointer(path.as_str()),
+            None => caps[3].to_string(),
+        };
+        return Ok(IndexCommand::Create(caps[1].to_string(), pointer));
+    }
+
+    if let Some(caps) = Regex::new(r"(?i)^DROP\s+INDEX\s+(\S+)$")?.captures(command) {
+        return Ok(IndexCommand::Drop(caps[1].to_string()));
+    }
+
+    Err(anyhow!("Invalid index command format"))
+}
+
+
+// SET COMPRESSION none|lz4|zstd [THRESHOLD <bytes>] / SHOW COMPRESSION
+pub fn parse_compression_command(command: &str) -> anyhow::Result<CompressionCommand> {
+    let command = command.trim();
+    if Regex::new(r"(?i)^SHOW\s+COMPRESSION$")?.is_match(command) {
+        return Ok(CompressionCommand::Show);
+    }
+
+    let re = Regex::new(r"(?i)^SET\s+COMPRESSION\s+(\w+)(?:\s+THRESHOLD\s+(\d+))?$")?;
     if let Some(caps) = re.captures(command) {
-        if let Some(key) = caps.get(1) {
-            return Ok(key.as_str().to_string());
+        let mut compression = Compression {
+            codec: caps[1].parse::<Codec>()?,
+            ..Compression::NONE
+        };
+        if let Some(threshold) = caps.get(2) {
+            compression = compression.threshold(threshold.as_str().parse()?);
         }
+        return Ok(CompressionCommand::Set(compression));
     }
 
-    Err(anyhow!("Invalid DELETE command format"))
+    Err(anyhow!("Invalid compression command format"))
+}
+
+
+pub fn parse_version_command(command: &str) -> anyhow::Result<String> {
+    let re = Regex::new(r#"(?i)^VERSION\s+WHERE\s+KEY\s*=\s*"([^"]+)"\s*$"#)?;
+
+    if let Some(key) = re.captures(command.trim()).and_then(|caps| caps.get(1)) {
+        return Ok(key.as_str().to_string());
+    }
+
+    Err(anyhow!("Invalid VERSION command format"))
 }
 
 
@@ -125,10 +408,8 @@ pub fn parse_identifier_get(command: &str) -> anyhow::Result<()> {
 pub fn parse_identifier_set(command: &str) -> anyhow::Result<String> {
     let re = Regex::new(r#"(?i)IDENTIFIER\s+SET\s+"([^"]+)"\s*$"#)?;
 
-    if let Some(caps) = re.captures(command) {
-        if let Some(id) = caps.get(1) {
-            return Ok(id.as_str().to_string());
-        }
+    if let Some(id) = re.captures(command).and_then(|caps| caps.get(1)) {
+        return Ok(id.as_str().to_string());
     }
 
     Err(anyhow!("Invalid IDENTIFIER SET command"))
@@ -144,6 +425,33 @@ pub fn parse_compact(command: &str) -> anyhow::Result<()> {
 }
 
 
+pub fn parse_checkpoint(command: &str) -> anyhow::Result<()> {
+    if command.trim().eq_ignore_ascii_case("CHECKPOINT") {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid CHECKPOINT command"))
+    }
+}
+
+
+pub fn parse_verify(command: &str) -> anyhow::Result<()> {
+    if command.trim().eq_ignore_ascii_case("VERIFY") {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid VERIFY command"))
+    }
+}
+
+
+pub fn parse_purge(command: &str) -> anyhow::Result<()> {
+    if command.trim().eq_ignore_ascii_case("PURGE") {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid PURGE command"))
+    }
+}
+
+
 fn get_formatted_time() -> String {
     Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
 }
@@ -179,12 +487,12 @@ fn get_header_value<'a>(
 #[allow(unused)]
 pub fn get_client_ip(req: &HttpRequest) -> String {
     // 优先检查 CF-Connecting-IP (Cloudflare 提供的真实 IP 头)
-    if let Some(ip) = get_header_value(&req, "CF-Connecting-IP") {
+    if let Some(ip) = get_header_value(req, "CF-Connecting-IP") {
         return ip.to_string();
     }
 
     // 其次检查 X-Forwarded-For 头
-    if let Some(ip) = get_header_value(&req, "X-Forwarded-For") {
+    if let Some(ip) = get_header_value(req, "X-Forwarded-For") {
         // X-Forwarded-For 可能包含多个 IP，取第一个
         let first_ip = ip.split(',').next().unwrap_or(ip).trim();
         return first_ip.to_string();
@@ -255,8 +563,6 @@ pub fn is_local_port_available(host: String, port: u16) -> bool {
         Ok(ip) => ip,
         Err(_) => return false,
     };
-    match TcpListener::bind(SocketAddr::new(ip, port)) {
-        Ok(_) => true,  // 绑定成功说明端口可用
-        Err(_) => false // 绑定失败说明端口被占用或无权访问
-    }
+    // 绑定成功说明端口可用，失败说明端口被占用或无权访问
+    TcpListener::bind(SocketAddr::new(ip, port)).is_ok()
 }